@@ -68,6 +68,11 @@ docker build \
 
 echo >&2 "Running backdrop on http://localhost:8000"
 
+# Download links are signed with a random secret unless one is given.
+DOWNLOAD_SECRET="${DOWNLOAD_SECRET:=$(head -c 32 /dev/urandom | od -An -tx1 | tr -d ' \n')}"
+# Webhooks are signed with `WEBHOOK_SECRET`. Receivers need it to verify them.
+WEBHOOK_SECRET="${WEBHOOK_SECRET:=$(head -c 32 /dev/urandom | od -An -tx1 | tr -d ' \n')}"
+
 # Run app with optionally pretty printed logs.
 if ! [ -x "$(command -v bunyan)" ]; then
   echo >&2 "Warning: bunyan formatter is not installed"
@@ -77,6 +82,8 @@ if ! [ -x "$(command -v bunyan)" ]; then
     -p 8000:8000 \
     --network $NET_NAME \
     --env APP_REDIS_URI="redis://${REDIS_NAME}" \
+    --env APP_APPLICATION__DOWNLOAD_SECRET="${DOWNLOAD_SECRET}" \
+    --env APP_WEBHOOKS__SECRET="${WEBHOOK_SECRET}" \
     $CONTAINER_TAG
 else
   # Run with pretty printing
@@ -84,6 +91,8 @@ else
     -p 8000:8000 \
     --network $NET_NAME \
     --env APP_REDIS_URI="redis://${REDIS_NAME}" \
+    --env APP_APPLICATION__DOWNLOAD_SECRET="${DOWNLOAD_SECRET}" \
+    --env APP_WEBHOOKS__SECRET="${WEBHOOK_SECRET}" \
     $CONTAINER_TAG \
     | bunyan
 fi
@@ -0,0 +1,412 @@
+use redis::AsyncCommands;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::priority::Priority;
+use crate::task_state::{ACTIVE_TTL, TASK_KEY_PREFIX};
+use crate::utils::{civil_date, unix_now};
+
+pub mod command;
+
+// The JSON API is used with API keys, which are managed by the `keys` command.
+// Every key has a tier, the highest priority its jobs are rendered with, and
+// quotas: how many jobs it may create per day, how large their uploads may be,
+// how many of its jobs may be pending at once and how long their videos may
+// be kept. Keys are only stored as their SHA-256 hash. Since they are long
+// random strings, a salted or slow hash wouldn't make them any harder to guess.
+//
+// Each job created with a key counts as a render on the day (in UTC) it's
+// created. The jobs which are still pending are tracked in a sorted set per
+// key, scored by when they were created. Finished jobs are removed from it
+// whenever the set is counted. All jobs of a key are also indexed in another
+// sorted set, scored by when they were created (in microseconds), so they
+// can be listed. Jobs older than their records are removed from the index.
+
+// Redis set of the IDs of all keys.
+const KEYS_KEY: &str = "api-keys";
+// Prefix of the redis hashes holding a key's name, tier and quotas.
+const KEY_PREFIX: &str = "api-key";
+// Prefix of the redis keys mapping the hash of a key to its ID.
+const HASH_PREFIX: &str = "api-key-hash";
+// Prefix of the redis sorted sets of the pending jobs of a key.
+const JOBS_PREFIX: &str = "api-key-jobs";
+// Prefix of the redis sorted sets of all jobs of a key.
+const INDEX_PREFIX: &str = "api-key-index";
+// Prefix of the redis hashes counting the usage of a key.
+const USAGE_PREFIX: &str = "api-key-usage";
+// Amount of time (in seconds) the usage of a single day is kept for.
+const DAILY_USAGE_TTL: usize = 32 * 24 * 60 * 60;
+// Amount of time (in seconds) a job is counted as pending before its task
+// record exists, which is while its assets are received.
+const UPLOAD_GRACE_PERIOD: u64 = 60 * 60;
+// Prefix of every key, so they can be recognized (e.g. by secret scanners).
+const TOKEN_PREFIX: &str = "bd_";
+
+fn key_key(id: Uuid) -> String {
+    format!("{KEY_PREFIX}:{id}")
+}
+
+fn hash_key(hash: &str) -> String {
+    format!("{HASH_PREFIX}:{hash}")
+}
+
+fn jobs_key(id: Uuid) -> String {
+    format!("{JOBS_PREFIX}:{id}")
+}
+
+fn index_key(id: Uuid) -> String {
+    format!("{INDEX_PREFIX}:{id}")
+}
+
+// Redis key of the usage of a key in total (`day == None`) or on a day.
+fn usage_key(id: Uuid, day: Option<u64>) -> String {
+    match day {
+        Some(day) => format!("{USAGE_PREFIX}:{id}:{day}"),
+        None => format!("{USAGE_PREFIX}:{id}"),
+    }
+}
+
+// Current unix time in microseconds.
+fn unix_now_micros() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+// Number of the current day (in UTC) since 1970-01-01.
+fn today() -> u64 {
+    unix_now() / 86400
+}
+
+// Limits of the jobs created with a key. `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Quotas {
+    // Jobs which may be created per day.
+    pub renders_per_day: Option<u64>,
+    // Total size (in bytes) of the assets of a job.
+    pub max_upload_size: Option<u64>,
+    // Jobs which may be queued or rendering at once.
+    pub max_concurrent_jobs: Option<u64>,
+    // Longest time (in seconds) a finished video may be kept.
+    pub max_lifetime: Option<usize>,
+}
+
+// An API key, without the key itself.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    // Who the key was handed out to.
+    pub name: String,
+    // Highest priority the jobs of the key are rendered with. It's
+    // also the priority of the jobs which don't choose one.
+    pub tier: Priority,
+    pub quotas: Quotas,
+    // URL which is sent the webhooks of jobs which don't name one.
+    pub callback_url: Option<String>,
+    pub created_at: u64,
+}
+
+impl ApiKey {
+    pub fn new(name: String, tier: Priority, quotas: Quotas, callback_url: Option<String>) -> Self {
+        Self { id: Uuid::new_v4(), name, tier, quotas, callback_url, created_at: unix_now() }
+    }
+
+    fn fields(&self, hash: &str) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("name", self.name.clone()),
+            ("tier", self.tier.as_str().to_owned()),
+            ("created_at", self.created_at.to_string()),
+            ("hash", hash.to_owned()),
+        ];
+        let optional = [
+            ("renders_per_day", self.quotas.renders_per_day.map(|n| n.to_string())),
+            ("max_upload_size", self.quotas.max_upload_size.map(|n| n.to_string())),
+            ("max_concurrent_jobs", self.quotas.max_concurrent_jobs.map(|n| n.to_string())),
+            ("max_lifetime", self.quotas.max_lifetime.map(|n| n.to_string())),
+            ("callback_url", self.callback_url.clone()),
+        ];
+        fields.extend(optional.into_iter().filter_map(|(name, value)| Some((name, value?))));
+        fields
+    }
+
+    fn from_fields(id: Uuid, mut fields: HashMap<String, String>) -> Option<Self> {
+        let name = fields.remove("name")?;
+        let number = |name: &str| fields.get(name).and_then(|v| v.parse::<u64>().ok());
+        Some(Self {
+            id,
+            name,
+            tier: fields.get("tier")
+                .and_then(|t| Priority::try_from(t.clone()).ok())
+                .unwrap_or_default(),
+            quotas: Quotas {
+                renders_per_day: number("renders_per_day"),
+                max_upload_size: number("max_upload_size"),
+                max_concurrent_jobs: number("max_concurrent_jobs"),
+                max_lifetime: number("max_lifetime").map(|n| n as usize),
+            },
+            created_at: number("created_at").unwrap_or(0),
+            callback_url: fields.remove("callback_url"),
+        })
+    }
+}
+
+// Create a new random key.
+pub fn generate_token() -> String {
+    // Version 4 UUIDs are made of 122 random bits each.
+    format!("{TOKEN_PREFIX}{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// Hash of a key as it's stored.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Store `key` and return the key to hand out, which isn't stored itself.
+pub async fn create(conn: &mut RedisConn, key: &ApiKey) -> redis::RedisResult<String> {
+    let token = generate_token();
+    let hash = hash_token(&token);
+    let _: () = redis::pipe()
+        .atomic()
+        .hset_multiple(key_key(key.id), &key.fields(&hash)).ignore()
+        .set(hash_key(&hash), key.id.to_string()).ignore()
+        .sadd(KEYS_KEY, key.id.to_string()).ignore()
+        .query_async(conn.deref_mut()).await?;
+    Ok(token)
+}
+
+// Load the key with the ID `id`, if there is one.
+pub async fn load(conn: &mut RedisConn, id: Uuid) -> redis::RedisResult<Option<ApiKey>> {
+    let fields: HashMap<String, String> = conn.hgetall(key_key(id)).await?;
+    Ok(ApiKey::from_fields(id, fields))
+}
+
+// Load all keys, oldest first.
+pub async fn list(conn: &mut RedisConn) -> redis::RedisResult<Vec<ApiKey>> {
+    let ids: Vec<String> = conn.smembers(KEYS_KEY).await?;
+    let mut keys = Vec::with_capacity(ids.len());
+    for id in ids.iter().filter_map(|id| Uuid::parse_str(id).ok()) {
+        if let Some(key) = load(conn, id).await? {
+            keys.push(key);
+        }
+    }
+    keys.sort_by_key(|k| k.created_at);
+    Ok(keys)
+}
+
+// Delete the key with the ID `id` and its usage. Jobs created with it are
+// kept. Returns `false` if there is no such key.
+pub async fn revoke(conn: &mut RedisConn, id: Uuid) -> redis::RedisResult<bool> {
+    let hash: Option<String> = conn.hget(key_key(id), "hash").await?;
+    let Some(hash) = hash else {
+        return Ok(false);
+    };
+    let _: () = redis::pipe()
+        .atomic()
+        .del(&[key_key(id), hash_key(&hash), jobs_key(id), index_key(id), usage_key(id, None)]).ignore()
+        .srem(KEYS_KEY, id.to_string()).ignore()
+        .query_async(conn.deref_mut()).await?;
+    Ok(true)
+}
+
+// Look up the key `token`. Returns `None` if it's unknown or was revoked.
+pub async fn authenticate(conn: &mut RedisConn, token: &str) -> redis::RedisResult<Option<ApiKey>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    let id: Option<String> = conn.get(hash_key(&hash_token(token))).await?;
+    match id.and_then(|id| Uuid::parse_str(&id).ok()) {
+        Some(id) => load(conn, id).await,
+        None => Ok(None),
+    }
+}
+
+// Outcome of reserving a job for a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reservation {
+    // The job was counted on the day `day` (see `today`).
+    Reserved { day: u64 },
+    // The key created as many jobs today as it may.
+    DailyLimitReached,
+    // As many jobs of the key as it may have are pending.
+    ConcurrencyLimitReached,
+}
+
+// Count the job `target` against the quotas of `key`, unless it would
+// exceed them. Reserved jobs which aren't created are `release`d.
+pub async fn reserve(conn: &mut RedisConn, key: &ApiKey, target: Uuid) -> redis::RedisResult<Reservation> {
+    // Jobs without a task record are still being received,
+    // unless they were reserved too long ago. The records of the
+    // pending jobs are passed as keys, following the fixed ones.
+    // If the pending jobs changed since they were read, 3 is
+    // returned to read them and try again.
+    let script = redis::Script::new(r"
+        local task_keys = {}
+        for i = 5, #KEYS do
+            task_keys[ARGV[i + 4]] = KEYS[i]
+        end
+        local now = tonumber(ARGV[2])
+        local pending = 0
+        local jobs = redis.call('ZRANGE', KEYS[1], 0, -1, 'WITHSCORES')
+        for i = 1, #jobs, 2 do
+            local task_key = task_keys[jobs[i]]
+            if not task_key then
+                return 3
+            end
+            local status = redis.call('HGET', task_key, 'status')
+            if status == 'queued' or status == 'rendering'
+                or (not status and tonumber(jobs[i + 1]) > now - tonumber(ARGV[3])) then
+                pending = pending + 1
+            else
+                redis.call('ZREM', KEYS[1], jobs[i])
+            end
+        end
+        local max_pending = tonumber(ARGV[4])
+        if max_pending >= 0 and pending >= max_pending then
+            return 2
+        end
+        local renders = tonumber(redis.call('HGET', KEYS[2], 'renders') or '0')
+        local max_renders = tonumber(ARGV[5])
+        if max_renders >= 0 and renders >= max_renders then
+            return 1
+        end
+
+        redis.call('ZADD', KEYS[1], now, ARGV[1])
+        redis.call('EXPIRE', KEYS[1], ARGV[6])
+        redis.call('HINCRBY', KEYS[2], 'renders', 1)
+        redis.call('EXPIRE', KEYS[2], ARGV[7])
+        redis.call('HINCRBY', KEYS[3], 'renders', 1)
+        local created = tonumber(ARGV[8])
+        redis.call('ZADD', KEYS[4], created, ARGV[1])
+        redis.call('ZREMRANGEBYSCORE', KEYS[4], '-inf', created - tonumber(ARGV[6]) * 1000000)
+        redis.call('EXPIRE', KEYS[4], ARGV[6])
+        return 0
+    ");
+    let limit = |quota: Option<u64>| quota.map_or(-1, |n| n.min(i64::MAX as u64) as i64);
+    let day = today();
+    let outcome = loop {
+        let pending: Vec<String> = conn.zrange(jobs_key(key.id), 0, -1).await?;
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(jobs_key(key.id))
+            .key(usage_key(key.id, Some(day)))
+            .key(usage_key(key.id, None))
+            .key(index_key(key.id))
+            .arg(target.to_string())
+            .arg(unix_now())
+            .arg(UPLOAD_GRACE_PERIOD)
+            .arg(limit(key.quotas.max_concurrent_jobs))
+            .arg(limit(key.quotas.renders_per_day))
+            .arg(ACTIVE_TTL)
+            .arg(DAILY_USAGE_TTL)
+            .arg(unix_now_micros());
+        for job in &pending {
+            invocation.key(format!("{TASK_KEY_PREFIX}:{job}")).arg(job);
+        }
+        let outcome: u8 = invocation.invoke_async(conn.deref_mut()).await?;
+        if outcome != 3 {
+            break outcome;
+        }
+    };
+    Ok(match outcome {
+        0 => Reservation::Reserved { day },
+        1 => Reservation::DailyLimitReached,
+        _ => Reservation::ConcurrencyLimitReached,
+    })
+}
+
+// Undo the reservation of the job `target` made on the day `day`,
+// which wasn't created after all.
+pub async fn release(conn: &mut RedisConn, key_id: Uuid, target: Uuid, day: u64) -> redis::RedisResult<()> {
+    let daily = usage_key(key_id, Some(day));
+    redis::pipe()
+        .zrem(jobs_key(key_id), target.to_string()).ignore()
+        .zrem(index_key(key_id), target.to_string()).ignore()
+        .hincr(&daily, "renders", -1).ignore()
+        .expire(&daily, DAILY_USAGE_TTL).ignore()
+        .hincr(usage_key(key_id, None), "renders", -1).ignore()
+        .query_async(conn.deref_mut()).await
+}
+
+// Up to `count` jobs of the key `key_id`, newest first, along with when they
+// were created (in microseconds). Only jobs created before `before` are
+// listed, so the creation time of the last job continues the listing.
+pub async fn jobs(
+    conn: &mut RedisConn,
+    key_id: Uuid,
+    before: Option<u64>,
+    count: usize,
+) -> redis::RedisResult<Vec<(Uuid, u64)>> {
+    let max = before.map_or("+inf".to_owned(), |before| format!("({before}"));
+    let jobs: Vec<(String, u64)> = conn.zrevrangebyscore_limit_withscores(
+        index_key(key_id), max, "-inf", 0, count as isize
+    ).await?;
+    Ok(jobs.into_iter()
+        .filter_map(|(id, created)| Some((Uuid::parse_str(&id).ok()?, created)))
+        .collect())
+}
+
+// Count `bytes` of uploaded assets towards the usage of the key `key_id`.
+pub async fn record_upload(conn: &mut RedisConn, key_id: Uuid, bytes: u64) -> redis::RedisResult<()> {
+    let daily = usage_key(key_id, Some(today()));
+    redis::pipe()
+        .hincr(&daily, "upload_bytes", bytes).ignore()
+        .expire(&daily, DAILY_USAGE_TTL).ignore()
+        .hincr(usage_key(key_id, None), "upload_bytes", bytes).ignore()
+        .query_async(conn.deref_mut()).await
+}
+
+// Usage of a key in total and on a number of recent days.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub renders: u64,
+    pub upload_bytes: u64,
+    // Jobs which are queued, rendering or still being uploaded.
+    pub pending_jobs: u64,
+    // Most recent day first.
+    pub days: Vec<DailyUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyUsage {
+    // Day in UTC as `YYYY-MM-DD`.
+    pub date: String,
+    pub renders: u64,
+    pub upload_bytes: u64,
+}
+
+// Load the usage of the key `key_id` in total and on the last `days` days.
+pub async fn usage(conn: &mut RedisConn, key_id: Uuid, days: u64) -> redis::RedisResult<Usage> {
+    let counters = |fields: HashMap<String, u64>| (
+        fields.get("renders").copied().unwrap_or(0),
+        fields.get("upload_bytes").copied().unwrap_or(0),
+    );
+    let (renders, upload_bytes) = counters(conn.hgetall(usage_key(key_id, None)).await?);
+
+    let today = today();
+    let mut daily = Vec::new();
+    for day in (0..days.min(today + 1)).map(|ago| today - ago) {
+        let (renders, upload_bytes) = counters(conn.hgetall(usage_key(key_id, Some(day))).await?);
+        let (year, month, day) = civil_date(day);
+        daily.push(DailyUsage { date: format!("{year:04}-{month:02}-{day:02}"), renders, upload_bytes });
+    }
+
+    let now = unix_now();
+    let jobs: Vec<(String, u64)> = conn.zrange_withscores(jobs_key(key_id), 0, -1).await?;
+    let mut pending_jobs = 0;
+    for (target, reserved_at) in jobs {
+        let status: Option<String> = conn.hget(format!("{TASK_KEY_PREFIX}:{target}"), "status").await?;
+        let pending = match status.as_deref() {
+            Some("queued" | "rendering") => true,
+            Some(_) => false,
+            None => reserved_at + UPLOAD_GRACE_PERIOD > now,
+        };
+        pending_jobs += u64::from(pending);
+    }
+
+    Ok(Usage { renders, upload_bytes, pending_jobs, days: daily })
+}
@@ -0,0 +1,132 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::priority::Priority;
+use crate::webhooks::parse_callback_url;
+use super::{ApiKey, Quotas};
+
+// Number of days whose usage is shown if `--days` isn't given.
+const DEFAULT_USAGE_DAYS: u64 = 7;
+
+pub const USAGE: &str = "\
+usage: backdrop keys create <name> [--tier <high|normal|low>] [--renders-per-day <n>]
+                            [--max-upload-size <bytes>] [--max-concurrent-jobs <n>]
+                            [--max-lifetime <minutes>] [--callback-url <url>]
+       backdrop keys list
+       backdrop keys revoke <key id>
+       backdrop keys usage <key id> [--days <n>]";
+
+// Admin command to manage API keys, run as `backdrop keys <command>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyCommand {
+    // Create a key and print it. The key itself is only shown once.
+    Create(ApiKey),
+    List,
+    Revoke(Uuid),
+    // Print the usage of a key on the last `days` days.
+    Usage { id: Uuid, days: u64 },
+}
+
+#[derive(Serialize)]
+struct CreatedKey<'a> {
+    #[serde(flatten)]
+    api_key: &'a ApiKey,
+    // The key to hand out.
+    key: String,
+}
+
+impl KeyCommand {
+    // Parse the arguments following `backdrop keys`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = args.next().ok_or("missing command")?;
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                positional.push(arg);
+                continue;
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_owned(), value.to_owned()),
+                None => (flag.to_owned(), args.next().ok_or(format!("missing value for `{arg}`"))?),
+            };
+            flags.push((name, value));
+        }
+
+        let command = match (command.as_str(), positional.as_slice()) {
+            ("create", [name]) => {
+                let mut tier = Priority::default();
+                let mut quotas = Quotas::default();
+                let mut callback_url = None;
+                for (flag, value) in flags.drain(..) {
+                    match flag.as_str() {
+                        "tier" => tier = Priority::try_from(value)?,
+                        "renders-per-day" => quotas.renders_per_day = Some(number(&flag, &value)?),
+                        "max-upload-size" => quotas.max_upload_size = Some(number(&flag, &value)?),
+                        "max-concurrent-jobs" => quotas.max_concurrent_jobs = Some(number(&flag, &value)?),
+                        "max-lifetime" => {
+                            quotas.max_lifetime = Some(number(&flag, &value)? as usize * 60)
+                        },
+                        "callback-url" => callback_url = Some(parse_callback_url(&value)?),
+                        _ => return Err(format!("unknown option `--{flag}`")),
+                    }
+                }
+                KeyCommand::Create(ApiKey::new(name.clone(), tier, quotas, callback_url))
+            },
+            ("list", []) => KeyCommand::List,
+            ("revoke", [id]) => KeyCommand::Revoke(key_id(id)?),
+            ("usage", [id]) => {
+                let mut days = DEFAULT_USAGE_DAYS;
+                for (flag, value) in flags.drain(..) {
+                    match flag.as_str() {
+                        "days" => days = number(&flag, &value)?,
+                        _ => return Err(format!("unknown option `--{flag}`")),
+                    }
+                }
+                KeyCommand::Usage { id: key_id(id)?, days }
+            },
+            ("create" | "list" | "revoke" | "usage", _) => {
+                return Err(format!("wrong number of arguments for `{command}`"));
+            },
+            _ => return Err(format!("unknown command `{command}`")),
+        };
+        match flags.first() {
+            Some((flag, _)) => Err(format!("unknown option `--{flag}`")),
+            None => Ok(command),
+        }
+    }
+
+    // Run the command and return its output, which is JSON.
+    pub async fn run(self, conn: &mut RedisConn) -> anyhow::Result<String> {
+        let output = match self {
+            KeyCommand::Create(key) => {
+                let token = super::create(conn, &key).await?;
+                serde_json::to_string_pretty(&CreatedKey { api_key: &key, key: token })?
+            },
+            KeyCommand::List => serde_json::to_string_pretty(&super::list(conn).await?)?,
+            KeyCommand::Revoke(id) => {
+                if !super::revoke(conn, id).await? {
+                    anyhow::bail!("there is no API key {id}");
+                }
+                serde_json::to_string_pretty(&serde_json::json!({ "revoked": id }))?
+            },
+            KeyCommand::Usage { id, days } => {
+                if super::load(conn, id).await?.is_none() {
+                    anyhow::bail!("there is no API key {id}");
+                }
+                serde_json::to_string_pretty(&super::usage(conn, id, days).await?)?
+            },
+        };
+        Ok(output)
+    }
+}
+
+fn number(flag: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("value of `--{flag}` is not a whole number: `{value}`"))
+}
+
+fn key_id(value: &str) -> Result<Uuid, String> {
+    Uuid::parse_str(value).map_err(|_| format!("`{value}` is not an API key ID"))
+}
@@ -0,0 +1,303 @@
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use redis::AsyncCommands;
+use std::collections::VecDeque;
+use std::ops::Range;
+use uuid::Uuid;
+
+use crate::{RedisConn, RedisPool};
+use crate::storage;
+use crate::utils::civil_date;
+
+// Several finished videos can be downloaded as one ZIP archive, either by
+// listing their progress IDs or by the ID of the batch they were uploaded in.
+// Batches are started by the server (see `POST /batches`), and uploads join
+// one by naming its ID along with the token signed for it.
+//
+// Archives are streamed one chunk of a video at a time, just like single
+// videos. Videos are already compressed, so they're stored as they are. The
+// CRC of each video is only known once it's streamed, so it follows the
+// video in a data descriptor. The size of the whole archive is known
+// upfront, though. Archives are plain ZIP archives (no ZIP64), so they're
+// limited to 4GiB and `u16::MAX` videos.
+
+// Prefix of the redis lists holding the progress IDs of the uploads of a batch.
+const BATCH_KEY_PREFIX: &str = "batch";
+
+// Redis key of the batch `batch`.
+pub fn batch_key(batch: Uuid) -> String {
+    format!("{BATCH_KEY_PREFIX}:{batch}")
+}
+
+// Add adding the upload of `target` to `batch` to `pipe`. The batch is kept
+// for `ttl` seconds after its last upload. This is not atomic, so it can be
+// part of a transaction.
+pub fn add_to_batch_in(pipe: &mut redis::Pipeline, batch: Uuid, target: Uuid, ttl: usize) {
+    pipe.rpush(batch_key(batch), target.to_string()).ignore()
+        .expire(batch_key(batch), ttl).ignore();
+}
+
+// Progress IDs of the uploads of `batch` in the order they were uploaded.
+pub async fn batch_uploads(conn: &mut RedisConn, batch: Uuid) -> redis::RedisResult<Vec<Uuid>> {
+    let ids: Vec<String> = conn.lrange(batch_key(batch), 0, -1).await?;
+    Ok(ids.iter().filter_map(|id| Uuid::parse_str(id).ok()).collect())
+}
+
+// Signatures of the ZIP records.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+// Version 2.0 of the format is needed for data descriptors.
+const ZIP_VERSION: u16 = 20;
+// The sizes and CRC follow the data (bit 3) and names are UTF-8 (bit 11).
+const ZIP_FLAGS: u16 = (1 << 3) | (1 << 11);
+// Sizes (in bytes) of the fixed parts of the ZIP records.
+const LOCAL_HEADER_SIZE: u64 = 30;
+const DATA_DESCRIPTOR_SIZE: u64 = 16;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+
+// A video put into an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    // Name of the file in the archive.
+    pub name: String,
+    // Redis key of the video.
+    pub video: String,
+    // Size (in bytes) of the video.
+    pub size: u64,
+    // Unix timestamp (in seconds) the file was last modified at.
+    pub modified: u64,
+}
+
+// Size (in bytes) of the archive of `entries`, or `None` if
+// that's too big or they are too many for a ZIP archive.
+pub fn archive_size(entries: &[ArchiveEntry]) -> Option<u64> {
+    if entries.len() > u16::MAX as usize {
+        return None;
+    }
+    let size = entries.iter()
+        .map(|entry| {
+            let name = entry.name.len() as u64;
+            LOCAL_HEADER_SIZE + name + entry.size + DATA_DESCRIPTOR_SIZE + CENTRAL_HEADER_SIZE + name
+        })
+        .sum::<u64>() + END_OF_CENTRAL_DIRECTORY_SIZE;
+    (size <= u32::MAX as u64).then_some(size)
+}
+
+// Name `name` as a file in an archive which already has files named
+// `taken`. Repeated names are numbered: `Video.mp4`, `Video (2).mp4`, ...
+pub fn unique_name(name: &str, taken: &[String]) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    let mut candidate = name.to_owned();
+    let mut number = 1;
+    while taken.contains(&candidate) {
+        number += 1;
+        candidate = format!("{stem} ({number}){extension}");
+    }
+    candidate
+}
+
+// Writes a ZIP archive of stored (uncompressed) files piece by piece.
+// Every piece is returned to be sent right away.
+pub struct ZipWriter {
+    // Number of bytes written so far.
+    written: u64,
+    central_directory: Vec<u8>,
+    entries: u16,
+    current: Option<CurrentEntry>,
+}
+
+// The file which is being written.
+struct CurrentEntry {
+    name: String,
+    modified: (u16, u16),
+    // Offset of the file's local header.
+    offset: u64,
+    crc: crc32fast::Hasher,
+    size: u64,
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self { written: 0, central_directory: Vec::new(), entries: 0, current: None }
+    }
+
+    // Start writing the file `name`, which was last modified at the unix
+    // timestamp `modified`. Returns the file's local header.
+    pub fn start_entry(&mut self, name: &str, modified: u64) -> Bytes {
+        debug_assert!(self.current.is_none(), "the previous entry wasn't finished");
+        let entry = CurrentEntry {
+            name: name.to_owned(),
+            modified: dos_date_time(modified),
+            offset: self.written,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+        };
+        let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE as usize + name.len());
+        put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut header, ZIP_VERSION);
+        put_u16(&mut header, ZIP_FLAGS);
+        put_u16(&mut header, 0);  // stored
+        put_u16(&mut header, entry.modified.1);
+        put_u16(&mut header, entry.modified.0);
+        // The CRC and the sizes are sent in the data descriptor.
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 0);  // no extra field
+        header.extend_from_slice(name.as_bytes());
+        self.current = Some(entry);
+        self.emit(header)
+    }
+
+    // Write `data` of the current file.
+    pub fn write(&mut self, data: Bytes) -> Bytes {
+        let entry = self.current.as_mut().expect("no entry was started");
+        entry.crc.update(&data);
+        entry.size += data.len() as u64;
+        self.written += data.len() as u64;
+        data
+    }
+
+    // Finish the current file. Returns its data descriptor.
+    pub fn finish_entry(&mut self) -> Bytes {
+        let entry = self.current.take().expect("no entry was started");
+        let crc = entry.crc.finalize();
+
+        let record = &mut self.central_directory;
+        put_u32(record, CENTRAL_HEADER_SIGNATURE);
+        put_u16(record, ZIP_VERSION);  // made by
+        put_u16(record, ZIP_VERSION);  // needed to extract
+        put_u16(record, ZIP_FLAGS);
+        put_u16(record, 0);  // stored
+        put_u16(record, entry.modified.1);
+        put_u16(record, entry.modified.0);
+        put_u32(record, crc);
+        put_u32(record, entry.size as u32);
+        put_u32(record, entry.size as u32);
+        put_u16(record, entry.name.len() as u16);
+        put_u16(record, 0);  // no extra field
+        put_u16(record, 0);  // no comment
+        put_u16(record, 0);  // disk number
+        put_u16(record, 0);  // internal attributes
+        put_u32(record, 0);  // external attributes
+        put_u32(record, entry.offset as u32);
+        record.extend_from_slice(entry.name.as_bytes());
+        self.entries += 1;
+
+        let mut descriptor = Vec::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut descriptor, crc);
+        put_u32(&mut descriptor, entry.size as u32);
+        put_u32(&mut descriptor, entry.size as u32);
+        self.emit(descriptor)
+    }
+
+    // Finish the archive. Returns its central directory.
+    pub fn finish(&mut self) -> Bytes {
+        let offset = self.written;
+        let mut end = std::mem::take(&mut self.central_directory);
+        let size = end.len() as u32;
+        put_u32(&mut end, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut end, 0);  // disk number
+        put_u16(&mut end, 0);  // disk of the central directory
+        put_u16(&mut end, self.entries);
+        put_u16(&mut end, self.entries);
+        put_u32(&mut end, size);
+        put_u32(&mut end, offset as u32);
+        put_u16(&mut end, 0);  // no comment
+        self.emit(end)
+    }
+
+    fn emit(&mut self, data: Vec<u8>) -> Bytes {
+        self.written += data.len() as u64;
+        Bytes::from(data)
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+// Convert a unix timestamp (in seconds) to the MS-DOS date and time used
+// by ZIP archives, in UTC. Times before 1980 are clamped to 1980.
+fn dos_date_time(timestamp: u64) -> (u16, u16) {
+    let (year, month, day) = civil_date(timestamp / 86400);
+    let secs = timestamp % 86400;
+    if year < 1980 {
+        return (0x21, 0);  // 1980-01-01 00:00:00
+    }
+    let date = (((year - 1980).min(127) << 9) | (month << 5) | day) as u16;
+    let time = (((secs / 3600) << 11) | ((secs % 3600 / 60) << 5) | (secs % 60 / 2)) as u16;
+    (date, time)
+}
+
+// Stream the archive of `entries`, which is `archive_size(entries)` bytes
+// long. Only a single chunk of a video is held in memory at a time.
+pub fn stream_archive(
+    redis_pool: RedisPool,
+    entries: Vec<ArchiveEntry>,
+) -> impl Stream<Item = redis::RedisResult<Bytes>> {
+    // Chunks of a video which are left to be written.
+    type Chunks = std::vec::IntoIter<(u64, Range<usize>)>;
+    struct State {
+        redis_pool: RedisPool,
+        entries: VecDeque<ArchiveEntry>,
+        writer: ZipWriter,
+        // The key of the video being written and its chunks.
+        current: Option<(String, Chunks)>,
+        finished: bool,
+    }
+
+    let state = State {
+        redis_pool,
+        entries: entries.into(),
+        writer: ZipWriter::new(),
+        current: None,
+        finished: false,
+    };
+    futures_util::stream::try_unfold(state, |mut state| async move {
+        if let Some((video, chunks)) = &mut state.current {
+            if let Some((index, range)) = chunks.next() {
+                let mut conn = storage::chunk_conn(&state.redis_pool).await?;
+                let chunk = storage::video_chunk(&mut conn, video, index, range).await?;
+                let chunk = state.writer.write(chunk);
+                return Ok(Some((chunk, state)));
+            }
+            state.current = None;
+            let descriptor = state.writer.finish_entry();
+            return Ok(Some((descriptor, state)));
+        }
+        if let Some(entry) = state.entries.pop_front() {
+            let header = state.writer.start_entry(&entry.name, entry.modified);
+            let chunks = match entry.size {
+                0 => Vec::new(),
+                size => storage::chunk_slices(0, size - 1).collect(),
+            };
+            state.current = Some((entry.video, chunks.into_iter()));
+            return Ok(Some((header, state)));
+        }
+        if !state.finished {
+            state.finished = true;
+            let end = state.writer.finish();
+            return Ok(Some((end, state)));
+        }
+        Ok(None)
+    })
+}
@@ -0,0 +1,169 @@
+use anyhow::Context;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::routes::{RenderOptions, SubtitleMode};
+
+// Render workers probe their `ffmpeg` binary when they start. Capabilities
+// every render needs are required for the worker to start at all, while
+// missing optional capabilities only disable the features which need them.
+// The features of all running workers decide what the upload form offers.
+
+// Encoders and muxers every render needs.
+const REQUIRED_ENCODERS: &[&str] = &["libx264"];
+const REQUIRED_MUXERS: &[&str] = &["mp4"];
+
+// What the probed `ffmpeg` binary can do.
+#[derive(Debug, Clone)]
+pub struct FfmpegCapabilities {
+    pub version: String,
+    pub encoders: BTreeSet<String>,
+    pub muxers: BTreeSet<String>,
+    pub filters: BTreeSet<String>,
+}
+
+impl FfmpegCapabilities {
+    // Run the `ffmpeg` binary at `path` to find out what it supports.
+    pub async fn probe(path: &Path) -> anyhow::Result<Self> {
+        let version = run(path, "-version").await?;
+        Ok(Self {
+            version: parse_version(&version)
+                .context("failed to read ffmpeg version")?,
+            encoders: parse_list(&run(path, "-encoders").await?),
+            muxers: parse_list(&run(path, "-muxers").await?),
+            filters: parse_list(&run(path, "-filters").await?),
+        })
+    }
+
+    // Fail with a list of all missing capabilities that every render needs.
+    pub fn check_required(&self) -> anyhow::Result<()> {
+        let missing: Vec<String> = REQUIRED_ENCODERS.iter()
+            .filter(|e| !self.encoders.contains(**e))
+            .map(|e| format!("encoder `{e}`"))
+            .chain(REQUIRED_MUXERS.iter()
+                .filter(|m| !self.muxers.contains(**m))
+                .map(|m| format!("muxer `{m}`")))
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!("ffmpeg {} is missing required {}", self.version, missing.join(", "));
+        }
+        Ok(())
+    }
+
+    // Optional features which can be rendered with these capabilities.
+    pub fn features(&self) -> Features {
+        let encoder = |e: &str| self.encoders.contains(e);
+        let filters = |fs: &[&str]| fs.iter().all(|f| self.filters.contains(*f));
+        // Trimmed and looped audio is re-encoded.
+        let trim = encoder("aac");
+        let loop_audio = trim && filters(&["aresample", "aloop"]);
+        Features {
+            trim,
+            loop_audio,
+            loop_crossfade: loop_audio
+                && filters(&["asplit", "atrim", "asetpts", "acrossfade", "concat"]),
+            burn_subtitles: filters(&["subtitles"]),
+            soft_subtitles: encoder("mov_text"),
+        }
+    }
+}
+
+async fn run(path: &Path, flag: &str) -> anyhow::Result<String> {
+    let output = Command::new(path)
+        .args(["-hide_banner", flag])
+        .output().await
+        .context(format!("failed to run {}", path.display()))?;
+    if !output.status.success() {
+        anyhow::bail!("`{} {flag}` failed ({})", path.display(), output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Read the version from the output of `ffmpeg -version`,
+// which starts with `ffmpeg version <version> Copyright ...`.
+pub fn parse_version(output: &str) -> Option<String> {
+    output.lines().next()?
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()
+        .map(str::to_owned)
+}
+
+// Read the names from the output of `ffmpeg -encoders`, `-muxers` or `-filters`.
+// Entries are lines of flags followed by the name(s) and a description, e.g.
+// ` A....D aac    AAC (Advanced Audio Coding)`. The legends above the entries
+// look the same, except the name is `=`.
+pub fn parse_list(output: &str) -> BTreeSet<String> {
+    let is_flags = |s: &str| s.chars().all(|c| c.is_ascii_uppercase() || c == '.' || c == '|');
+    output.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let (flags, names) = (tokens.next()?, tokens.next()?);
+            (is_flags(flags) && names != "=").then_some(names)
+        })
+        // Some entries have multiple names, e.g. `matroska,webm`.
+        .flat_map(|names| names.split(','))
+        .map(str::to_owned)
+        .collect()
+}
+
+// Optional features a render worker supports.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub trim: bool,
+    pub loop_audio: bool,
+    pub loop_crossfade: bool,
+    pub burn_subtitles: bool,
+    pub soft_subtitles: bool,
+}
+
+impl Default for Features {
+    // Workers which don't report their features assume all of them are available.
+    fn default() -> Self {
+        Self {
+            trim: true,
+            loop_audio: true,
+            loop_crossfade: true,
+            burn_subtitles: true,
+            soft_subtitles: true,
+        }
+    }
+}
+
+impl Features {
+    // Features supported by both `self` and `other`.
+    pub fn intersect(self, other: Features) -> Features {
+        Features {
+            trim: self.trim && other.trim,
+            loop_audio: self.loop_audio && other.loop_audio,
+            loop_crossfade: self.loop_crossfade && other.loop_crossfade,
+            burn_subtitles: self.burn_subtitles && other.burn_subtitles,
+            soft_subtitles: self.soft_subtitles && other.soft_subtitles,
+        }
+    }
+
+    // Check all features needed to render with the given options are supported.
+    pub fn check(&self, options: &RenderOptions, has_subtitles: bool) -> Result<(), String> {
+        let needed = [
+            (options.is_trimmed(), self.trim, "trimming audio"),
+            (options.loop_duration.is_some(), self.loop_audio, "looping audio"),
+            (options.loop_crossfade.is_some(), self.loop_crossfade, "crossfading loops"),
+            (
+                has_subtitles && options.subtitle_mode == SubtitleMode::Burn,
+                self.burn_subtitles,
+                "showing lyrics in the video",
+            ),
+            (
+                has_subtitles && options.subtitle_mode == SubtitleMode::Soft,
+                self.soft_subtitles,
+                "adding a subtitle track",
+            ),
+        ];
+        match needed.iter().find(|(needed, supported, _)| *needed && !supported) {
+            Some((_, _, feature)) => Err(format!("{feature} is not supported right now")),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use serde_aux::field_attributes::deserialize_number_from_string;
 use secrecy::Secret;
+use std::path::PathBuf;
 
 #[derive(Clone, serde::Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub render_worker: RenderWorkerSettings,
+    pub webhooks: WebhookSettings,
     pub redis_uri: Secret<String>,
+    // Which parts of backdrop this process runs. Can be overwritten
+    // with the `--mode` command line flag.
+    #[serde(default)]
+    pub run_mode: RunMode,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -13,6 +19,11 @@ pub struct ApplicationSettings {
     pub host: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
+    // Token required to use the admin API. The admin API is disabled if it's not set.
+    #[serde(default)]
+    pub admin_token: Option<Secret<String>>,
+    // Secret used to sign download links.
+    pub download_secret: Secret<String>,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -24,9 +35,163 @@ pub struct RenderWorkerSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub laziness: u16,
     // Amount of time (in minutes) until a finished render
-    // is deleted again.
+    // is deleted again, unless the uploader chose another lifetime.
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub lifetime: u16,
+    // Longest amount of time (in minutes) uploaders may keep a finished render.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lifetime: u32,
+    // Amount of time (in seconds) a task may wait in the render queue before
+    // it's taken ahead of tasks with a higher priority.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_queue_wait: u32,
+    // Amount of time (in seconds) a running render may take to finish
+    // after shutdown was requested. Renders which take longer are aborted
+    // and their task is put back into the queue.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_grace_period: u16,
+    // Number of times rendering a task is attempted before it fails for good.
+    // Attempts count renders which failed as well as renders whose worker died.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    // Path of the `ffmpeg` binary used to render videos. Uploads
+    // are probed with the `ffprobe` binary next to it.
+    pub ffmpeg_path: PathBuf,
+    // Directory in which the files `ffmpeg` reads are buffered.
+    // Every task gets its own directory inside of it.
+    pub temp_root: PathBuf,
+    // Maximum amount of disk space (in megabytes) all buffered files may take
+    // up. Tasks which would exceed it are put back into the queue. 0 disables the limit.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub temp_quota: u64,
+    // Amount of time (in seconds) after which a task directory is considered
+    // abandoned. Abandoned directories are deleted when a worker starts.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub temp_max_age: u32,
+    // Amount of time (in seconds) after which a worker which stopped sending
+    // heartbeats is considered dead. Its tasks are then put back into the queue.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub heartbeat_ttl: u16,
+    // Port of the worker's own HTTP server reporting its health.
+    // The host is shared with the API server.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    // Resources a single `ffmpeg` process may use.
+    pub limits: RenderLimits,
+    // Whether `ffmpeg` runs in a sandbox without network access
+    // which can only write to the task's directory.
+    #[serde(default)]
+    pub sandbox: SandboxMode,
+}
+
+// Delivery of the webhooks which report finished renders.
+#[derive(Clone, serde::Deserialize)]
+pub struct WebhookSettings {
+    // Secret used to sign webhook payloads.
+    pub secret: Secret<String>,
+    // Amount of time (in seconds) a receiver may take to respond.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout: u16,
+    // Number of times a webhook is sent before it's given up on.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    // Amount of time (in seconds) until a failed webhook is sent again.
+    // The delay doubles with every failed attempt.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_delay: u32,
+    // Whether webhooks may be sent to loopback, private and link-local
+    // addresses. Only meant for tests.
+    #[serde(default)]
+    pub allow_private_addresses: bool,
+}
+
+// Resource limits of `ffmpeg` processes. A limit of 0 disables it.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RenderLimits {
+    // CPU time (in seconds) a single render may use.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cpu_time: u64,
+    // Amount of memory (in megabytes) a render may allocate.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory: u64,
+    // Size (in megabytes) of files a render may write, including the video.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub file_size: u64,
+    // Number of threads `ffmpeg` uses for encoding and filtering.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub threads: u16,
+    // Niceness added to the priority of `ffmpeg` processes (0 - 19).
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub nice: u8,
+}
+
+// Whether renders run in a sandbox. Sandboxing requires bubblewrap (`bwrap`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum SandboxMode {
+    // Never use a sandbox.
+    Off,
+    // Use a sandbox if it's available on the system.
+    #[default]
+    Auto,
+    // Refuse to start the worker if no sandbox is available.
+    Required,
+}
+
+impl TryFrom<String> for SandboxMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "auto" => Ok(Self::Auto),
+            "required" => Ok(Self::Required),
+            other => Err(format!(
+                "{} is not a supported sandbox mode. \
+                Use either `off`, `auto` or `required`.", other
+            ))
+        }
+    }
+}
+
+// Parts of backdrop a single process can run. Running the API and the
+// render workers in separate processes allows scaling them independently.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum RunMode {
+    // Only serve the web app and API.
+    Api,
+    // Only render videos from the queue.
+    Worker,
+    // Serve the API and render videos in the same process.
+    #[default]
+    All,
+}
+
+impl RunMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunMode::Api => "api",
+            RunMode::Worker => "worker",
+            RunMode::All => "all",
+        }
+    }
+}
+
+impl TryFrom<String> for RunMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "api" => Ok(Self::Api),
+            "worker" => Ok(Self::Worker),
+            "all" => Ok(Self::All),
+            other => Err(format!(
+                "{} is not a supported run mode. \
+                Use either `api`, `worker` or `all`.", other
+            ))
+        }
+    }
 }
 
 pub enum Environment {
@@ -35,7 +200,7 @@ pub enum Environment {
 }
 
 impl Environment {
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Environment::Local => "local",
             Environment::Production => "production",
@@ -0,0 +1,109 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::utils::unix_now;
+
+// Videos are downloaded through links which are only handed out once a
+// video is ready. A link names the progress ID of the video and the time it
+// expires at, signed with a server-side secret. Links can therefore neither
+// be forged for other keys nor be used after they expired.
+//
+// The uploader of a video additionally gets a delete token, which is
+// signed with the same secret. It allows to delete the video early.
+// Batches of uploads are started by the server, which hands out a batch
+// token signed the same way. Only uploads holding it can join the batch.
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Signs and verifies download links.
+pub struct DownloadLinks {
+    secret: Secret<String>,
+}
+
+impl DownloadLinks {
+    pub fn new(secret: Secret<String>) -> Self {
+        Self { secret }
+    }
+
+    fn mac(&self, progress_id: Uuid, expires: u64) -> HmacSha256 {
+        let mut mac = self.keyed_mac();
+        mac.update(progress_id.as_bytes());
+        mac.update(&expires.to_be_bytes());
+        mac
+    }
+
+    // The prefix keeps delete tokens from being valid link signatures.
+    fn delete_mac(&self, progress_id: Uuid) -> HmacSha256 {
+        let mut mac = self.keyed_mac();
+        mac.update(b"delete");
+        mac.update(progress_id.as_bytes());
+        mac
+    }
+
+    fn batch_mac(&self, batch: Uuid) -> HmacSha256 {
+        let mut mac = self.keyed_mac();
+        mac.update(b"batch");
+        mac.update(batch.as_bytes());
+        mac
+    }
+
+    fn keyed_mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(self.secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length")
+    }
+
+    // Hex encoded signature of a link to `progress_id` which expires at
+    // the unix timestamp `expires`.
+    pub fn sign(&self, progress_id: Uuid, expires: u64) -> String {
+        hex::encode(self.mac(progress_id, expires).finalize().into_bytes())
+    }
+
+    // URL to download the video of `progress_id` until `expires`.
+    pub fn url(&self, progress_id: Uuid, expires: u64) -> String {
+        self.link("load", progress_id, expires)
+    }
+
+    // URL of the poster of the video of `progress_id` until `expires`.
+    // The poster shares the signature of the video.
+    pub fn poster_url(&self, progress_id: Uuid, expires: u64) -> String {
+        self.link("poster", progress_id, expires)
+    }
+
+    fn link(&self, route: &str, progress_id: Uuid, expires: u64) -> String {
+        let signature = self.sign(progress_id, expires);
+        format!("/{route}/{progress_id}?expires={expires}&signature={signature}")
+    }
+
+    // Check a link was signed by us and hasn't expired yet.
+    pub fn verify(&self, progress_id: Uuid, expires: u64, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        expires >= unix_now()
+            && self.mac(progress_id, expires).verify_slice(&signature).is_ok()
+    }
+
+    // Hex encoded token which allows to delete the video of `progress_id`.
+    pub fn delete_token(&self, progress_id: Uuid) -> String {
+        hex::encode(self.delete_mac(progress_id).finalize().into_bytes())
+    }
+
+    // Check a delete token was issued for `progress_id`.
+    pub fn verify_delete_token(&self, progress_id: Uuid, token: &str) -> bool {
+        hex::decode(token)
+            .is_ok_and(|token| self.delete_mac(progress_id).verify_slice(&token).is_ok())
+    }
+
+    // Hex encoded token which allows uploads to join `batch`.
+    pub fn batch_token(&self, batch: Uuid) -> String {
+        hex::encode(self.batch_mac(batch).finalize().into_bytes())
+    }
+
+    // Check a batch token was issued for `batch`.
+    pub fn verify_batch_token(&self, batch: Uuid, token: &str) -> bool {
+        hex::decode(token)
+            .is_ok_and(|token| self.batch_mac(batch).verify_slice(&token).is_ok())
+    }
+}
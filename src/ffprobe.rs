@@ -0,0 +1,130 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::configuration::RenderLimits;
+use crate::render_worker::sandbox::apply_limits;
+
+// What `ffprobe` found out about uploaded audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioProbe {
+    // Duration in seconds.
+    pub duration: f64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+// Amount of time `ffprobe` may take to read an upload. Crafted files can
+// make it hang, so it's killed after this.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The `ffprobe` binary used to read uploaded audio. It comes with `ffmpeg`,
+// so it's looked up next to the `ffmpeg` binary the renders use. Uploads are
+// untrusted, so it runs with the resource limits of renders.
+#[derive(Debug, Clone)]
+pub struct Ffprobe {
+    path: PathBuf,
+    limits: RenderLimits,
+}
+
+impl Ffprobe {
+    // `ffprobe` next to the `ffmpeg` binary at `ffmpeg_path`, e.g.
+    // `/opt/ffmpeg/bin/ffprobe` for `/opt/ffmpeg/bin/ffmpeg`. Binaries
+    // without `ffmpeg` in their name fall back to `ffprobe` on the `PATH`.
+    pub fn next_to(ffmpeg_path: &Path, limits: RenderLimits) -> Self {
+        let name = ffmpeg_path.file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.contains("ffmpeg"))
+            .map(|name| name.replacen("ffmpeg", "ffprobe", 1));
+        let path = match name {
+            Some(name) => ffmpeg_path.with_file_name(name),
+            None => PathBuf::from("ffprobe"),
+        };
+        Self { path, limits }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Determine the duration (in seconds) and the title and artist tags of the
+    // given media data. `None` is returned if `ffprobe` could not make sense
+    // of the data in time.
+    //
+    // The data is buffered in a temporary file because `ffprobe` cannot
+    // reliably determine the duration of piped input.
+    pub async fn probe_audio(&self, data: &[u8]) -> anyhow::Result<Option<AudioProbe>> {
+        let buffer = ProbeBuffer(std::env::temp_dir().join(format!("backdrop-probe-{}", Uuid::new_v4())));
+        tokio::fs::write(&buffer.0, data).await
+            .context("failed to buffer media data for probing")?;
+
+        let mut command = Command::new(&self.path);
+        command
+            .args(["-v", "error"])
+            // Only print the container's duration and tags.
+            .args(["-show_entries", "format=duration:format_tags"])
+            .args(["-of", "json"])
+            .arg(&buffer.0)
+            .stdin(Stdio::null())
+            // The probe is killed if the request is dropped or it times out.
+            .kill_on_drop(true);
+        apply_limits(&mut command, &self.limits);
+
+        let output = match tokio::time::timeout(PROBE_TIMEOUT, command.output()).await {
+            Ok(output) => output.context("failed to spawn ffprobe process")?,
+            Err(_) => {
+                tracing::warn!("ffprobe took longer than {PROBE_TIMEOUT:?} to read an upload");
+                return Ok(None);
+            },
+        };
+        if !output.status.success() {
+            tracing::trace!("ffprobe stderr: {0}", String::from_utf8_lossy(&output.stderr));
+            return Ok(None);
+        }
+        Ok(parse_probe(&output.stdout))
+    }
+}
+
+// Temporary file holding data to probe. It's removed when dropped, which
+// includes requests dropped by disconnecting clients.
+struct ProbeBuffer(PathBuf);
+
+impl Drop for ProbeBuffer {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.0) {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => tracing::warn!("failed to remove probe buffer {}: {e:?}", self.0.display()),
+        }
+    }
+}
+
+// Parse the JSON output of `ffprobe`.
+pub fn parse_probe(output: &[u8]) -> Option<AudioProbe> {
+    #[derive(serde::Deserialize)]
+    struct Output {
+        format: Format,
+    }
+    #[derive(serde::Deserialize)]
+    struct Format {
+        duration: Option<String>,
+        #[serde(default)]
+        tags: HashMap<String, String>,
+    }
+
+    let format = serde_json::from_slice::<Output>(output).ok()?.format;
+    // `ffprobe` leaves out the duration if it's unknown.
+    let duration = format.duration?.parse::<f64>().ok()
+        .filter(|d| d.is_finite() && *d > 0.0)?;
+    // Some containers use upper case tag names (e.g. `TITLE` in FLAC files).
+    let tag = |name: &str| format.tags.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_owned())
+        .filter(|value| !value.is_empty());
+    Some(AudioProbe { duration, title: tag("title"), artist: tag("artist") })
+}
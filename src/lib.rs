@@ -5,6 +5,22 @@ pub mod utils;
 pub mod configuration;
 pub mod render_worker;
 pub mod content_length_limit;
+pub mod ffprobe;
+pub mod storage;
+pub mod shutdown;
+pub mod priority;
+pub mod capabilities;
+pub mod render_cache;
+pub mod render_stats;
+pub mod metrics;
+pub mod download_link;
+pub mod purge;
+pub mod retention;
+pub mod progress_events;
+pub mod webhooks;
+pub mod task_state;
+pub mod archive;
+pub mod api_keys;
 
 pub type RedisPool = mobc::Pool<mobc_redis::RedisConnectionManager>;
 pub type RedisConn = mobc::Connection<mobc_redis::RedisConnectionManager>;
@@ -15,6 +31,8 @@ pub const PENDING: &str = "pending";
 pub const GONE: &str = "gone";
 // Indicate a requested asset is ready for download.
 pub const READY: &str = "ready";
+// Indicate the render of an asset failed for good.
+pub const FAILED: &str = "failed";
 // Redis key for the render queue
 pub const RENDER_QUEUE_KEY: &str = "render-worker-queue";
 // Redis discard command name (I am afraid I will misspell it otherwise).
@@ -23,3 +41,4 @@ const REDIS_DISCARD: &str = "DISCARD";
 // const REDIS_TTL_NO_EXPIRE: i32 = -1;
 // Value returned by redis TTL command if the given key does not exist -> has expired.
 const REDIS_TTL_EXPIRED: i32 = -2;
+
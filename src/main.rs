@@ -1,7 +1,10 @@
 use backdrop::startup::Application;
-use backdrop::configuration::get_configuration;
+use backdrop::configuration::{get_configuration, RunMode};
 use backdrop::telemetry::*;
 use backdrop::render_worker;
+use backdrop::shutdown::Shutdown;
+use backdrop::startup::get_redis_pool;
+use backdrop::api_keys::command::{KeyCommand, USAGE};
 
 use tokio::task::JoinError;
 
@@ -13,38 +16,122 @@ async fn main() -> anyhow::Result<()> {
         std::io::stdout,
     ));
 
-    let configuration = get_configuration().expect("Failed to read configuration");
-    let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task= tokio::spawn(render_worker::run_until_stopped(configuration));
-
-    tokio::select!(
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Render worker", o),
-    );
-    
-    Ok(())
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    let mut args = std::env::args().skip(1).peekable();
+    // Admin command to manage API keys.
+    if args.next_if(|arg| arg == "keys").is_some() {
+        let command = KeyCommand::parse(args)
+            .map_err(|e| anyhow::anyhow!("{e}\n\n{USAGE}"))?;
+        let redis_pool = get_redis_pool(configuration.redis_uri).await?;
+        let mut conn = redis_pool.get().await
+            .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+        println!("{}", command.run(&mut conn).await?);
+        return Ok(());
+    }
+
+    let run_mode = match run_mode_from_args(args) {
+        Ok(Some(mode)) => mode,
+        Ok(None) => configuration.run_mode,
+        Err(e) => anyhow::bail!(e),
+    };
+    tracing::info!("Starting backdrop in `{}` mode", run_mode.as_str());
+    // The API reports the mode the process actually runs in.
+    configuration.run_mode = run_mode;
+
+    let shutdown = Shutdown::on_signals();
+
+    match run_mode {
+        RunMode::Api => {
+            let application = Application::build(configuration).await?;
+            stop_on_shutdown(&application, shutdown);
+            report_exit("API", tokio::spawn(application.run_until_stopped()).await)
+        },
+        RunMode::Worker => {
+            let worker_task = tokio::spawn(render_worker::run_until_stopped(configuration, shutdown));
+            report_exit("Render worker", worker_task.await)
+        },
+        RunMode::All => {
+            let application = Application::build(configuration.clone()).await?;
+            stop_on_shutdown(&application, shutdown.clone());
+            let mut application_task = tokio::spawn(application.run_until_stopped());
+            let mut worker_task= tokio::spawn(
+                render_worker::run_until_stopped(configuration, shutdown.clone())
+            );
+
+            // On shutdown, the other task is awaited as well so it can finish its work.
+            // The process fails if either of them failed.
+            tokio::select!(
+                o = &mut application_task => {
+                    let api = report_exit("API", o);
+                    if shutdown.is_triggered() {
+                        api.and(report_exit("Render worker", worker_task.await))
+                    } else {
+                        api
+                    }
+                },
+                o = &mut worker_task => {
+                    let worker = report_exit("Render worker", o);
+                    if shutdown.is_triggered() {
+                        worker.and(report_exit("API", application_task.await))
+                    } else {
+                        worker
+                    }
+                },
+            )
+        },
+    }
+}
+
+// Gracefully stop the application's server once shutdown is triggered.
+fn stop_on_shutdown(application: &Application, mut shutdown: Shutdown) {
+    let handle = application.handle();
+    tokio::spawn(async move {
+        shutdown.triggered().await;
+        tracing::info!("Stopping API server");
+        handle.stop(true).await;
+    });
 }
 
+// Read the run mode from the `--mode <mode>` (or `--mode=<mode>`) flag.
+fn run_mode_from_args(mut args: impl Iterator<Item = String>) -> Result<Option<RunMode>, String> {
+    let mut mode = None;
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--mode") {
+            Some("") => args.next().ok_or("missing value for `--mode`")?,
+            Some(rest) if rest.starts_with('=') => rest[1..].to_owned(),
+            _ => return Err(format!("unknown argument `{arg}`")),
+        };
+        mode = Some(RunMode::try_from(value)?);
+    }
+    Ok(mode)
+}
+
+// Log how the task exited. A failed task is returned as an error,
+// so the process exits with a non-zero status.
 fn report_exit(
     task_name: &str,
     outcome: Result<Result<(), impl std::fmt::Debug + std::fmt::Display>, JoinError>,
-) {
+) -> anyhow::Result<()> {
     match outcome {
-        Ok(Ok(())) => tracing::info!("{task_name} has exited"),
+        Ok(Ok(())) => {
+            tracing::info!("{task_name} has exited");
+            Ok(())
+        },
         Ok(Err(e)) => {
             tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
                 "{task_name} failed",
-            )
+            );
+            anyhow::bail!("{task_name} failed: {e}")
         },
         Err(e) => {
             tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
                 "'{task_name}' task failed to complete",
-            )
+            );
+            anyhow::bail!("'{task_name}' task failed to complete: {e}")
         }
     }
 }
@@ -0,0 +1,211 @@
+use actix_web::{web, Error, HttpResponse};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::CONTENT_TYPE;
+use futures_util::future::LocalBoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, Histogram, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::future::{ready, Ready};
+use std::ops::DerefMut;
+use std::time::Instant;
+
+use crate::RedisPool;
+use crate::priority::Priority;
+
+// Metrics are collected in a process-wide registry and served in the
+// Prometheus text format on `GET /metrics`. The API serves them on its own
+// port and render workers serve them next to their health check, so worker-only
+// processes can be scraped as well.
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+// Register a metric in the registry. Registering only fails for
+// duplicate or invalid metrics, which is a bug.
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY.register(Box::new(metric.clone())).expect("invalid metric");
+    metric
+}
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("http_requests_total", "Number of HTTP requests handled"),
+    &["method", "route", "status"],
+).unwrap()));
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| register(HistogramVec::new(
+    HistogramOpts::new("http_request_duration_seconds", "Time spent handling HTTP requests"),
+    &["method", "route"],
+).unwrap()));
+
+pub static UPLOAD_SIZE: Lazy<Histogram> = Lazy::new(|| register(Histogram::with_opts(
+    HistogramOpts::new("upload_size_bytes", "Total size of the assets of an upload")
+        .buckets(prometheus::exponential_buckets(64.0 * 1024.0, 2.0, 8).unwrap()),
+).unwrap()));
+
+pub static QUEUE_LENGTH: Lazy<IntGaugeVec> = Lazy::new(|| register(IntGaugeVec::new(
+    Opts::new("render_queue_length", "Number of tasks waiting in the render queue"),
+    &["priority"],
+).unwrap()));
+
+pub static RENDER_DURATION: Lazy<HistogramVec> = Lazy::new(|| register(HistogramVec::new(
+    HistogramOpts::new("render_duration_seconds", "Time spent on a render attempt, whatever its outcome")
+        .buckets(prometheus::exponential_buckets(1.0, 2.0, 12).unwrap()),
+    &["profile"],
+).unwrap()));
+
+pub static RENDER_OUTCOMES: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("render_outcomes_total", "Number of finished render attempts by outcome"),
+    &["outcome"],
+).unwrap()));
+
+pub static WRITTEN_BYTES: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("written_bytes_total", "Number of bytes of uploads and videos written to redis"),
+    &["kind"],
+).unwrap()));
+
+pub static STORED_BYTES: Lazy<IntGauge> = Lazy::new(|| register(IntGauge::with_opts(
+    Opts::new("redis_used_memory_bytes", "Memory redis uses to store all data, including files"),
+).unwrap()));
+
+// Make sure all metrics are exported, including the ones which weren't used yet.
+fn register_all() {
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&UPLOAD_SIZE);
+    Lazy::force(&QUEUE_LENGTH);
+    Lazy::force(&RENDER_DURATION);
+    Lazy::force(&RENDER_OUTCOMES);
+    Lazy::force(&WRITTEN_BYTES);
+    Lazy::force(&STORED_BYTES);
+}
+
+// Outcomes of a render attempt.
+#[derive(Debug, Clone, Copy)]
+pub enum RenderOutcome {
+    // The video was rendered and published.
+    Success,
+    // The task failed for good.
+    Failure,
+    // The task was put back into the queue to be rendered again.
+    Retry,
+    // The task was resolved to an identical video which was rendered before.
+    Cached,
+}
+
+impl RenderOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RenderOutcome::Success => "success",
+            RenderOutcome::Failure => "failure",
+            RenderOutcome::Retry => "retry",
+            RenderOutcome::Cached => "cached",
+        }
+    }
+}
+
+pub fn record_render_outcome(outcome: RenderOutcome) {
+    RENDER_OUTCOMES.with_label_values(&[outcome.as_str()]).inc();
+}
+
+// Serve all metrics in the Prometheus text format. Gauges which
+// reflect the state in redis are updated on every scrape.
+pub async fn metrics(redis_pool: web::Data<RedisPool>) -> HttpResponse {
+    register_all();
+    if let Err(e) = update_queue_length(&redis_pool).await {
+        tracing::warn!("Failed to query render queue length for metrics: {e:?}");
+    }
+    if let Err(e) = update_stored_bytes(&redis_pool).await {
+        tracing::warn!("Failed to query redis memory usage for metrics: {e:?}");
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buf) {
+        tracing::error!("Failed to encode metrics: {e:?}");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, encoder.format_type()))
+        .body(buf)
+}
+
+async fn update_queue_length(redis_pool: &RedisPool) -> anyhow::Result<()> {
+    let mut conn = redis_pool.get().await?;
+    let mut pipe = redis::pipe();
+    for priority in Priority::ALL {
+        pipe.llen(priority.queue_key());
+    }
+    let lengths: Vec<i64> = pipe.query_async(conn.deref_mut()).await?;
+    for (priority, length) in Priority::ALL.iter().zip(lengths) {
+        QUEUE_LENGTH.with_label_values(&[priority.as_str()]).set(length);
+    }
+    Ok(())
+}
+
+// Files are stored in redis, so its memory usage is the amount of stored data.
+async fn update_stored_bytes(redis_pool: &RedisPool) -> anyhow::Result<()> {
+    let mut conn = redis_pool.get().await?;
+    let info: String = redis::cmd("INFO").arg("memory")
+        .query_async(conn.deref_mut()).await?;
+    let used_memory = info.lines()
+        .find_map(|line| line.strip_prefix("used_memory:"))
+        .ok_or_else(|| anyhow::anyhow!("`INFO memory` lacks `used_memory`"))?;
+    STORED_BYTES.set(used_memory.trim().parse()?);
+    Ok(())
+}
+
+// Middleware recording the count and duration of requests per route.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            // Routes are labeled by their pattern (e.g. `/done/{progressId}`)
+            // so the number of labels doesn't grow with every ID.
+            let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_owned());
+            let status = res.status().as_u16().to_string();
+            HTTP_REQUESTS.with_label_values(&[&method, &route, &status]).inc();
+            HTTP_REQUEST_DURATION.with_label_values(&[&method, &route])
+                .observe(start.elapsed().as_secs_f64());
+            Ok(res)
+        })
+    }
+}
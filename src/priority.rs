@@ -0,0 +1,69 @@
+use serde::{Serialize, Deserialize};
+
+use crate::RENDER_QUEUE_KEY;
+
+// Redis keys of the render queue's priority lanes.
+// Normal priority tasks use the original queue key.
+const HIGH_PRIORITY_QUEUE_KEY: &str = "render-worker-queue:high";
+const LOW_PRIORITY_QUEUE_KEY: &str = "render-worker-queue:low";
+
+// Priority of a render task. Every priority has its own lane in the
+// render queue, and workers take tasks from higher lanes first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    // All priorities in the order in which their lanes are drained.
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+
+    // Whether tasks of this priority are rendered before those of `other`.
+    pub fn is_above(&self, other: Priority) -> bool {
+        let rank = |p: Priority| Self::ALL.iter().position(|q| *q == p);
+        rank(*self) < rank(other)
+    }
+
+    // Redis key of the list holding the queued tasks of this priority.
+    pub fn queue_key(&self) -> &'static str {
+        match self {
+            Priority::High => HIGH_PRIORITY_QUEUE_KEY,
+            Priority::Normal => RENDER_QUEUE_KEY,
+            Priority::Low => LOW_PRIORITY_QUEUE_KEY,
+        }
+    }
+}
+
+impl TryFrom<String> for Priority {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "high" => Ok(Self::High),
+            "normal" => Ok(Self::Normal),
+            "low" => Ok(Self::Low),
+            other => Err(format!(
+                "{} is not a supported priority. \
+                Use either `high`, `normal` or `low`.", other
+            ))
+        }
+    }
+}
+
+impl From<Priority> for String {
+    fn from(p: Priority) -> Self {
+        p.as_str().to_owned()
+    }
+}
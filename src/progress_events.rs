@@ -0,0 +1,140 @@
+use futures_util::{stream, Stream, StreamExt};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::RedisConn;
+
+// Render workers publish an event on a redis channel of an upload whenever
+// the state of its task changes and while its video is rendered. The API
+// forwards the events to the download page (see `GET /done/events`), so the
+// page doesn't have to poll for changes. Events are only hints: the state
+// itself is always read from redis, so a missed event is never harmful.
+
+// Prefix of the redis channels carrying the events of an upload.
+const CHANNEL_PREFIX: &str = "progress-events";
+// Minimum amount of time between two progress events of a render.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+// Number of events kept for subscribers which haven't received them yet.
+const EVENT_BUFFER: usize = 1024;
+// Amount of time between attempts to subscribe to the events again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+fn channel(target: Uuid) -> String {
+    format!("{CHANNEL_PREFIX}:{target}")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ProgressEvent {
+    // The state of the upload changed. Its task was picked up, put back into
+    // the queue, finished or failed, or the upload was extended or deleted.
+    State,
+    // Rendering has advanced to `fraction` (0 - 1) of the video.
+    Progress { fraction: f64 },
+}
+
+// Publish `event` for the upload of `target`. Failing to publish is
+// only logged, because nothing depends on the event being received.
+pub async fn publish(conn: &mut RedisConn, target: Uuid, event: ProgressEvent) {
+    let payload = match serde_json::to_string(&event) {
+        Ok(payload) => payload,
+        Err(e) => return tracing::warn!("Failed to serialize progress event: {e:?}"),
+    };
+    if let Err(e) = conn.publish::<_, _, ()>(channel(target), payload).await {
+        tracing::warn!("Failed to publish progress event of {target}: {e:?}");
+    }
+}
+
+// Subscriptions to the events of uploads. A single connection subscribes
+// to the events of all uploads and hands them out to the subscribers,
+// since subscribed connections can't be pooled.
+#[derive(Clone)]
+pub struct ProgressEvents {
+    sender: broadcast::Sender<(Uuid, ProgressEvent)>,
+}
+
+impl ProgressEvents {
+    // Start receiving events in the background. Must be called inside of a runtime.
+    pub fn new(client: redis::Client) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        tokio::spawn(receive_events(client, sender.clone()));
+        Self { sender }
+    }
+
+    // Receive the events of the upload of `target`. A subscriber which falls
+    // behind gets a `State` event in place of the events it missed.
+    pub fn subscribe(&self, target: Uuid) -> impl Stream<Item = ProgressEvent> {
+        stream::unfold(self.sender.subscribe(), move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok((upload, event)) if upload == target => return Some((event, receiver)),
+                    Ok(_) => {},
+                    Err(RecvError::Lagged(_)) => return Some((ProgressEvent::State, receiver)),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+// Forward the events of all uploads to `sender`, reconnecting
+// whenever the connection is lost.
+async fn receive_events(client: redis::Client, sender: broadcast::Sender<(Uuid, ProgressEvent)>) {
+    loop {
+        match subscribe_all(&client).await {
+            Ok(messages) => {
+                let mut messages = Box::pin(messages);
+                while let Some(msg) = messages.next().await {
+                    let upload = msg.get_channel_name()
+                        .strip_prefix(CHANNEL_PREFIX)
+                        .and_then(|name| name.strip_prefix(':'))
+                        .and_then(|id| Uuid::parse_str(id).ok());
+                    let event = msg.get_payload::<String>().ok()
+                        .and_then(|payload| serde_json::from_str(&payload).ok());
+                    if let (Some(upload), Some(event)) = (upload, event) {
+                        // Sending only fails if nobody is subscribed.
+                        let _ = sender.send((upload, event));
+                    }
+                }
+                tracing::warn!("Lost the subscription to progress events; reconnecting");
+            },
+            Err(e) => tracing::warn!("Failed to subscribe to progress events: {e:?}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscribe_all(client: &redis::Client) -> redis::RedisResult<impl Stream<Item = redis::Msg>> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.psubscribe(format!("{CHANNEL_PREFIX}:*")).await?;
+    Ok(pubsub.into_on_message())
+}
+
+// Publishes the progress of a render, at most once per `TICK_INTERVAL`.
+pub struct ProgressTicker {
+    conn: RedisConn,
+    target: Uuid,
+    // Duration (in seconds) of the video being rendered.
+    duration: f64,
+    last_tick: Option<Instant>,
+}
+
+impl ProgressTicker {
+    pub fn new(conn: RedisConn, target: Uuid, duration: f64) -> Self {
+        Self { conn, target, duration, last_tick: None }
+    }
+
+    // Report that the first `rendered` seconds of the video are rendered.
+    pub async fn tick(&mut self, rendered: f64) {
+        if self.duration <= 0.0 || self.last_tick.is_some_and(|t| t.elapsed() < TICK_INTERVAL) {
+            return;
+        }
+        self.last_tick = Some(Instant::now());
+        let fraction = (rendered / self.duration).clamp(0.0, 1.0);
+        publish(&mut self.conn, self.target, ProgressEvent::Progress { fraction }).await;
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::priority::Priority;
+use crate::render_cache::REFS_KEY_PREFIX;
+use crate::render_worker::{queue, registry};
+use crate::storage::INFO_KEY_PREFIX;
+use crate::utils::unix_now;
+use crate::webhooks::{self, DELIVERY_QUEUE_KEY};
+use crate::task_state::{task_key, GRACE_PERIOD};
+
+// Uploads can be deleted before they expire. Everything stored for the
+// upload is deleted at once and its task is cancelled:
+// - A queued task is removed from the queue along with its assets.
+// - The assets of a task which is being rendered are deleted. The worker
+//   notices the cancelled task and drops the task or its video.
+// - A finished video is deleted unless other uploads still refer to it
+//   (see `render_cache`). Only the reference of this upload is removed then.
+
+// Delete everything stored for the upload of `target`. Returns `false`
+// if there is no such upload, or it was already cancelled or expired.
+pub async fn purge(conn: &mut RedisConn, target: Uuid) -> anyhow::Result<bool> {
+    let registry = registry::list_workers(conn).await?;
+    let processing_keys = registry.alive.iter().map(|w| w.id)
+        .chain(registry.dead)
+        .map(queue::processing_key);
+
+    let script = redis::Script::new(r"
+        local task = redis.call('HMGET', KEYS[1], 'status', 'video')
+        local status, video = task[1], task[2]
+        if not status or status == 'cancelled' or status == 'expired' then
+            return 0
+        end
+
+        if status == 'queued' or status == 'rendering' then
+            -- Report the cancellation to the upload's callback URL.
+            local url = redis.call('GET', KEYS[2])
+            if url then
+                redis.call('DEL', KEYS[2])
+                local delivery = cjson.encode({
+                    id = ARGV[5], url = url, target = ARGV[1], event = 'cancelled',
+                    occurred_at = tonumber(ARGV[6]), attempts = 0,
+                })
+                redis.call('ZADD', KEYS[3], ARGV[6], delivery)
+            end
+
+            local lanes = tonumber(ARGV[4])
+            for i = 4, #KEYS do
+                for _, raw in ipairs(redis.call('LRANGE', KEYS[i], 0, -1)) do
+                    local ok, task = pcall(cjson.decode, raw)
+                    if ok and task.target == ARGV[1] then
+                        -- Tasks in processing lists are owned by their worker.
+                        if i < 4 + lanes then
+                            redis.call('LREM', KEYS[i], 1, raw)
+                        end
+                        redis.call('DEL', task.image, task.audio)
+                        if type(task.subtitles) == 'string' then
+                            redis.call('DEL', task.subtitles)
+                        end
+                    end
+                end
+            end
+        elseif status == 'ready' and video then
+            local refs = ARGV[2] .. ':' .. video
+            if redis.call('EXISTS', refs) == 0 or redis.call('DECR', refs) <= 0 then
+                redis.call('DEL', video, refs, ARGV[3] .. ':' .. video)
+            end
+        end
+
+        redis.call('HDEL', KEYS[1], 'video')
+        redis.call('HSET', KEYS[1], 'status', 'cancelled', 'updated_at', ARGV[6], 'finished_at', ARGV[6])
+        redis.call('EXPIRE', KEYS[1], ARGV[7])
+        return 1
+    ");
+    let mut invocation = script.prepare_invoke();
+    invocation
+        .key(task_key(target))
+        .key(webhooks::callback_key(target))
+        .key(DELIVERY_QUEUE_KEY);
+    for lane in Priority::ALL {
+        invocation.key(lane.queue_key());
+    }
+    for key in processing_keys {
+        invocation.key(key);
+    }
+    invocation
+        .arg(target.to_string())
+        .arg(REFS_KEY_PREFIX)
+        .arg(INFO_KEY_PREFIX)
+        .arg(Priority::ALL.len())
+        .arg(Uuid::new_v4().to_string())
+        .arg(unix_now())
+        .arg(GRACE_PERIOD);
+
+    let purged: u8 = invocation.invoke_async(conn.deref_mut()).await
+        .context("failed to purge upload")?;
+    Ok(purged == 1)
+}
@@ -0,0 +1,138 @@
+use sha2::{Digest, Sha256};
+use std::ops::DerefMut;
+
+use crate::RedisConn;
+use crate::storage;
+use crate::routes::RenderOptions;
+
+// Uploading the same assets with the same options always renders the same
+// video. Every upload is therefore assigned a cache key derived from the
+// hashes of its assets and its options. Once a video is rendered, the cache
+// key points to it for as long as the video lives, so repeated uploads are
+// resolved to the existing video instead of rendering it again.
+//
+// A cached video can be referenced by several uploads. The number of
+// references is counted next to the video, so it's only deleted once no
+// upload refers to it anymore. The video, its metadata, its reference count
+// and the cache entry always share the same TTL, which is extended to the
+// lifetime the new upload chose whenever the video is referenced again.
+
+// Version of the cache key derivation. Bump it whenever the way videos are
+// rendered changes, so videos rendered the old way are not reused.
+const CACHE_KEY_VERSION: &str = "v1";
+// Prefix of the redis keys mapping cache keys to videos.
+const CACHE_KEY_PREFIX: &str = "render-cache";
+// Prefix of the redis keys counting the references to a video.
+pub const REFS_KEY_PREFIX: &str = "video-refs";
+
+// Hash of an asset's data.
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Derive the cache key of a render from the hashes of its assets and its options.
+pub fn cache_key(
+    audio_hash: &str,
+    image_hash: &str,
+    subtitles_hash: Option<&str>,
+    options: &RenderOptions,
+) -> String {
+    let mut hasher = Sha256::new();
+    for part in [CACHE_KEY_VERSION, audio_hash, image_hash, subtitles_hash.unwrap_or("")] {
+        hasher.update(part.as_bytes());
+        // Separate the parts so they can't be shifted into each other.
+        hasher.update([0]);
+    }
+    // Serializing a struct always writes the fields in the same order.
+    hasher.update(serde_json::to_vec(options).unwrap_or_default());
+    format!("{:x}", hasher.finalize())
+}
+
+fn cache_entry_key(cache_key: &str) -> String {
+    format!("{CACHE_KEY_PREFIX}:{cache_key}")
+}
+
+pub fn refs_key(video_key: &str) -> String {
+    format!("{REFS_KEY_PREFIX}:{video_key}")
+}
+
+// Access to the render cache for an upload whose video is kept for `lifetime`
+// seconds. Cached videos live for at least that long after they were last referenced.
+#[derive(Clone, Copy)]
+pub struct RenderCache {
+    lifetime: usize,
+}
+
+impl RenderCache {
+    pub fn new(lifetime: usize) -> Self {
+        Self { lifetime }
+    }
+
+    // Add a reference to the live video cached under `cache_key`. Returns the
+    // key of the video and its remaining lifetime, or `None` if no live video
+    // is cached.
+    pub async fn reference(
+        &self,
+        conn: &mut RedisConn,
+        cache_key: &str,
+    ) -> redis::RedisResult<Option<(String, usize)>> {
+        // This is a script so the video can't expire in between checking
+        // and referencing it.
+        redis::Script::new(r"
+            local video = redis.call('GET', KEYS[1])
+            if not video then
+                return false
+            end
+            local ttl = redis.call('TTL', video)
+            if ttl < 0 then
+                redis.call('DEL', KEYS[1])
+                return false
+            end
+            ttl = math.max(ttl, tonumber(ARGV[2]))
+            local refs = ARGV[1] .. ':' .. video
+            local info = ARGV[3] .. ':' .. video
+            redis.call('INCR', refs)
+            for _, key in ipairs({video, refs, info, KEYS[1]}) do
+                redis.call('EXPIRE', key, ttl)
+            end
+            return {video, ttl}
+        ")
+            .key(cache_entry_key(cache_key))
+            .arg(REFS_KEY_PREFIX)
+            .arg(self.lifetime)
+            .arg(storage::INFO_KEY_PREFIX)
+            .invoke_async(conn.deref_mut()).await
+    }
+
+    // Cache the freshly rendered video under `cache_key` with a single reference.
+    // This is meant to be called inside the transaction which publishes the video.
+    pub async fn store(
+        &self,
+        conn: &mut RedisConn,
+        cache_key: &str,
+        video_key: &str,
+    ) -> redis::RedisResult<()> {
+        redis::pipe()
+            .set_ex(cache_entry_key(cache_key), video_key, self.lifetime).ignore()
+            .set_ex(refs_key(video_key), 1, self.lifetime).ignore()
+            .query_async(conn.deref_mut()).await
+    }
+
+    pub fn lifetime(&self) -> usize {
+        self.lifetime
+    }
+}
+
+// Remove a reference to the cached video `video_key` which turned out not to
+// be needed. The video is deleted if no other upload refers to it anymore.
+pub async fn release(conn: &mut RedisConn, video_key: &str) -> redis::RedisResult<()> {
+    redis::Script::new(r"
+        if redis.call('EXISTS', KEYS[2]) == 0 or redis.call('DECR', KEYS[2]) <= 0 then
+            redis.call('DEL', KEYS[1], KEYS[2], KEYS[3])
+        end
+    ")
+        .key(video_key)
+        .key(refs_key(video_key))
+        .key(storage::info_key(video_key))
+        .invoke_async(conn.deref_mut()).await
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use crate::RedisConn;
+use crate::routes::RenderTask;
+
+// Workers record how long their renders take, grouped by the render's
+// profile (see `RenderOptions::profile`). The totals are used to estimate
+// how long queued tasks will take to render, based on the duration of
+// the videos they produce.
+
+// Prefix of the redis hashes holding the totals of a profile.
+const STATS_KEY_PREFIX: &str = "render-stats";
+// Seconds it takes to render a second of video if nothing was rendered yet.
+const DEFAULT_SPEED: f64 = 0.5;
+// Seconds a render of unknown length takes if nothing was rendered yet.
+const DEFAULT_RENDER_TIME: f64 = 30.0;
+
+fn stats_key(profile: &str) -> String {
+    format!("{STATS_KEY_PREFIX}:{profile}")
+}
+
+// Add a finished render of `profile` which took `render_secs` seconds
+// and produced a video of `output_secs` seconds to the totals.
+pub async fn record(
+    conn: &mut RedisConn,
+    profile: &str,
+    render_secs: f64,
+    output_secs: f64,
+) -> redis::RedisResult<()> {
+    let key = stats_key(profile);
+    redis::pipe()
+        .atomic()
+        .hincr(&key, "renders", 1).ignore()
+        .cmd("HINCRBYFLOAT").arg(&key).arg("render_secs").arg(render_secs).ignore()
+        .cmd("HINCRBYFLOAT").arg(&key).arg("output_secs").arg(output_secs).ignore()
+        .query_async(conn.deref_mut()).await
+}
+
+// Totals of all renders of a profile.
+#[derive(Debug, Default)]
+struct ProfileStats {
+    renders: f64,
+    render_secs: f64,
+    output_secs: f64,
+}
+
+// Historical render speeds used to estimate render times.
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    profiles: HashMap<String, ProfileStats>,
+}
+
+impl RenderStats {
+    // Load the totals of the given profiles.
+    pub async fn load<'a>(
+        conn: &mut RedisConn,
+        profiles: impl IntoIterator<Item = &'a str>,
+    ) -> redis::RedisResult<Self> {
+        let mut profiles: Vec<&str> = profiles.into_iter().collect();
+        profiles.sort_unstable();
+        profiles.dedup();
+
+        let mut pipe = redis::pipe();
+        for profile in &profiles {
+            pipe.hgetall(stats_key(profile));
+        }
+        let totals: Vec<HashMap<String, f64>> = pipe.query_async(conn.deref_mut()).await?;
+
+        let profiles = profiles.into_iter().zip(totals)
+            .filter(|(_, totals)| !totals.is_empty())
+            .map(|(profile, totals)| {
+                let get = |field: &str| totals.get(field).copied().unwrap_or_default();
+                (profile.to_owned(), ProfileStats {
+                    renders: get("renders"),
+                    render_secs: get("render_secs"),
+                    output_secs: get("output_secs"),
+                })
+            })
+            .collect();
+        Ok(Self { profiles })
+    }
+
+    // Estimate how many seconds rendering `task` takes.
+    pub fn estimate(&self, task: &RenderTask) -> f64 {
+        let stats = self.profiles.get(task.options.profile(task.subtitles.is_some()));
+        let output_secs = task.audio_duration
+            .map(|duration| task.options.output_duration(duration));
+        match (stats, output_secs) {
+            (Some(stats), Some(output_secs)) if stats.output_secs > 0.0 => {
+                output_secs * stats.render_secs / stats.output_secs
+            },
+            (Some(stats), None) if stats.renders > 0.0 => stats.render_secs / stats.renders,
+            (_, Some(output_secs)) => output_secs * DEFAULT_SPEED,
+            (_, None) => DEFAULT_RENDER_TIME,
+        }
+    }
+}
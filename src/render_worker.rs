@@ -1,343 +1,1087 @@
 use std::time::Duration;
 use anyhow::Context;
 use redis::AsyncCommands;
-use std::process::Command;
-use tokio::io::AsyncWriteExt;
-use tokio::fs::File;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use uuid::Uuid;
 use std::ops::DerefMut;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::net::TcpListener;
+use actix_web::web;
 
 use crate::configuration::{Settings, RenderWorkerSettings};
+use crate::capabilities::{FfmpegCapabilities, Features};
 use crate::startup::get_redis_pool;
-use crate::{RedisPool, RENDER_QUEUE_KEY};
-use crate::routes::RenderTask;
-use crate::utils::spawn_blocking_with_tracing;
+use crate::RedisPool;
+use crate::shutdown::Shutdown;
+use crate::utils::unix_now;
+use crate::routes::{RenderTask, RenderOptions, SubtitleMode, SubtitleStyle, SubtitlePosition, Cue, to_srt};
+use crate::storage::{self, CHUNK_SIZE, VideoInfo};
+use crate::render_cache::{self, RenderCache};
+use crate::retention::Retention;
+use crate::render_stats;
+use crate::metrics::{self, RenderOutcome, record_render_outcome};
+use crate::progress_events::{self, ProgressEvent, ProgressTicker};
+use crate::webhooks::{self, Delivery, WebhookEvent, Webhooks};
+use buffer::{TempRoot, TaskDir, FfmpegAssetBuffer, FfmpegBufferName, QuotaExceeded};
+use sandbox::{Sandbox, LimitExceeded, ResourceLimit};
+use health::{WorkerHealth, RENDER_STALL_TIMEOUT};
+use queue::{RenderQueue, QueuedTask, QueueQueryOutcome};
+use registry::WorkerInfo;
+use crate::task_state::{self, TaskStatus, Transition};
 use crate::{RedisConn, REDIS_DISCARD};
 
-const ASSETS_DIR: &str = "tmp_assets";
+mod buffer;
+mod health;
+pub mod queue;
+pub mod registry;
+pub mod sandbox;
 
-pub async fn run_until_stopped(configuration: Settings) -> anyhow::Result<()> {
+pub async fn run_until_stopped(
+    configuration: Settings,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
     let render_config = configuration.render_worker;
     let redis_pool = get_redis_pool(configuration.redis_uri).await?;
+    let webhooks = Webhooks::new(&configuration.webhooks)?;
+
+    // Refuse to start if `ffmpeg` can't render videos at all.
+    let sandbox = Sandbox::new(render_config.sandbox, render_config.limits.clone()).await?;
+    let ffmpeg = Ffmpeg::probe(render_config.ffmpeg_path.clone(), sandbox).await?;
+
+    // Remove buffer files left behind by workers which crashed.
+    let temp_root = TempRoot::new(render_config.temp_root.clone(), render_config.temp_quota);
+    temp_root.sweep(Duration::from_secs(render_config.temp_max_age.into())).await?;
+
+    let address = format!("{}:{}", configuration.application.host, render_config.port);
+    let listener = TcpListener::bind(&address)
+        .context(format!("failed to bind worker health server to {address}"))?;
+    let health = web::Data::new(WorkerHealth::new(render_config.laziness));
+    let health_server = health::run(listener, health.clone(), redis_pool.clone())?;
+
+    let worker_id = Uuid::new_v4();
+    let heartbeat_ttl = render_config.heartbeat_ttl;
+    tracing::info!("Set up render worker {worker_id}; Now entering working loop.");
+    let outcome = tokio::select! {
+        outcome = health_server => outcome.context("worker health server failed"),
+        outcome = heartbeat_loop(&redis_pool, worker_id, heartbeat_ttl, &ffmpeg, &health) => outcome,
+        outcome = webhooks.run(&redis_pool) => outcome.context("webhook delivery failed"),
+        outcome = worker_loop(redis_pool.clone(), render_config, &ffmpeg, &temp_root, &health, worker_id, shutdown) => outcome,
+    };
 
-    // Make sure buffer file directory for assets exists.
-    tokio::fs::create_dir_all(ASSETS_DIR).await?;
+    // A worker which stops with tasks in its processing list is left
+    // in the registry, so its tasks are requeued by the reaper.
+    if outcome.is_ok() {
+        let mut conn = redis_pool.get().await
+            .context("failed to acquire redis connection to deregister worker")?;
+        registry::deregister(&mut conn, worker_id).await?;
+    }
+    outcome
+}
+
+// Keep the worker's heartbeat alive and requeue the tasks of dead workers.
+// The heartbeat is refreshed a few times per TTL, so a single failed
+// refresh doesn't make the worker look dead.
+async fn heartbeat_loop(
+    redis_pool: &RedisPool,
+    worker_id: Uuid,
+    ttl: u16,
+    ffmpeg: &Ffmpeg,
+    health: &WorkerHealth,
+) -> anyhow::Result<()> {
+    let started_at = unix_now();
+    let interval = Duration::from_secs((u64::from(ttl) / 3).max(1));
+    loop {
+        let (current_task, task_started_at) = health.current_task().unzip();
+        let info = WorkerInfo {
+            id: worker_id,
+            started_at,
+            current_task,
+            task_started_at,
+            last_heartbeat: unix_now(),
+            ffmpeg_version: ffmpeg.version.clone(),
+            features: ffmpeg.features,
+        };
+        match redis_pool.get().await {
+            Ok(mut conn) => {
+                if let Err(e) = registry::heartbeat(&mut conn, &info, ttl.into()).await {
+                    tracing::warn!("Failed to send heartbeat: {e:?}");
+                }
+                if let Err(e) = reap_dead_workers(&mut conn).await {
+                    tracing::warn!("Failed to reap dead workers: {e:?}");
+                }
+            },
+            Err(e) => tracing::warn!("Failed to acquire redis connection for heartbeat: {e:?}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
 
-    tracing::info!("Set up render worker; Now entering working loop.");
-    worker_loop(redis_pool, render_config).await
+// Put the tasks of all workers whose heartbeat expired back into the queue.
+async fn reap_dead_workers(conn: &mut RedisConn) -> anyhow::Result<()> {
+    for worker_id in registry::list_workers(conn).await?.dead {
+        let requeued = queue::requeue_abandoned(conn, worker_id).await?;
+        registry::forget(conn, worker_id).await?;
+        tracing::warn!("Render worker {worker_id} is dead; requeued {requeued} of its task(s)");
+    }
+    Ok(())
 }
 
 async fn worker_loop(
     redis_pool: RedisPool,
     render_config: RenderWorkerSettings,
+    ffmpeg: &Ffmpeg,
+    temp_root: &TempRoot,
+    health: &WorkerHealth,
+    worker_id: Uuid,
+    mut shutdown: Shutdown,
 ) -> anyhow::Result<()> {
     let laziness = render_config.laziness.into();
-    let lifetime = render_config.lifetime;
-    loop {
-        let task = match get_next_task(redis_pool.clone()).await {
+    let retention = Retention::new(render_config.lifetime, render_config.max_lifetime);
+    let grace_period = Duration::from_secs(render_config.shutdown_grace_period.into());
+    let queue = RenderQueue::new(worker_id, render_config.max_queue_wait.into());
+    let max_attempts = render_config.max_attempts.max(1);
+
+    // Stop taking new tasks once shutdown is triggered.
+    while !shutdown.is_triggered() {
+        health.touch();
+        let queued = match queue.next_task(&redis_pool).await {
             Ok(QueueQueryOutcome::NewTask(t)) => t,
             Ok(QueueQueryOutcome::EmptyQueue) => {
                 // Wait for queue to fill up.
-                tokio::time::sleep(Duration::from_secs(laziness)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(laziness)) => {},
+                    _ = shutdown.triggered() => {},
+                }
                 continue;
             },
             Err(e) => {
                 tracing::error!("Render queue error: {e:?}");
-                continue;  // try again
+                // Wait and try again.
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
             },
         };
+        let task = &queued.task;
+        let lifetime = task.lifetime(&retention);
+        let cache = RenderCache::new(lifetime);
 
         let mut conn = redis_pool.get().await
             .context("failed to acquire redis connection")?;
 
-        match try_render_task(&mut conn, &task).await {
-            Ok(data) => {
-                // Store finished video in redis and delete its assets.
-                try_save_render(&mut conn, task, &data, lifetime).await?;
+        // Give up on tasks which failed too often, including tasks whose
+        // workers died while rendering them.
+        let attempts = task_state::load(&mut conn, task.target).await
+            .context("failed to load task record")?
+            .map_or(0, |record| record.attempts);
+        if attempts >= max_attempts {
+            tracing::warn!("Giving up on task {0} after {attempts} attempts", task.target);
+            try_fail_task(&mut conn, &queue, &queued, &gave_up_reason(attempts), lifetime).await?;
+            record_render_outcome(RenderOutcome::Failure);
+            progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+            continue;
+        }
+
+        // Tasks this worker can't render are left to a worker which can.
+        // They fail if no running worker can render them.
+        let has_subtitles = task.subtitles.is_some();
+        if let Err(missing) = ffmpeg.features.check(&task.options, has_subtitles) {
+            if registry::supported_by_any(&mut conn, &task.options, has_subtitles).await? {
+                tracing::info!("Leaving task {0} to another worker: {missing}", task.target);
+                queue.requeue(&mut conn, &queued, false, None).await?;
+                // Give the other workers a chance to take it.
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(laziness)) => {},
+                    _ = shutdown.triggered() => {},
+                }
+            } else {
+                tracing::warn!("No render worker can render task {0}: {missing}", task.target);
+                try_fail_task(&mut conn, &queue, &queued, &missing, lifetime).await?;
+                record_render_outcome(RenderOutcome::Failure);
+                progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+            }
+            continue;
+        }
+
+        // The upload might have been deleted since the task was queued.
+        let started = task_state::transition(&mut conn, task.target, &Transition::rendering()).await
+            .context("failed to update task record")?;
+        if !started {
+            tracing::info!("Dropping task {0} of a deleted upload", task.target);
+            try_drop_task(&mut conn, &queue, &queued, None).await?;
+            progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+            continue;
+        }
+
+        // An identical task might have been rendered since this one was queued.
+        if let Some(cache_key) = &task.cache_key {
+            match cache.reference(&mut conn, cache_key).await {
+                Ok(Some((video_key, ttl))) => {
+                    tracing::info!("Resolved task {0} to a cached render", task.target);
+                    try_discard_task(&mut conn, &queue, &queued, &video_key, ttl).await?;
+                    record_render_outcome(RenderOutcome::Cached);
+                    progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+                    continue;
+                },
+                Ok(None) => {},
+                // The task can still be rendered without the cache.
+                Err(e) => tracing::warn!("Failed to look up render cache: {e:?}"),
+            }
+        }
+
+        health.set_current_task(Some(task.target));
+        progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+        // Progress is reported relative to the length of the video, if it's known.
+        let ticker = match task.audio_duration {
+            Some(duration) => redis_pool.get().await
+                .map(|conn| ProgressTicker::new(conn, task.target, task.options.output_duration(duration)))
+                .map_err(|e| tracing::warn!("Failed to acquire redis connection to report progress: {e:?}"))
+                .ok(),
+            None => None,
+        };
+        let video_key = Uuid::new_v4().to_string();
+        let render_start = std::time::Instant::now();
+        let outcome = {
+            let mut render = Box::pin(try_render_task(&mut conn, ffmpeg, temp_root, task, &video_key, ticker, health));
+            tokio::select! {
+                outcome = &mut render => Some(outcome),
+                _ = shutdown.triggered() => {
+                    tracing::info!("Waiting up to {grace_period:?} for render of {0} to finish", task.target);
+                    tokio::time::timeout(grace_period, &mut render).await.ok()
+                },
+            }
+            // Dropping an unfinished render kills `ffmpeg` and removes its task directory.
+        };
+        health.set_current_task(None);
+        // Failed and aborted renders took their time as well.
+        let profile = task.options.profile(task.subtitles.is_some());
+        let render_secs = render_start.elapsed().as_secs_f64();
+        metrics::RENDER_DURATION.with_label_values(&[profile]).observe(render_secs);
+
+        // A task which was requeued while it was rendered belongs to the queue
+        // again, and another worker might be rendering it already. Its outcome
+        // isn't recorded then. Aborted renders are checked once they're cleaned up.
+        if outcome.is_some() && !queue.owns(&mut conn, &queued).await.context("failed to read processing list")? {
+            tracing::warn!("Dropping render of {0}; the task was requeued while it was rendered", task.target);
+            let _: () = conn.del(&video_key).await
+                .context("failed to delete video of requeued task")?;
+            continue;
+        }
+
+        match outcome {
+            Some(Ok(rendered)) => {
+                // Publish finished video and delete its assets.
+                let saved = try_save_render(
+                    &mut conn, &queue, &queued, &video_key, &rendered, &cache
+                ).await?;
+                match saved {
+                    SavedRender::Published => {},
+                    SavedRender::UploadDeleted => {
+                        tracing::info!("Dropping video of {0}; its upload was deleted", task.target);
+                        try_drop_task(&mut conn, &queue, &queued, Some(&video_key)).await?;
+                        progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
+                        continue;
+                    },
+                    SavedRender::TaskLost => {
+                        tracing::warn!("Dropping video of {0}; the task was requeued before it was saved", task.target);
+                        let _: () = conn.del(&video_key).await
+                            .context("failed to delete video of requeued task")?;
+                        continue;
+                    },
+                }
+                record_render_outcome(RenderOutcome::Success);
+                if let Some(duration) = task.audio_duration {
+                    let output_secs = task.options.output_duration(duration);
+                    if let Err(e) = render_stats::record(&mut conn, profile, render_secs, output_secs).await {
+                        tracing::warn!("Failed to record render speed: {e:?}");
+                    }
+                }
             },
-            Err(e) => {
+            Some(Err(e)) if e.downcast_ref::<LimitExceeded>().is_some() => {
+                // Rendering the task again would exceed the limit again.
+                tracing::warn!("Render of {0} failed: {e:?}", task.target);
+                // The outermost context is the `LimitExceeded` error.
+                try_fail_task(&mut conn, &queue, &queued, &e.to_string(), lifetime).await?;
+                record_render_outcome(RenderOutcome::Failure);
+            },
+            Some(Err(e)) if e.downcast_ref::<QuotaExceeded>().is_some() => {
+                // Requeuing the task would only bail on the quota again.
+                tracing::warn!("Render of {0} failed: {e:?}", task.target);
+                // `create_task_dir` adds no context, so its error is the outermost one.
+                try_fail_task(&mut conn, &queue, &queued, &e.to_string(), lifetime).await?;
+                record_render_outcome(RenderOutcome::Failure);
+            },
+            // Rendering fails if the assets are deleted along with the upload.
+            Some(Err(_)) if was_deleted(&mut conn, task).await? => {
+                tracing::info!("Dropping task {0} of a deleted upload", task.target);
+                try_drop_task(&mut conn, &queue, &queued, None).await?;
+            },
+            Some(Err(e)) if attempts + 1 >= max_attempts => {
+                tracing::error!("Render worker error: {e:?}");
+                tracing::warn!("Giving up on task {0} after {max_attempts} attempts", task.target);
+                try_fail_task(&mut conn, &queue, &queued, &gave_up_reason(max_attempts), lifetime).await?;
+                record_render_outcome(RenderOutcome::Failure);
+            },
+            Some(Err(e)) => {
                 tracing::error!("Render worker error: {e:?}");
 
-                // Abort changes to assets.
-                redis::cmd(REDIS_DISCARD)
-                    .query_async(conn.deref_mut()).await
-                    .context("failed to discard redis transaction after render error")?;
-
-                // Queue the task again.
-                // TODO: Store a counter in the task so erroneous tasks are deleted eventually.
-                match task.queue(&mut conn).await {
-                    Ok(_) => {},  // the target ID returned by `queue` is not interesting here.
-                    Err(e) => {
-                        tracing::warn!("failed to re-queue previously failed task; \
-                            deleting task. Re-queue error: {e:?}");
-                    },
+                // Queue the task again. The error itself is only logged,
+                // since the record is shown to uploaders.
+                if let Err(e) = queue.requeue(&mut conn, &queued, false, Some(RENDER_ERROR)).await {
+                    tracing::warn!("failed to re-queue previously failed task; \
+                        the task stays in the worker's processing list. Re-queue error: {e:?}");
                 }
-                
+                record_render_outcome(RenderOutcome::Retry);
+
                 // Wait and try again.
                 tokio::time::sleep(Duration::from_secs(1)).await;
-            }
+            },
+            None => {
+                tracing::warn!("Aborted render of {0} after the shutdown grace period", task.target);
+                // The aborted render might have left the connection in the middle
+                // of a command, so it's closed instead of going back to the pool.
+                let aborted = std::mem::replace(&mut conn, redis_pool.get().await
+                    .context("failed to acquire redis connection to clean up aborted render")?);
+                drop(aborted.into_inner());
+                let _: () = conn.del(&video_key).await
+                    .context("failed to delete partially stored video of aborted render")?;
+                // Put the task at the front of the queue so the next worker picks it up right away.
+                if queue.owns(&mut conn, &queued).await.context("failed to read processing list")? {
+                    queue.requeue(&mut conn, &queued, true, None).await?;
+                }
+                record_render_outcome(RenderOutcome::Retry);
+            },
         }
+        progress_events::publish(&mut conn, task.target, ProgressEvent::State).await;
     }
+
+    tracing::info!("Render worker {worker_id} has stopped taking tasks");
+    Ok(())
+}
+
+// Reason recorded for tasks whose render failed unexpectedly.
+const RENDER_ERROR: &str = "rendering failed unexpectedly";
+
+// Reason recorded for tasks which were given up on after `attempts` attempts.
+fn gave_up_reason(attempts: u32) -> String {
+    format!("rendering failed {attempts} times")
+}
+
+// The `ffmpeg` binary used to render videos, the features
+// it supports and the sandbox it runs in.
+struct Ffmpeg {
+    path: PathBuf,
+    version: String,
+    features: Features,
+    sandbox: Sandbox,
 }
 
-// Save the given render result data in redis and delete its assets.
-// Saving is wrapped in a transaction to ensure the progress key
-// is updated along with the video data in any case where the
-// video data is saved.
+impl Ffmpeg {
+    // Probe the binary at `path` and fail if it's missing a required capability.
+    async fn probe(path: PathBuf, sandbox: Sandbox) -> anyhow::Result<Self> {
+        let capabilities = FfmpegCapabilities::probe(&path).await
+            .context("failed to probe ffmpeg capabilities")?;
+        capabilities.check_required()?;
+        let features = capabilities.features();
+        tracing::info!("Using ffmpeg {} with features {features:?}", capabilities.version);
+        if features != Features::default() {
+            tracing::warn!("ffmpeg {} lacks capabilities for some features; \
+                they are disabled", capabilities.version);
+        }
+        Ok(Self { path, version: capabilities.version, features, sandbox })
+    }
+
+    // Command to run `ffmpeg` in the sandbox of the given task.
+    fn command(&self, task_dir: &TaskDir) -> Command {
+        self.sandbox.command(&self.path, task_dir.path())
+    }
+}
+
+// What became of a rendered video when it was saved.
+enum SavedRender {
+    Published,
+    // The upload was deleted while the video was rendered.
+    UploadDeleted,
+    // The task was requeued while the video was rendered, because the
+    // worker's heartbeat lapsed. It belongs to the queue again.
+    TaskLost,
+}
+
+// Publish the video stored under `video_key` and delete its assets.
+// Publishing is wrapped in a transaction to ensure the task record
+// is updated along with the expiration of the video data in any
+// case where the video data is kept.
 // The assets are deleted because they were only used to render the
-// video once.
+// video once. The video is kept for the lifetime of `cache`. Nothing is published
+// if the upload was deleted or the task was requeued in the meantime.
 async fn try_save_render(
     conn: &mut RedisConn,
-    task: RenderTask,
-    data: &[u8],
-    lifetime_mins: u16,
-) -> anyhow::Result<()> {
-    let video_key = Uuid::new_v4().to_string();
+    queue: &RenderQueue,
+    queued: &QueuedTask,
+    video_key: &str,
+    rendered: &RenderedVideo,
+    cache: &RenderCache,
+) -> anyhow::Result<SavedRender> {
+    let task = &queued.task;
+    // The transaction is aborted if the upload is deleted or the
+    // task is requeued before it finishes.
+    redis::cmd("WATCH").arg(task_state::task_key(task.target)).arg(queue.processing_key())
+        .query_async::<_, ()>(conn.deref_mut()).await
+        .context("failed to watch task record")?;
+    if !queue.owns(conn, queued).await.context("failed to read processing list")? {
+        redis::cmd("UNWATCH").query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to unwatch task record")?;
+        return Ok(SavedRender::TaskLost);
+    }
+    let record = task_state::load(conn, task.target).await
+        .context("failed to load task record")?;
+    let ready = Transition::ready(video_key, Some(&rendered.info), cache.lifetime());
+    let mut publish = redis::pipe();
+    let can_publish = record.is_some_and(|record| {
+        task_state::transition_in(&mut publish, task.target, record.status, &ready)
+    });
+    if !can_publish {
+        redis::cmd("UNWATCH").query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to unwatch task record")?;
+        return Ok(SavedRender::UploadDeleted);
+    }
 
-    redis::cmd("MULTI").query_async(conn.deref_mut()).await
+    redis::cmd("MULTI").query_async::<_, ()>(conn.deref_mut()).await
         .context("failed to start transaction to save render")?;
 
-    // Store video data
-    let _: () = match conn.set(&video_key, data).await {
-        Ok(_r) => _r,  // this passing around is required to satisfy `set`s generics.
-        Err(e) => {
-            redis::cmd(REDIS_DISCARD).query_async(conn.deref_mut()).await
-                .context("failed to abort transaction to save render")?;
-            return Err(anyhow::anyhow!("failed to store video data in redis: {e:?}"));
-        },
-    };
-
     // Set expiration of video data
-    let lifetime_secs: usize = (lifetime_mins * 60).into();
-    let _: () = match conn.expire(&video_key, lifetime_secs).await {
-        Ok(_r) => _r,
+    let _: () = match conn.expire(video_key, cache.lifetime()).await {
+        Ok(_r) => _r,  // this passing around is required to satisfy `expire`s generics.
         Err(e) => {
-            redis::cmd(REDIS_DISCARD).query_async(conn.deref_mut()).await
+            redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
                 .context("failed to abort transaction to save render")?;
             return Err(anyhow::anyhow!("failed set video data expiration in redis: {e:?}"));
         },
     };
 
-    // Store key of video in progress key to access video data again from `GET /load`
-    let _: () = match conn.set(&task.target.to_string(), &video_key).await {
-        Ok(_r) => _r,
-        Err(e) => {
-            redis::cmd(REDIS_DISCARD).query_async(conn.deref_mut()).await
+    // Store the video's metadata and poster next to it.
+    let poster = rendered.poster.as_deref();
+    if let Err(e) = storage::store_info(conn, video_key, &rendered.info, poster, cache.lifetime()).await {
+        redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to abort transaction to save render")?;
+        return Err(anyhow::anyhow!("failed to store video info in redis: {e:?}"));
+    }
+
+    // Record the key of the video in the task record to access the video
+    // data again from `GET /load`.
+    if let Err(e) = publish.query_async::<_, ()>(conn.deref_mut()).await {
+        redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to abort transaction to save render")?;
+        return Err(anyhow::anyhow!("failed to update task record in redis: {e:?}"));
+    }
+
+    // Make the video available to identical tasks. The cache entry and
+    // the video's reference count expire along with the video.
+    if let Some(cache_key) = &task.cache_key {
+        if let Err(e) = cache.store(conn, cache_key, video_key).await {
+            redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
                 .context("failed to abort transaction to save render")?;
-            return Err(anyhow::anyhow!("failed to store video key in redis: {e:?}"));
-        },
-    };
+            return Err(anyhow::anyhow!("failed to cache video in redis: {e:?}"));
+        }
+    }
 
     // Delete image
-    let _: () = match conn.del(&task.image.to_string()).await {
+    let _: () = match conn.del(task.image.to_string()).await {
         Ok(_r) => _r,
         Err(e) => {
-            redis::cmd(REDIS_DISCARD).query_async(conn.deref_mut()).await
+            redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
                 .context("failed to abort transaction to save render")?;
             return Err(anyhow::anyhow!("failed to delete image asset in redis: {e:?}"));
         }
     };
 
     // Delete audio
-    let _: () = match conn.del(&task.audio.to_string()).await {
+    let _: () = match conn.del(task.audio.to_string()).await {
         Ok(_r) => _r,
         Err(e) => {
-            redis::cmd(REDIS_DISCARD).query_async(conn.deref_mut()).await
+            redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
                 .context("failed to abort transaction to save render")?;
             return Err(anyhow::anyhow!("failed to delete audio asset in redis: {e:?}"));
         }
     };
 
-    redis::cmd("EXEC").query_async(conn.deref_mut()).await
+    // Delete subtitles
+    if let Some(subtitles) = task.subtitles {
+        let _: () = match conn.del(subtitles.to_string()).await {
+            Ok(_r) => _r,
+            Err(e) => {
+                redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
+                    .context("failed to abort transaction to save render")?;
+                return Err(anyhow::anyhow!("failed to delete subtitles asset in redis: {e:?}"));
+            }
+        };
+    }
+
+    // Tell the uploader the video is ready.
+    if let Some(url) = &task.callback_url {
+        let mut pipe = redis::pipe();
+        webhooks::enqueue_in(&mut pipe, &Delivery::new(url.clone(), task.target, WebhookEvent::Ready, None));
+        if let Err(e) = pipe.query_async::<_, ()>(conn.deref_mut()).await {
+            redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
+                .context("failed to abort transaction to save render")?;
+            return Err(anyhow::anyhow!("failed to queue webhook in redis: {e:?}"));
+        }
+    }
+
+    // Remove the task from the processing list
+    if let Err(e) = queue.complete(conn, queued).await {
+        redis::cmd(REDIS_DISCARD).query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to abort transaction to save render")?;
+        return Err(anyhow::anyhow!("failed to complete task in redis: {e:?}"));
+    }
+
+    let result: redis::Value = redis::cmd("EXEC").query_async(conn.deref_mut()).await
         .context("failed to finish transaction to save render")?;
+    if result == redis::Value::Nil {
+        let owned = queue.owns(conn, queued).await.context("failed to read processing list")?;
+        return Ok(if owned { SavedRender::UploadDeleted } else { SavedRender::TaskLost });
+    }
 
     tracing::trace!("Successfully updated video in redis {video_key}. \
         Deleted task {task:?} and all its assets");
 
-    Ok(())
+    Ok(SavedRender::Published)
 }
 
-// Try to get the next task from the render task queue. This function
-// will pop the task from the queue. If rendering fails the task has
-// to be pushed to the queue again manually or it is lost.
-async fn get_next_task(
-    redis_pool: RedisPool,
-) -> anyhow::Result<QueueQueryOutcome> {
-    // Acquire own connection to ensure the connection is not inside a
-    // transaction. The `conn.rpop` call is required to return the value
-    // directly, because of this a transaction would break this check.
-    let mut conn = redis_pool.get().await
-        .context("failed to acquire redis connection to check queue")?;
-
-    let task: RenderTask = {
-        // Pop the next task entry from the queue.
-        // Return if the queue is empty to wait for the queue to fill up.
-        let raw_task: String = match conn.rpop(RENDER_QUEUE_KEY, None).await {
-            Err(_) => return Ok(QueueQueryOutcome::EmptyQueue),
-            Ok(raw) => raw,
-        };
+// Whether the upload of `task` was deleted.
+async fn was_deleted(conn: &mut RedisConn, task: &RenderTask) -> anyhow::Result<bool> {
+    let record = task_state::load(conn, task.target).await
+        .context("failed to load task record")?;
+    Ok(record.is_none_or(|record| record.status == TaskStatus::Cancelled))
+}
 
-        serde_json::from_str(&raw_task)
-            // If we return an error here, the task is deleted
-            // because it cannot be queued again. This is OK as a task
-            // which cannot be deserialized cannot ever be used anyways.
-            .context("failed to deserialize task; task deleted")?
-    };
+// Drop a task whose upload was deleted along with its assets and
+// the video rendered for it, if there is one.
+async fn try_drop_task(
+    conn: &mut RedisConn,
+    queue: &RenderQueue,
+    queued: &QueuedTask,
+    video_key: Option<&str>,
+) -> anyhow::Result<()> {
+    let task = &queued.task;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .del(task.image.to_string()).ignore()
+        .del(task.audio.to_string()).ignore();
+    if let Some(subtitles) = task.subtitles {
+        pipe.del(subtitles.to_string()).ignore();
+    }
+    if let Some(video_key) = video_key {
+        pipe.del(video_key).ignore();
+    }
+    queue.complete_in(&mut pipe, queued);
+    pipe.query_async::<_, ()>(conn.deref_mut()).await
+        .context("failed to drop task")
+}
+
+// Delete the assets of a task which doesn't need to be rendered anymore,
+// because its video is the cached video `video_key` which is kept for
+// `ttl` more seconds, and remove it from the processing list.
+async fn try_discard_task(
+    conn: &mut RedisConn,
+    queue: &RenderQueue,
+    queued: &QueuedTask,
+    video_key: &str,
+    ttl: usize,
+) -> anyhow::Result<()> {
+    let task = &queued.task;
+    let info = storage::video_info(conn, video_key).await
+        .context("failed to load info of cached video")?;
+    // The upload might have been deleted in the meantime. Its reference
+    // to the cached video is given back then.
+    let ready = task_state::transition(conn, task.target, &Transition::ready(video_key, info.as_ref(), ttl)).await
+        .context("failed to update task record")?;
+    if !ready {
+        render_cache::release(conn, video_key).await
+            .context("failed to release cached video")?;
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .del(task.image.to_string()).ignore()
+        .del(task.audio.to_string()).ignore();
+    if let Some(url) = task.callback_url.as_ref().filter(|_| ready) {
+        webhooks::enqueue_in(&mut pipe, &Delivery::new(url.clone(), task.target, WebhookEvent::Ready, None));
+    }
+    if let Some(subtitles) = task.subtitles {
+        pipe.del(subtitles.to_string()).ignore();
+    }
+    queue.complete_in(&mut pipe, queued);
+    pipe.query_async::<_, ()>(conn.deref_mut()).await
+        .context("failed to discard task")
+}
 
-    tracing::trace!("Received render task: {task:?}");
+// Give up on a task which can never be rendered and delete its assets.
+// The task's record reports the failure and its `reason` until it expires
+// after the same lifetime a finished video would have had.
+async fn try_fail_task(
+    conn: &mut RedisConn,
+    queue: &RenderQueue,
+    queued: &QueuedTask,
+    reason: &str,
+    lifetime_secs: usize,
+) -> anyhow::Result<()> {
+    let task = &queued.task;
+    // The upload might have been deleted in the meantime.
+    let failed = task_state::transition(conn, task.target, &Transition::failed(reason, lifetime_secs)).await
+        .context("failed to update task record")?;
 
-    Ok(QueueQueryOutcome::NewTask(task))
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .del(task.image.to_string()).ignore()
+        .del(task.audio.to_string()).ignore();
+    if let Some(url) = task.callback_url.as_ref().filter(|_| failed) {
+        let delivery = Delivery::new(url.clone(), task.target, WebhookEvent::Failed, Some(reason.to_owned()));
+        webhooks::enqueue_in(&mut pipe, &delivery);
+    }
+    if let Some(subtitles) = task.subtitles {
+        pipe.del(subtitles.to_string()).ignore();
+    }
+    queue.complete_in(&mut pipe, queued);
+    pipe.query_async::<_, ()>(conn.deref_mut()).await
+        .context("failed to store failed render")
 }
 
-// > I had to use this double-"que" name!
-enum QueueQueryOutcome {
-    NewTask(RenderTask),
-    EmptyQueue,
+// A video which was rendered and stored in redis, but not published yet.
+struct RenderedVideo {
+    info: VideoInfo,
+    // JPEG thumbnail of the video's image.
+    poster: Option<Vec<u8>>,
 }
 
+// Render the given task and store the resulting video in redis under
+// `video_key`. If rendering fails, any partially stored video data is
+// deleted again.
 async fn try_render_task(
     conn: &mut RedisConn,
+    ffmpeg: &Ffmpeg,
+    temp_root: &TempRoot,
     task: &RenderTask,
-) -> anyhow::Result<Vec<u8>> {
-    // Buffer audio data in file.
+    video_key: &str,
+    ticker: Option<ProgressTicker>,
+    health: &WorkerHealth,
+) -> anyhow::Result<RenderedVideo> {
     let audio_data: Vec<u8> = conn.get(task.audio.to_string()).await
         .context("failed to query audio data")?;
+    let image_data: Vec<u8> = conn.get(task.image.to_string()).await
+        .context("failed to query image data")?;
+    // Convert subtitles to an SRT file matching the (trimmed) audio.
+    let srt = match task.subtitles {
+        Some(subtitles_key) => {
+            let raw_cues: Vec<u8> = conn.get(subtitles_key.to_string()).await
+                .context("failed to query subtitles")?;
+            let cues: Vec<Cue> = serde_json::from_slice(&raw_cues)
+                .context("failed to deserialize subtitles")?;
+            let duration = task.audio_duration
+                .map(|total| task.options.output_duration(total))
+                .unwrap_or(f64::MAX);
+            Some((subtitles_key, to_srt(&cues, task.options.trim_start.unwrap_or(0.0), duration)))
+        },
+        None => None,
+    };
+
+    // Buffer all assets in the task's own directory.
+    let size = audio_data.len() + image_data.len() + srt.as_ref().map_or(0, |(_, s)| s.len());
+    // The error is left as it is, so a full temp root fails the task.
+    let task_dir = temp_root.create_task_dir(size as u64).await?;
+
     let mut audio_buf = FfmpegAssetBuffer::new(
-        FfmpegBufferName::new_audio(task.audio)
+        &task_dir, FfmpegBufferName::new_audio(task.audio)
     ).await.context("failed to create audio buffer file")?;
     audio_buf.add_data(&audio_data).await?;
 
-    // Buffer image  data in file.
-    let image_data: Vec<u8> = conn.get(task.image.to_string()).await
-        .context("failed to query image data")?;
     let mut image_buf = FfmpegAssetBuffer::new(
-        FfmpegBufferName::new_image(task.image)
+        &task_dir, FfmpegBufferName::new_image(task.image)
     ).await.context("failed to create image buffer file")?;
     image_buf.add_data(&image_data).await?;
 
+    let subtitles_buf = match srt {
+        Some((subtitles_key, srt)) => {
+            let mut buf = FfmpegAssetBuffer::new(
+                &task_dir, FfmpegBufferName::new_subtitles(subtitles_key)
+            ).await.context("failed to create subtitles buffer file")?;
+            buf.add_data(srt.as_bytes()).await?;
+            Some(buf)
+        },
+        None => None,
+    };
+
     // Render the video
     tracing::trace!("Starting rendering {0}", task.target);
-    let video_data = render_video(
-        image_buf.get_path(),
-        audio_buf.get_path(),
-    ).await?;
+    let args = ffmpeg_args(
+        &image_buf.get_path(),
+        &audio_buf.get_path(),
+        subtitles_buf.as_ref().map(|b| b.get_path()).as_deref(),
+        &task.options,
+        task.audio_duration,
+        ffmpeg.sandbox.threads(),
+    )?;
+    let rendered = render_video(conn, ffmpeg, &task_dir, video_key, args, ticker, health).await;
+    let size = match rendered {
+        Ok(size) => size,
+        Err(e) => {
+            task_dir.remove().await;
+            let _: () = conn.del(video_key).await
+                .context("failed to delete partially stored video")?;
+            return Err(e);
+        },
+    };
     tracing::info!("Finished rendering {0}", task.target);
 
-    Ok(video_data)
+    // The poster is only shown on the download page, so the video
+    // is published without it if rendering the poster fails.
+    let poster = render_poster(ffmpeg, &task_dir, &image_buf.get_path()).await
+        .map_err(|e| tracing::warn!("Failed to render poster of {0}: {e:?}", task.target))
+        .ok();
+    task_dir.remove().await;
+
+    // The video has the size of its image.
+    let resolution = imagesize::blob_size(&image_data).ok();
+    let info = VideoInfo {
+        duration: task.audio_duration.map(|total| task.options.output_duration(total)),
+        width: resolution.as_ref().map(|r| r.width as u32),
+        height: resolution.as_ref().map(|r| r.height as u32),
+        size,
+    };
+    Ok(RenderedVideo { info, poster })
 }
 
-// Render the video using the given files a assets.
-// This function will also delete the files again.
-async fn render_video(
-    image_path: PathBuf,
-    audio_path: PathBuf,
-) -> anyhow::Result<Vec<u8>> {
-    let output = spawn_blocking_with_tracing(move || {
-        let image_path = image_path.to_str()
-            .expect("ffmpeg can't use invalid image path");
-        let audio_path = audio_path.to_str()
-            .expect("ffmpeg can't use invalid audio path");
-
-        // Create a video from the assets.
-        Command::new("ffmpeg")
-            // Loop the  image with a tiny frame rate (1FPS)
-            .args(["-r", "1", "-loop", "1"])
-            // Use the given image and audio files as inputs.
-            .args(["-i", image_path, "-i", audio_path])
-            // Stop the video when the audio stops.
-            .args(["-shortest", "-fflags", "shortest", "-max_interleave_delta", "100M"])
-            // Enable piped MP4.
-            .args(["-movflags", "frag_keyframe+empty_moov"])
-            // Copy audio codec and use libx264 for video.
-            .args(["-acodec", "copy", "-vcodec", "libx264"])
-            // More rendering speedups for still image videos
-            .args(["-tune", "stillimage", "-preset", "ultrafast"])
-            // Save result encoded as MP4 to stdout.
-            .args(["-f", "mp4", "-"])
-            .output()
-    })
-    .await?  // bubble up `JoinError`s
-    .context("failed to spawn video rendering process")?;
-
-    tracing::trace!("render stderr: {0}", String::from_utf8_lossy(&output.stderr));
+// Maximum width (in pixels) of the posters shown before a video is played.
+const POSTER_WIDTH: u32 = 640;
 
+// Render a JPEG thumbnail of the image at `image_path`.
+async fn render_poster(
+    ffmpeg: &Ffmpeg,
+    task_dir: &TaskDir,
+    image_path: &Path,
+) -> anyhow::Result<Vec<u8>> {
+    let output = ffmpeg.command(task_dir)
+        .args(["-hide_banner", "-v", "error", "-i"])
+        .arg(image_path)
+        .args(["-frames:v", "1", "-vf", &format!("scale='min({POSTER_WIDTH},iw)':-2")])
+        .args(["-c:v", "mjpeg", "-f", "image2pipe", "-"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output().await
+        .context("failed to spawn poster rendering process")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "poster rendering process failed ({0}): {1}",
+            output.status, String::from_utf8_lossy(&output.stderr),
+        );
+    }
     Ok(output.stdout)
 }
 
-// Buffering asset data in files so `ffmpeg` can use the data
-// brings the danger of dandling files which will never be used again.
-// This type therefore wrapps creating and deleting such buffer files
-// to avoid ever forgetting to delete any of them.
+// Run the `ffmpeg` command with the given arguments and stream
+// the video it writes to stdout into redis under `video_key`.
+// The progress `ffmpeg` reports is passed on to `ticker`.
+// Returns the size of the video in bytes.
+async fn render_video(
+    conn: &mut RedisConn,
+    ffmpeg: &Ffmpeg,
+    task_dir: &TaskDir,
+    video_key: &str,
+    args: Vec<String>,
+    ticker: Option<ProgressTicker>,
+    health: &WorkerHealth,
+) -> anyhow::Result<u64> {
+    let max_size = ffmpeg.sandbox.max_output_size();
+    let cpu_time_before = sandbox::children_cpu_time();
+    let mut child = ffmpeg.command(task_dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to spawn video rendering process")?;
 
-// new
-// name
-// drop
+    // Collect stderr concurrently so ffmpeg never blocks on a full pipe.
+    // Progress reports are interleaved with the log and not collected.
+    // They are published by a task of their own, so a slow redis never
+    // holds up reading stderr. Only the latest progress is published.
+    let (progress, mut latest_progress) = tokio::sync::watch::channel(0.0);
+    let mut advanced = progress.subscribe();
+    if let Some(mut ticker) = ticker {
+        tokio::spawn(async move {
+            // This ends once stderr is closed and `progress` is dropped.
+            while latest_progress.changed().await.is_ok() {
+                let rendered = *latest_progress.borrow_and_update();
+                ticker.tick(rendered).await;
+            }
+        });
+    }
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_task = tokio::spawn(async move {
+        let mut stderr = BufReader::new(stderr);
+        let mut raw_line = Vec::new();
+        let mut buf = String::new();
+        // The log isn't necessarily valid UTF-8, e.g. if it names files.
+        while stderr.read_until(b'\n', &mut raw_line).await? > 0 {
+            let line = String::from_utf8_lossy(&raw_line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            match parse_progress_line(line) {
+                // Only advancing progress counts, so a render stuck
+                // in the same place is recognized as stalled.
+                Some(("out_time_us", value)) => {
+                    if let Ok(us) = value.parse::<f64>() {
+                        progress.send_if_modified(|rendered| {
+                            let advanced = us / 1_000_000.0 > *rendered;
+                            if advanced {
+                                *rendered = us / 1_000_000.0;
+                            }
+                            advanced
+                        });
+                    }
+                },
+                Some(_) => {},
+                None => {
+                    buf.push_str(line);
+                    buf.push('\n');
+                },
+            }
+            raw_line.clear();
+        }
+        Ok::<_, std::io::Error>(buf)
+    });
 
-// TODO mod
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let store_output = async {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        let mut size = 0;
+        loop {
+            // Fill the chunk completely so only the last one is shorter.
+            let n = (&mut stdout)
+                .take((CHUNK_SIZE - chunk.len()) as u64)
+                .read_to_end(&mut chunk).await
+                .context("failed to read rendered video data")?;
+            if let Some(max) = max_size.filter(|max| size + chunk.len() as u64 > *max) {
+                // Dropping the child kills `ffmpeg`.
+                return Err(anyhow::anyhow!("the rendered video exceeds {max} bytes")
+                    .context(LimitExceeded(ResourceLimit::FileSize)));
+            }
+            if chunk.len() == CHUNK_SIZE || (n == 0 && !chunk.is_empty()) {
+                storage::append_chunk(conn, video_key, &chunk).await
+                    .context("failed to store video data in redis")?;
+                metrics::WRITTEN_BYTES.with_label_values(&["video"]).inc_by(chunk.len() as u64);
+                size += chunk.len() as u64;
+                chunk.clear();
+            }
+            if n == 0 {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(size)
+    };
+    // A render which stops advancing is considered hung and killed.
+    let watchdog = async {
+        loop {
+            match tokio::time::timeout(RENDER_STALL_TIMEOUT, advanced.changed()).await {
+                Ok(Ok(())) => health.touch(),
+                // `ffmpeg` closed stderr, so it's about to exit.
+                Ok(Err(_)) => std::future::pending::<()>().await,
+                Err(_) => return,
+            }
+        }
+    };
+    let size = tokio::select! {
+        size = store_output => size?,
+        // Dropping the child kills `ffmpeg`.
+        () = watchdog => anyhow::bail!("the render made no progress for {RENDER_STALL_TIMEOUT:?}"),
+    };
 
-struct FfmpegAssetBuffer {
-    file: tokio::fs::File,
-    path: PathBuf,
-}
+    let status = child.wait().await
+        .context("failed to wait for video rendering process")?;
+    let stderr = stderr_task.await?.unwrap_or_default();
+    tracing::trace!("render stderr: {stderr}");
 
-impl FfmpegAssetBuffer {
-    // Create a new buffer file from a given name.
-    async fn new(name: FfmpegBufferName)-> anyhow::Result<FfmpegAssetBuffer> {
-        let path = PathBuf::from(format!("{ASSETS_DIR}/{name}"));
-        let file = File::create(&path).await
-            .context("failed to create file")?;
-        Ok(Self { file, path })
+    if !status.success() {
+        let error = anyhow::anyhow!("video rendering process failed ({status}): {stderr}");
+        let cpu_time = sandbox::children_cpu_time().saturating_sub(cpu_time_before);
+        return Err(match sandbox::exceeded_limit(ffmpeg.sandbox.limits(), status, &stderr, cpu_time) {
+            Some(limit) => error.context(LimitExceeded(limit)),
+            None => error,
+        });
     }
+    Ok(size)
+}
+
+// Split a line of the progress `ffmpeg` reports into its key and
+// value. Returns `None` for lines of the regular log.
+fn parse_progress_line(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once('=')?;
+    let is_key = !key.is_empty()
+        && key.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    is_key.then(|| (key, value.trim()))
+}
+
+// Build the `ffmpeg` arguments to render a video from the
+// given image, audio and subtitle files using the task's options.
+fn ffmpeg_args(
+    image_path: &Path,
+    audio_path: &Path,
+    subtitles_path: Option<&Path>,
+    options: &RenderOptions,
+    audio_duration: Option<f64>,
+    threads: u16,
+) -> anyhow::Result<Vec<String>> {
+    let image_path = image_path.to_str()
+        .context("ffmpeg can't use invalid image path")?;
+    let audio_path = audio_path.to_str()
+        .context("ffmpeg can't use invalid audio path")?;
+    let subtitles_path = subtitles_path
+        .map(|p| p.to_str().context("ffmpeg can't use invalid subtitles path"))
+        .transpose()?;
+    let loop_filter = match (options.loop_duration, audio_duration) {
+        (Some(_), Some(total)) => Some(loop_filter(options, options.trimmed_duration(total))),
+        (Some(_), None) => anyhow::bail!("can't loop audio of unknown duration"),
+        _ => None,
+    };
+    let burn_filter = match (subtitles_path, options.subtitle_mode) {
+        (Some(path), SubtitleMode::Burn) => Some(burn_subtitles_filter(path, &options.subtitle_style)),
+        _ => None,
+    };
+    let soft_subtitles = subtitles_path.filter(|_| options.subtitle_mode == SubtitleMode::Soft);
 
-    // Store the given data in the buffer file.
-    async fn add_data(&mut self, data: &[u8]) -> anyhow::Result<()> {
-        self.file
-            .write_all(data).await
-            .context(format!("failed to write data to buffer {}", self.path.display()))
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |a: &[&str]| args.extend(a.iter().map(|s| s.to_string()));
+
+    // Report the progress as `key=value` lines on stderr.
+    push(&["-hide_banner", "-nostats", "-progress", "pipe:2"]);
+    if threads > 0 {
+        push(&["-filter_threads", &threads.to_string()]);
+    }
+    // Loop the  image with a tiny frame rate (1FPS). Burned-in subtitles
+    // need a higher frame rate to appear and disappear on time.
+    let frame_rate = if burn_filter.is_some() { "10" } else { "1" };
+    push(&["-r", frame_rate, "-loop", "1", "-i", image_path]);
+    // Cut the audio input to the requested range.
+    // Seeking on the input is sample accurate when the audio is re-encoded.
+    if let Some(start) = options.trim_start {
+        push(&["-ss", &format!("{start:.3}")]);
+    }
+    if let Some(end) = options.trim_end {
+        // `-t` is relative to the seek position given by `-ss`.
+        let duration = end - options.trim_start.unwrap_or(0.0);
+        push(&["-t", &format!("{duration:.3}")]);
+    }
+    push(&["-i", audio_path]);
+    if let Some(path) = soft_subtitles {
+        push(&["-i", path]);
     }
 
-    // Return the file path of this buffer.
-    fn get_path(&self) -> PathBuf {
-        self.path.clone()
+    // Combine all filters into one graph and select the streams to output.
+    let filters: Vec<&str> = [&burn_filter, &loop_filter].into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    if !filters.is_empty() {
+        push(&["-filter_complex", &filters.join(";")]);
+    }
+    push(&["-map", if burn_filter.is_some() { "[vout]" } else { "0:v" }]);
+    push(&["-map", if loop_filter.is_some() { "[aout]" } else { "1:a" }]);
+    if soft_subtitles.is_some() {
+        // MP4 only supports `mov_text` subtitles.
+        push(&["-map", "2:s", "-c:s", "mov_text"]);
+    } else {
+        // Stop the video when the audio stops. This is left out with a
+        // subtitle track, which may end before the audio.
+        push(&["-shortest", "-fflags", "shortest", "-max_interleave_delta", "100M"]);
     }
-}
 
-impl Drop for FfmpegAssetBuffer {
-    fn drop(&mut self) {
-        let err_msg = format!("failed to remove ffmpeg asset buffer file: {}", self.path.display());
-        // WARNING: This is blocking I/O : (
-        // Sadly there doesn't seem to be a quick method to
-        // use async in `drop`. Maybe come back to this later.
-        std::fs::remove_file(&self.path).expect(&err_msg)
+    // Copying the audio codec can only cut at packet boundaries,
+    // so trimmed or looped audio is re-encoded to cut it precisely.
+    let reencode = options.is_trimmed() || loop_filter.is_some();
+    // Make the output exactly as long as requested.
+    if let (true, Some(total)) = (reencode || soft_subtitles.is_some(), audio_duration) {
+        push(&["-t", &format!("{:.3}", options.output_duration(total))]);
     }
-}
+    // Enable piped MP4.
+    push(&["-movflags", "frag_keyframe+empty_moov"]);
+    // Copy audio codec if possible and use libx264 for video.
+    push(&["-acodec", if reencode { "aac" } else { "copy" }, "-vcodec", "libx264"]);
+    // More rendering speedups for still image videos
+    push(&["-tune", "stillimage", "-preset", "ultrafast"]);
+    if threads > 0 {
+        push(&["-threads", &threads.to_string()]);
+    }
+    // Save result encoded as MP4 to stdout.
+    push(&["-f", "mp4", "-"]);
 
-// This type is used to wrap file names of
-// ffmpeg buffers to further secure their use
-// (e.g. a programmer (me) accidentally passing a file
-// name with the wrong extension to `FfmpegAssetBuffer::new`).
-enum FfmpegBufferName {
-    Image(String),
-    Audio(String),
+    Ok(args)
 }
 
-impl FfmpegBufferName {
-    fn new_audio(audio_key: Uuid) -> Self {
-        Self::Audio(format!("{audio_key}.mp3"))
-        
-    }
-
-    fn new_image(image_key: Uuid) -> Self {
-        Self::Image(format!("{image_key}.jpg"))
-    }
+// Filter rendering the subtitles in the given SRT file into the video.
+fn burn_subtitles_filter(path: &str, style: &SubtitleStyle) -> String {
+    // ASS colors are written as `&HAABBGGRR`.
+    let (r, g, b) = (&style.color[1..3], &style.color[3..5], &style.color[5..7]);
+    // ASS alignments follow the layout of a numpad.
+    let alignment = match style.position {
+        SubtitlePosition::Top => 8,
+        SubtitlePosition::Middle => 5,
+        SubtitlePosition::Bottom => 2,
+    };
+    let path = escape_filter_value(path);
+    format!(
+        "[0:v]subtitles=filename={path}:force_style='FontSize={},PrimaryColour=&H00{b}{g}{r},\
+        Alignment={alignment},BorderStyle=1,Outline=2'[vout]",
+        style.font_size,
+    )
 }
 
-impl std::fmt::Display for FfmpegBufferName {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self  {
-            FfmpegBufferName::Image(name) => write!(f, "{name}"),
-            FfmpegBufferName::Audio(name) => write!(f, "{name}"),
+// Escape `value` to be used as an option value of a filter in a filter graph.
+// Values are unescaped twice, once when the graph is split into filters
+// and once when the filter's options are parsed.
+fn escape_filter_value(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| value.chars().fold(String::new(), |mut escaped, c| {
+        if special.contains(&c) {
+            escaped.push('\\');
         }
+        escaped.push(c);
+        escaped
+    });
+    let option = escape(value, &['\\', '\'', ':']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+// Sample rate the audio is resampled to when looping. A fixed rate
+// is required to express the length of the loop in samples.
+const LOOP_SAMPLE_RATE: u32 = 48000;
+
+// Filter graph to repeat the (trimmed) audio of the given duration indefinitely.
+// With a crossfade the end of the audio is faded into its start, which makes
+// the loop one crossfade shorter than the audio itself:
+//
+//   audio: [head][    middle    ][tail]
+//   loop:  [tail x head][    middle    ]
+//
+// `aloop` keeps the decoded loop in memory while the output is streamed,
+// which is why uploads only loop audio up to `MAX_LOOP_SEGMENT` long.
+fn loop_filter(options: &RenderOptions, segment: f64) -> String {
+    let rate = LOOP_SAMPLE_RATE;
+    match options.loop_crossfade {
+        None => {
+            let size = (segment * f64::from(rate)).round() as u64;
+            format!("[1:a]aresample={rate},aloop=loop=-1:size={size}[aout]")
+        },
+        Some(fade) => {
+            let size = ((segment - fade) * f64::from(rate)).round() as u64;
+            let tail_start = segment - fade;
+            format!(
+                "[1:a]aresample={rate},asplit=3[h][m][t];\
+                [h]atrim=end={fade:.3},asetpts=PTS-STARTPTS[head];\
+                [m]atrim=start={fade:.3}:end={tail_start:.3},asetpts=PTS-STARTPTS[middle];\
+                [t]atrim=start={tail_start:.3},asetpts=PTS-STARTPTS[tail];\
+                [tail][head]acrossfade=d={fade:.3}[seam];\
+                [seam][middle]concat=n=2:v=0:a=1,aloop=loop=-1:size={size}[aout]"
+            )
+        },
     }
 }
@@ -0,0 +1,226 @@
+use anyhow::Context;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+// Buffering asset data in files so `ffmpeg` can use the data
+// brings the danger of dandling files which will never be used again.
+// Every task therefore gets its own directory inside of the temp root
+// which holds all of its buffer files. The directory is removed as a
+// whole once the task is done (or dropped) and directories left behind
+// by crashed workers are swept when a worker starts.
+
+// Directory holding the task directories of the render workers.
+pub struct TempRoot {
+    path: PathBuf,
+    // Maximum number of bytes all buffer files may take up. `None` means unlimited.
+    quota: Option<u64>,
+}
+
+impl TempRoot {
+    // Create a temp root with a quota given in megabytes. A quota of 0 disables the limit.
+    pub fn new(path: PathBuf, quota_mb: u64) -> Self {
+        let quota = (quota_mb > 0).then_some(quota_mb << 20);
+        Self { path, quota }
+    }
+
+    // Make sure the temp root exists and delete everything in it
+    // which is older than `max_age`. Other workers on the same machine
+    // may share the temp root, so newer directories are left alone.
+    pub async fn sweep(&self, max_age: Duration) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.path).await
+            .context(format!("failed to create temp root {}", self.path.display()))?;
+
+        let mut swept = 0;
+        let mut entries = tokio::fs::read_dir(&self.path).await
+            .context("failed to read temp root")?;
+        while let Some(entry) = entries.next_entry().await.context("failed to read temp root")? {
+            let Ok(metadata) = entry.metadata().await else { continue };
+            let age = metadata.modified().ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+            if age < max_age {
+                continue;
+            }
+            let removed = if metadata.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await
+            } else {
+                tokio::fs::remove_file(entry.path()).await
+            };
+            match removed {
+                Ok(()) => swept += 1,
+                Err(e) => tracing::warn!("failed to sweep stale buffer {}: {e}", entry.path().display()),
+            }
+        }
+        if swept > 0 {
+            tracing::info!("Swept {swept} stale buffer(s) from {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    // Create a new directory for the buffer files of a single task. Fails if
+    // storing `size` more bytes would exceed the quota of the temp root.
+    pub async fn create_task_dir(&self, size: u64) -> anyhow::Result<TaskDir> {
+        if let Some(quota) = self.quota {
+            // This is only a best effort check since other workers
+            // might create their buffers at the same time.
+            let used = disk_usage(&self.path).await
+                .context("failed to determine disk usage of temp root")?;
+            if used + size > quota {
+                return Err(QuotaExceeded { size, used, quota }.into());
+            }
+        }
+
+        let path = self.path.join(Uuid::new_v4().to_string());
+        tokio::fs::create_dir(&path).await
+            .context(format!("failed to create task directory {}", path.display()))?;
+        let dir = TaskDir { path, removed: false };
+        // The absolute path is needed to make the directory writable in the render sandbox.
+        let path = tokio::fs::canonicalize(dir.path()).await
+            .context(format!("failed to resolve task directory {}", dir.path().display()))?;
+        Ok(TaskDir { path, ..dir })
+    }
+}
+
+// Error of a task whose assets don't fit into the temp root. It's
+// raised before rendering, so retrying the task won't help it fit.
+#[derive(thiserror::Error, Debug)]
+#[error("buffering {size} bytes would exceed the temp root quota ({used} of {quota} bytes used)")]
+pub struct QuotaExceeded {
+    size: u64,
+    used: u64,
+    quota: u64,
+}
+
+// Total size (in bytes) of all files inside of `path`.
+async fn disk_usage(path: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            // The directory of a finished task may be gone already.
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else { continue };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    Ok(total)
+}
+
+// Directory holding the buffer files of a single task. It's removed
+// along with its content by `remove` or, as a fallback, when dropped.
+pub struct TaskDir {
+    path: PathBuf,
+    removed: bool,
+}
+
+impl TaskDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Remove the directory and all buffer files in it.
+    pub async fn remove(mut self) {
+        self.removed = true;
+        remove_task_dir(&self.path).await;
+    }
+}
+
+impl Drop for TaskDir {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        // Dropping happens e.g. when a render is aborted. There is no way to
+        // await in `drop`, so the directory is removed in the background.
+        // If the runtime is gone already, the next startup sweep removes it.
+        let path = std::mem::take(&mut self.path);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { remove_task_dir(&path).await });
+        }
+    }
+}
+
+async fn remove_task_dir(path: &Path) {
+    match tokio::fs::remove_dir_all(path).await {
+        Ok(()) => {},
+        Err(e) if e.kind() == ErrorKind::NotFound => {},
+        Err(e) => tracing::warn!("failed to remove task directory {}: {e}", path.display()),
+    }
+}
+
+// A file inside of a task directory which buffers asset data for `ffmpeg`.
+pub struct FfmpegAssetBuffer {
+    file: File,
+    path: PathBuf,
+}
+
+impl FfmpegAssetBuffer {
+    // Create a new buffer file from a given name inside of the task directory.
+    pub async fn new(dir: &TaskDir, name: FfmpegBufferName) -> anyhow::Result<FfmpegAssetBuffer> {
+        let path = dir.path().join(name.to_string());
+        let file = File::create(&path).await
+            .context("failed to create file")?;
+        Ok(Self { file, path })
+    }
+
+    // Store the given data in the buffer file.
+    pub async fn add_data(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.file
+            .write_all(data).await
+            .context(format!("failed to write data to buffer {}", self.path.display()))?;
+        self.file
+            .flush().await
+            .context(format!("failed to flush buffer {}", self.path.display()))
+    }
+
+    // Return the file path of this buffer.
+    pub fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+}
+
+// This type is used to wrap file names of
+// ffmpeg buffers to further secure their use
+// (e.g. a programmer (me) accidentally passing a file
+// name with the wrong extension to `FfmpegAssetBuffer::new`).
+pub enum FfmpegBufferName {
+    Image(String),
+    Audio(String),
+    Subtitles(String),
+}
+
+impl FfmpegBufferName {
+    pub fn new_audio(audio_key: Uuid) -> Self {
+        Self::Audio(format!("{audio_key}.mp3"))
+    }
+
+    pub fn new_image(image_key: Uuid) -> Self {
+        Self::Image(format!("{image_key}.jpg"))
+    }
+
+    pub fn new_subtitles(subtitles_key: Uuid) -> Self {
+        Self::Subtitles(format!("{subtitles_key}.srt"))
+    }
+}
+
+impl std::fmt::Display for FfmpegBufferName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self  {
+            FfmpegBufferName::Image(name) => write!(f, "{name}"),
+            FfmpegBufferName::Audio(name) => write!(f, "{name}"),
+            FfmpegBufferName::Subtitles(name) => write!(f, "{name}"),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::dev::Server;
+use serde::Serialize;
+use std::net::TcpListener;
+use std::ops::DerefMut;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
+use uuid::Uuid;
+
+use crate::RedisPool;
+use crate::metrics::{self, RequestMetrics};
+use crate::utils::unix_now;
+
+// Render workers run their own small HTTP server so they can be
+// monitored when running without the API in the same process.
+
+// Amount of time a render may go without advancing. Renders report their
+// progress about twice a second, so a render which doesn't is hung.
+pub const RENDER_STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Shared state the worker loop uses to report on its health.
+pub struct WorkerHealth {
+    // Unix timestamp (in seconds) of the last time the worker loop made
+    // progress, or the render of the current task advanced.
+    last_activity: AtomicU64,
+    // Amount of time (in seconds) without progress after which the idle
+    // worker is considered stalled. Renders use `RENDER_STALL_TIMEOUT`.
+    stall_timeout: u64,
+    // Target ID of the task that is being rendered right now
+    // and the unix timestamp (in seconds) of when rendering started.
+    current_task: Mutex<Option<(Uuid, u64)>>,
+}
+
+impl WorkerHealth {
+    pub fn new(laziness: u16) -> Self {
+        Self {
+            last_activity: AtomicU64::new(unix_now()),
+            stall_timeout: u64::from(laziness) * 3 + 30,
+            current_task: Mutex::new(None),
+        }
+    }
+
+    // Record that the worker loop is still running, or its render advanced.
+    pub fn touch(&self) {
+        self.last_activity.store(unix_now(), Ordering::Relaxed);
+    }
+
+    // Record the task the worker is now rendering, or `None` if it's idle again.
+    pub fn set_current_task(&self, task: Option<Uuid>) {
+        *self.current_task.lock().expect("worker health lock poisoned") = task.map(|t| (t, unix_now()));
+        self.touch();
+    }
+
+    pub fn current_task(&self) -> Option<(Uuid, u64)> {
+        *self.current_task.lock().expect("worker health lock poisoned")
+    }
+
+    fn report(&self) -> HealthReport {
+        let idle_secs = unix_now().saturating_sub(self.last_activity.load(Ordering::Relaxed));
+        let current_task = self.current_task().map(|(task, _)| task);
+        let stall_timeout = match current_task {
+            Some(_) => RENDER_STALL_TIMEOUT.as_secs(),
+            None => self.stall_timeout,
+        };
+        let stalled = idle_secs > stall_timeout;
+        HealthReport {
+            mode: "worker",
+            status: if stalled { "stalled" } else { "ok" },
+            current_task,
+            idle_secs,
+            redis: "ok",
+        }
+    }
+}
+
+// Body of the worker's health check response.
+#[derive(Debug, Serialize)]
+struct HealthReport {
+    mode: &'static str,
+    status: &'static str,
+    current_task: Option<Uuid>,
+    idle_secs: u64,
+    redis: &'static str,
+}
+
+// Report whether the worker loop is making progress and can reach redis.
+// Responds with `503 Service Unavailable` if either is not the case.
+async fn health_check(
+    health: web::Data<WorkerHealth>,
+    redis_pool: web::Data<RedisPool>,
+) -> HttpResponse {
+    let mut report = health.report();
+
+    let redis_ok = match redis_pool.get().await {
+        Ok(mut conn) => redis::cmd("PING")
+            .query_async::<_, String>(conn.deref_mut()).await
+            .is_ok(),
+        Err(_) => false,
+    };
+    if !redis_ok {
+        report.redis = "unavailable";
+    }
+
+    if redis_ok && report.status == "ok" {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+pub fn run(
+    listener: TcpListener,
+    health: web::Data<WorkerHealth>,
+    redis_pool: RedisPool,
+) -> Result<Server, std::io::Error> {
+    let redis_pool = web::Data::new(redis_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(RequestMetrics)
+            .wrap(TracingLogger::default())
+            .route("/health_check", web::get().to(health_check))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .app_data(health.clone())
+            .app_data(redis_pool.clone())
+    })
+    .workers(1)
+    // The server is stopped along with the worker loop.
+    .disable_signals()
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
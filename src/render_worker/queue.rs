@@ -0,0 +1,301 @@
+use anyhow::Context;
+use redis::AsyncCommands;
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::priority::Priority;
+use crate::routes::RenderTask;
+use crate::task_state::{self, task_key, Transition, TRANSITION_LUA};
+use crate::utils::unix_now;
+use crate::{RedisConn, RedisPool};
+
+// Prefix of the redis keys of the lists holding the tasks which are taken
+// from the render queue by a worker. Each worker has its own list.
+// Tasks are moved to this list atomically when they are taken from the
+// queue, so they are never lost if a worker stops while rendering.
+const PROCESSING_KEY_PREFIX: &str = "render-worker-processing";
+
+// Redis key of the processing list of the given worker.
+pub fn processing_key(worker_id: Uuid) -> String {
+    format!("{PROCESSING_KEY_PREFIX}:{worker_id}")
+}
+
+// The render queue consists of one lane per priority. Lanes are drained
+// from the highest to the lowest priority. To keep lower priority tasks
+// from starving, a task which has waited longer than `max_wait` is
+// taken before any other task, regardless of its priority.
+
+// A worker's view of the render queue.
+pub struct RenderQueue {
+    processing_key: String,
+    // Amount of time (in seconds) after which a queued task is overdue.
+    max_wait: u64,
+}
+
+// A task taken from the queue. The raw queue entry is kept
+// to remove the task from the processing list again.
+pub struct QueuedTask {
+    pub task: RenderTask,
+    raw: String,
+}
+
+// > I had to use this double-"que" name!
+pub enum QueueQueryOutcome {
+    NewTask(Box<QueuedTask>),
+    EmptyQueue,
+}
+
+impl RenderQueue {
+    pub fn new(worker_id: Uuid, max_wait: u64) -> Self {
+        Self {
+            processing_key: processing_key(worker_id),
+            max_wait,
+        }
+    }
+
+    // Try to get the next task from the render task queue. This function
+    // moves the task from the queue to the worker's processing list. Once
+    // the task is done, it must be either completed or requeued.
+    pub async fn next_task(
+        &self,
+        redis_pool: &RedisPool,
+    ) -> anyhow::Result<QueueQueryOutcome> {
+        // Acquire own connection to ensure the connection is not inside a
+        // transaction. The `conn.rpoplpush` call is required to return the value
+        // directly, because of this a transaction would break this check.
+        let mut conn = redis_pool.get().await
+            .context("failed to acquire redis connection to check queue")?;
+
+        // Move the next task entry from the queue to the processing list.
+        // Return if the queue is empty to wait for the queue to fill up.
+        let raw = loop {
+            let Some(lane) = self.pick_lane(&mut conn).await? else {
+                return Ok(QueueQueryOutcome::EmptyQueue);
+            };
+            let raw: Option<String> = conn.rpoplpush(lane.queue_key(), &self.processing_key).await
+                .context("failed to take task from the render queue")?;
+            // Another worker might have emptied the lane in the meantime.
+            if let Some(raw) = raw {
+                break raw;
+            }
+        };
+
+        let task: RenderTask = match serde_json::from_str(&raw) {
+            Ok(task) => task,
+            Err(e) => {
+                // The task is deleted because a task which cannot
+                // be deserialized cannot ever be used anyways.
+                let _: () = conn.lrem(&self.processing_key, 1, &raw).await
+                    .context("failed to delete malformed task")?;
+                return Err(anyhow::anyhow!(e).context("failed to deserialize task; task deleted"));
+            },
+        };
+
+        tracing::trace!("Received render task: {task:?}");
+
+        Ok(QueueQueryOutcome::NewTask(Box::new(QueuedTask { task, raw })))
+    }
+
+    // Choose the lane to take the next task from. This is the lane whose
+    // next task is the most overdue, or the highest non-empty lane if no
+    // task is overdue. `None` is returned if all lanes are empty.
+    async fn pick_lane(&self, conn: &mut RedisConn) -> anyhow::Result<Option<Priority>> {
+        // Peek at the next task of every lane. Tasks are taken from the right end.
+        let mut pipe = redis::pipe();
+        for lane in Priority::ALL {
+            pipe.lindex(lane.queue_key(), -1);
+        }
+        let heads: Vec<Option<String>> = pipe.query_async(conn.deref_mut()).await
+            .context("failed to peek into the render queue")?;
+
+        let now = unix_now();
+        let lanes = Priority::ALL.into_iter().zip(heads)
+            .filter_map(|(lane, head)| head.map(|raw| (lane, queued_at(&raw))));
+
+        let mut highest = None;
+        let mut most_overdue: Option<(Priority, u64)> = None;
+        for (lane, queued_at) in lanes {
+            highest.get_or_insert(lane);
+            let Some(queued_at) = queued_at else { continue };
+            let is_overdue = now.saturating_sub(queued_at) > self.max_wait;
+            if is_overdue && most_overdue.is_none_or(|(_, oldest)| queued_at < oldest) {
+                most_overdue = Some((lane, queued_at));
+            }
+        }
+
+        if let Some((lane, _)) = most_overdue {
+            if Some(lane) != highest {
+                tracing::debug!("Taking overdue task from the {} priority lane", lane.as_str());
+            }
+            return Ok(Some(lane));
+        }
+        Ok(highest)
+    }
+
+    // Remove the task from the processing list. This is meant to be called
+    // inside the transaction which publishes the finished video.
+    pub async fn complete(
+        &self,
+        conn: &mut RedisConn,
+        task: &QueuedTask,
+    ) -> redis::RedisResult<()> {
+        conn.lrem(&self.processing_key, 1, &task.raw).await
+    }
+
+    // Whether the task is still in the worker's processing list. The tasks of
+    // a worker whose heartbeat lapsed are requeued, even if it's still alive,
+    // so another worker might be rendering the task by now.
+    pub async fn owns(&self, conn: &mut RedisConn, task: &QueuedTask) -> redis::RedisResult<bool> {
+        let raw_tasks: Vec<String> = conn.lrange(&self.processing_key, 0, -1).await?;
+        Ok(raw_tasks.contains(&task.raw))
+    }
+
+    // Redis key of the worker's processing list.
+    pub fn processing_key(&self) -> &str {
+        &self.processing_key
+    }
+
+    // Add removing the task from the processing list to the given pipeline.
+    pub fn complete_in(&self, pipe: &mut redis::Pipeline, task: &QueuedTask) {
+        pipe.lrem(&self.processing_key, 1, &task.raw).ignore();
+    }
+
+    // Atomically move the task from the processing list back into the queue.
+    // Tasks put at the `front` are the next ones to be rendered, all
+    // others have to wait for the tasks which are already queued. The
+    // task's record is put back into the queued state, recording `error`.
+    // A cancelled task isn't changed and is dropped by the worker taking it.
+    // Nothing is moved if the task isn't in the processing list anymore.
+    pub async fn requeue(
+        &self,
+        conn: &mut RedisConn,
+        task: &QueuedTask,
+        front: bool,
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        // Tasks are taken from the right end of the queue.
+        let script = redis::Script::new(&format!("{TRANSITION_LUA}
+            if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 0 then
+                return 0
+            end
+            if ARGV[2] == '1' then
+                redis.call('RPUSH', KEYS[2], ARGV[1])
+            else
+                redis.call('LPUSH', KEYS[2], ARGV[1])
+            end
+            apply_transition(KEYS[3], 3)
+            return 1
+        "));
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(&self.processing_key)
+            .key(task.task.priority.queue_key())
+            .key(task_key(task.task.target))
+            .arg(&task.raw)
+            .arg(u8::from(front));
+        task_state::transition_args(&mut invocation, &Transition::queued(error));
+        invocation.invoke_async::<_, u8>(conn.deref_mut()).await
+            .context("failed to requeue task")?;
+        Ok(())
+    }
+}
+
+// Move the tasks a dead worker left in its processing list back to the front
+// of the queue. Returns the number of requeued tasks. Multiple workers may
+// reap the same dead worker at once, so every task is only moved if it's
+// still in the processing list.
+pub async fn requeue_abandoned(conn: &mut RedisConn, worker_id: Uuid) -> anyhow::Result<usize> {
+    let processing_key = processing_key(worker_id);
+    let raw_tasks: Vec<String> = conn.lrange(&processing_key, 0, -1).await
+        .context("failed to read processing list of dead worker")?;
+
+    // The record of a moved task is put back into the queued state, too.
+    let script = redis::Script::new(r"
+        if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 1 then
+            redis.call('RPUSH', KEYS[2], ARGV[1])
+            if redis.call('HGET', KEYS[3], 'status') == 'rendering' then
+                redis.call('HSET', KEYS[3], 'status', 'queued', 'updated_at', ARGV[2])
+            end
+            return 1
+        end
+        return 0
+    ");
+    let mut requeued = 0;
+    for raw in raw_tasks {
+        let Ok(task) = serde_json::from_str::<RenderTask>(&raw) else {
+            tracing::warn!("Deleting malformed task of dead worker {worker_id}");
+            let _: () = conn.lrem(&processing_key, 1, &raw).await
+                .context("failed to delete malformed task")?;
+            continue;
+        };
+        // Tasks are taken from the right end of the queue.
+        let moved: u8 = script
+            .key(&processing_key)
+            .key(task.priority.queue_key())
+            .key(task_key(task.target))
+            .arg(&raw)
+            .arg(unix_now())
+            .invoke_async(conn.deref_mut()).await
+            .context("failed to requeue task of dead worker")?;
+        requeued += usize::from(moved);
+    }
+    Ok(requeued)
+}
+
+// Where a task is in the render queue.
+pub enum TaskLocation {
+    // The task waits in the queue behind the tasks in `ahead`.
+    Queued { task: RenderTask, ahead: Vec<RenderTask> },
+    // The task was taken from the queue by the worker `worker_id`.
+    Processing { task: RenderTask, worker_id: Uuid },
+    // The task is neither queued nor being processed by one of the given workers.
+    Unknown,
+}
+
+// Find the task of `target` in the render queue or in the processing lists
+// of `workers`. Tasks in higher priority lanes are considered ahead of the
+// task, even though overdue tasks of lower lanes may be taken before it.
+pub async fn locate(
+    conn: &mut RedisConn,
+    target: Uuid,
+    workers: &[Uuid],
+) -> anyhow::Result<TaskLocation> {
+    let mut pipe = redis::pipe();
+    for lane in Priority::ALL {
+        pipe.lrange(lane.queue_key(), 0, -1);
+    }
+    let lanes: Vec<Vec<String>> = pipe.query_async(conn.deref_mut()).await
+        .context("failed to read the render queue")?;
+
+    let mut ahead = Vec::new();
+    // Tasks are taken from the right end of the queue.
+    for raw in lanes.into_iter().flat_map(|lane| lane.into_iter().rev()) {
+        let Ok(task) = serde_json::from_str::<RenderTask>(&raw) else { continue };
+        if task.target == target {
+            return Ok(TaskLocation::Queued { task, ahead });
+        }
+        ahead.push(task);
+    }
+
+    for &worker_id in workers {
+        let processing: Vec<String> = conn.lrange(processing_key(worker_id), 0, -1).await
+            .context("failed to read processing list")?;
+        let task = processing.iter()
+            .filter_map(|raw| serde_json::from_str::<RenderTask>(raw).ok())
+            .find(|task| task.target == target);
+        if let Some(task) = task {
+            return Ok(TaskLocation::Processing { task, worker_id });
+        }
+    }
+    Ok(TaskLocation::Unknown)
+}
+
+// Read the time at which a raw queue entry was queued.
+// Returns `None` for tasks queued without a timestamp.
+fn queued_at(raw: &str) -> Option<u64> {
+    #[derive(serde::Deserialize)]
+    struct QueuedAt {
+        queued_at: Option<u64>,
+    }
+    serde_json::from_str::<QueuedAt>(raw).ok()?.queued_at
+}
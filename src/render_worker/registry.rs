@@ -0,0 +1,136 @@
+use anyhow::Context;
+use redis::AsyncCommands;
+use serde::{Serialize, Deserialize};
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::capabilities::Features;
+use crate::routes::RenderOptions;
+
+// Every running render worker registers itself in redis so it's possible
+// to tell which tasks are being rendered and which were abandoned.
+// A worker's heartbeat key expires unless the worker keeps refreshing it.
+// Workers whose heartbeat expired are considered dead and the tasks in
+// their processing lists are put back into the queue by the reaper.
+
+// Redis key of the set holding the IDs of all registered workers.
+const WORKERS_KEY: &str = "render-workers";
+// Prefix of the redis keys of the workers' heartbeats.
+const HEARTBEAT_KEY_PREFIX: &str = "render-worker-heartbeat";
+
+// Information a worker publishes with every heartbeat.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkerInfo {
+    pub id: Uuid,
+    // Unix timestamp (in seconds) of when the worker started.
+    pub started_at: u64,
+    // Target ID of the task that is being rendered right now.
+    pub current_task: Option<Uuid>,
+    // Unix timestamp (in seconds) of when rendering the current task started.
+    pub task_started_at: Option<u64>,
+    // Unix timestamp (in seconds) of the worker's last heartbeat.
+    pub last_heartbeat: u64,
+    // Version of the worker's `ffmpeg` binary.
+    #[serde(default)]
+    pub ffmpeg_version: String,
+    // Optional features the worker's `ffmpeg` binary supports.
+    #[serde(default)]
+    pub features: Features,
+}
+
+// All workers in the registry.
+#[derive(Serialize, Debug)]
+pub struct WorkerRegistry {
+    // Workers with a live heartbeat.
+    pub alive: Vec<WorkerInfo>,
+    // Registered workers whose heartbeat expired. Their
+    // tasks are requeued the next time the reaper runs.
+    pub dead: Vec<Uuid>,
+}
+
+fn heartbeat_key(worker_id: Uuid) -> String {
+    format!("{HEARTBEAT_KEY_PREFIX}:{worker_id}")
+}
+
+// Publish the worker's heartbeat, which expires after `ttl` seconds.
+pub async fn heartbeat(conn: &mut RedisConn, info: &WorkerInfo, ttl: usize) -> anyhow::Result<()> {
+    let raw = serde_json::to_string(info)
+        .context("failed to serialize worker info")?;
+    // The heartbeat is set before the worker is added to the set,
+    // so the reaper never sees a registered worker without one.
+    let _: () = conn.set_ex(heartbeat_key(info.id), raw, ttl).await
+        .context("failed to store worker heartbeat")?;
+    let _: () = conn.sadd(WORKERS_KEY, info.id.to_string()).await
+        .context("failed to register worker")?;
+    Ok(())
+}
+
+// Remove the worker from the registry. This is called by workers which stop
+// gracefully, after their processing list has been emptied.
+pub async fn deregister(conn: &mut RedisConn, worker_id: Uuid) -> anyhow::Result<()> {
+    redis::pipe()
+        .atomic()
+        .del(heartbeat_key(worker_id)).ignore()
+        .srem(WORKERS_KEY, worker_id.to_string()).ignore()
+        .query_async::<_, ()>(conn.deref_mut()).await
+        .context("failed to deregister worker")
+}
+
+// Read all registered workers and sort them by whether their heartbeat is alive.
+pub async fn list_workers(conn: &mut RedisConn) -> anyhow::Result<WorkerRegistry> {
+    let mut ids: Vec<Uuid> = conn.smembers::<_, Vec<String>>(WORKERS_KEY).await
+        .context("failed to read worker registry")?
+        .iter()
+        .filter_map(|id| id.parse().ok())
+        .collect();
+    ids.sort();
+
+    let mut registry = WorkerRegistry { alive: Vec::new(), dead: Vec::new() };
+    if ids.is_empty() {
+        return Ok(registry);
+    }
+
+    let keys: Vec<String> = ids.iter().copied().map(heartbeat_key).collect();
+    // `MGET` with a single key would return a single value instead of a list.
+    let heartbeats: Vec<Option<String>> = redis::cmd("MGET").arg(&keys)
+        .query_async(conn.deref_mut()).await
+        .context("failed to read worker heartbeats")?;
+    for (id, heartbeat) in ids.into_iter().zip(heartbeats) {
+        match heartbeat.and_then(|raw| serde_json::from_str(&raw).ok()) {
+            Some(info) => registry.alive.push(info),
+            None => registry.dead.push(id),
+        }
+    }
+    Ok(registry)
+}
+
+// Forget about a dead worker once its tasks have been requeued.
+pub async fn forget(conn: &mut RedisConn, worker_id: Uuid) -> anyhow::Result<()> {
+    let _: () = conn.srem(WORKERS_KEY, worker_id.to_string()).await
+        .context("failed to remove dead worker from registry")?;
+    Ok(())
+}
+
+// Features supported by all workers which are alive. Any worker can take
+// any task, so only these features can be rendered reliably. If no worker
+// is running, all features are assumed to be available.
+pub async fn available_features(conn: &mut RedisConn) -> anyhow::Result<Features> {
+    Ok(list_workers(conn).await?
+        .alive
+        .iter()
+        .map(|worker| worker.features)
+        .fold(Features::default(), Features::intersect))
+}
+
+// Whether any worker which is alive can render a task with the given options.
+pub async fn supported_by_any(
+    conn: &mut RedisConn,
+    options: &RenderOptions,
+    has_subtitles: bool,
+) -> anyhow::Result<bool> {
+    Ok(list_workers(conn).await?
+        .alive
+        .iter()
+        .any(|worker| worker.features.check(options, has_subtitles).is_ok()))
+}
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::configuration::{RenderLimits, SandboxMode};
+
+// Uploaded files are untrusted input to `ffmpeg`. Every render therefore
+// runs with resource limits and a lowered priority. If bubblewrap is
+// installed, `ffmpeg` additionally runs without network access and can
+// only write to the directory of its task.
+
+const BWRAP: &str = "bwrap";
+
+// How `ffmpeg` processes are started.
+pub struct Sandbox {
+    limits: RenderLimits,
+    // Path of the bubblewrap binary if renders are sandboxed.
+    bwrap: Option<PathBuf>,
+}
+
+impl Sandbox {
+    // Set up the sandbox according to `mode`. Fails if a
+    // sandbox is required but not available on this system.
+    pub async fn new(mode: SandboxMode, limits: RenderLimits) -> anyhow::Result<Self> {
+        let bwrap = match mode {
+            SandboxMode::Off => None,
+            SandboxMode::Auto | SandboxMode::Required => {
+                let available = bwrap_works().await;
+                if !available && mode == SandboxMode::Required {
+                    anyhow::bail!("a render sandbox is required, but `{BWRAP}` is not usable");
+                }
+                if !available {
+                    tracing::warn!("`{BWRAP}` is not usable; renders run without a sandbox");
+                }
+                available.then(|| PathBuf::from(BWRAP))
+            },
+        };
+        tracing::info!("Rendering with limits {limits:?}; sandboxed: {}", bwrap.is_some());
+        Ok(Self { limits, bwrap })
+    }
+
+    // Number of threads `ffmpeg` may use. 0 lets `ffmpeg` decide.
+    pub fn threads(&self) -> u16 {
+        self.limits.threads
+    }
+
+    // Largest video (in bytes) a render may output. The file size limit
+    // doesn't apply to the video, since `ffmpeg` writes it to a pipe.
+    pub fn max_output_size(&self) -> Option<u64> {
+        (self.limits.file_size > 0).then_some(self.limits.file_size << 20)
+    }
+
+    pub fn limits(&self) -> &RenderLimits {
+        &self.limits
+    }
+
+    // Build the command to run `ffmpeg` for a task. Only `task_dir` is writable.
+    pub fn command(&self, ffmpeg_path: &Path, task_dir: &Path) -> Command {
+        let mut command = match &self.bwrap {
+            Some(bwrap) => {
+                let task_dir = task_dir.as_os_str();
+                let mut command = Command::new(bwrap);
+                command
+                    .args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"])
+                    .arg("--bind").arg(task_dir).arg(task_dir)
+                    .args(["--unshare-all", "--die-with-parent", "--new-session", "--"])
+                    .arg(ffmpeg_path);
+                command
+            },
+            None => Command::new(ffmpeg_path),
+        };
+        apply_limits(&mut command, &self.limits);
+        command
+    }
+}
+
+// Run the process of `command` with `limits` and a lowered priority. This
+// also applies to `ffprobe`, which reads untrusted uploads as well.
+#[cfg(target_os = "linux")]
+pub fn apply_limits(command: &mut Command, limits: &RenderLimits) {
+    let limits = limits.clone();
+    // SAFETY: The closure runs in the forked child before `exec`
+    // and only calls the async-signal-safe `setrlimit` and `nice`.
+    unsafe {
+        command.pre_exec(move || {
+            let mb = |n: u64| n << 20;
+            // The process gets a few more seconds after the soft CPU limit
+            // (`SIGXCPU`) before it's killed by the hard limit.
+            set_limit(libc::RLIMIT_CPU, limits.cpu_time, limits.cpu_time + 5)?;
+            set_limit(libc::RLIMIT_AS, mb(limits.memory), mb(limits.memory))?;
+            set_limit(libc::RLIMIT_FSIZE, mb(limits.file_size), mb(limits.file_size))?;
+            // `nice` returns the new niceness, which can legitimately be -1.
+            *libc::__errno_location() = 0;
+            if libc::nice(limits.nice.into()) == -1 && *libc::__errno_location() != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_limits(_command: &mut Command, _limits: &RenderLimits) {}
+
+// Set a resource limit of the current process. A limit of 0 is left unlimited.
+#[cfg(target_os = "linux")]
+fn set_limit(resource: libc::__rlimit_resource_t, soft: u64, hard: u64) -> std::io::Result<()> {
+    if soft == 0 {
+        return Ok(());
+    }
+    let limit = libc::rlimit { rlim_cur: soft, rlim_max: hard };
+    // SAFETY: `limit` is a valid `rlimit` for the duration of the call.
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Check whether bubblewrap is installed and allowed to create namespaces.
+async fn bwrap_works() -> bool {
+    Command::new(BWRAP)
+        .args(["--ro-bind", "/", "/", "--unshare-all", "--die-with-parent", "--", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status().await
+        .is_ok_and(|status| status.success())
+}
+
+// Resource limits a render can exceed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceLimit {
+    CpuTime,
+    Memory,
+    FileSize,
+}
+
+impl std::fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResourceLimit::CpuTime => write!(f, "CPU time"),
+            ResourceLimit::Memory => write!(f, "memory"),
+            ResourceLimit::FileSize => write!(f, "file size"),
+        }
+    }
+}
+
+// Error of a render which was stopped because it exceeded a resource limit.
+// Retrying such a render would only exceed the limit again.
+#[derive(thiserror::Error, Debug)]
+#[error("the render exceeded its {0} limit")]
+pub struct LimitExceeded(pub ResourceLimit);
+
+// CPU time used by all child processes of this process which have been
+// waited for, including their own children (e.g. `ffmpeg` inside of `bwrap`).
+// The CPU time of a single render is the difference before and after it.
+#[cfg(target_os = "linux")]
+pub fn children_cpu_time() -> Duration {
+    // SAFETY: `usage` is written by `getrusage` before it's read.
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        if libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) != 0 {
+            return Duration::ZERO;
+        }
+        usage
+    };
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn children_cpu_time() -> Duration {
+    Duration::ZERO
+}
+
+// Find out whether `ffmpeg` stopped because it exceeded one of its `limits`,
+// after using `cpu_time`. Limits which are disabled are never exceeded, and
+// the CPU limit is only blamed if the render actually used up its CPU time,
+// since `SIGKILL` is also sent by e.g. the kernel's OOM killer.
+pub fn exceeded_limit(
+    limits: &RenderLimits,
+    status: ExitStatus,
+    stderr: &str,
+    cpu_time: Duration,
+) -> Option<ResourceLimit> {
+    // Failed allocations are reported by `ffmpeg` itself. Without a memory
+    // limit, they mean the system ran out of memory, which may pass.
+    let out_of_memory = stderr.contains("Cannot allocate memory") || stderr.contains("Out of memory");
+    if limits.memory > 0 && out_of_memory {
+        return Some(ResourceLimit::Memory);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // Bubblewrap reports the signal that killed its child as exit code 128 + signal.
+        let signal = status.signal()
+            .or_else(|| status.code().filter(|c| *c > 128).map(|c| c - 128));
+        // CPU time is accounted in ticks, so it may fall just short of the limit.
+        let used_cpu_time = limits.cpu_time > 0
+            && cpu_time + Duration::from_secs(1) >= Duration::from_secs(limits.cpu_time);
+        match signal {
+            // The hard CPU limit is enforced with `SIGKILL`.
+            Some(libc::SIGXCPU | libc::SIGKILL) if used_cpu_time => return Some(ResourceLimit::CpuTime),
+            Some(libc::SIGXFSZ) if limits.file_size > 0 => return Some(ResourceLimit::FileSize),
+            _ => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (status, cpu_time);
+    None
+}
@@ -0,0 +1,97 @@
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::render_cache::REFS_KEY_PREFIX;
+use crate::storage::INFO_KEY_PREFIX;
+use crate::task_state::{task_key, TaskStatus, GRACE_PERIOD};
+use crate::utils::unix_now;
+
+// Uploaders choose how long their video is kept once it's rendered, up to
+// a configured maximum. Uploads which don't choose keep their video for the
+// default lifetime. The lifetime of a live video can be extended later on,
+// again up to the maximum counted from the time of the extension.
+
+// Lifetimes (in seconds) finished videos can be kept for.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    default: usize,
+    max: usize,
+}
+
+impl Retention {
+    pub fn new(default_mins: u16, max_mins: u32) -> Self {
+        let default = usize::from(default_mins) * 60;
+        // The default lifetime is always allowed.
+        let max = (max_mins as usize * 60).max(default);
+        Self { default, max }
+    }
+
+    pub fn default_lifetime(&self) -> usize {
+        self.default
+    }
+
+    pub fn max_lifetime(&self) -> usize {
+        self.max
+    }
+
+    // Lifetime (in seconds) of a video which is kept for `minutes`.
+    // Fails with a message for the user if that's not allowed.
+    pub fn lifetime(&self, minutes: u32) -> Result<usize, String> {
+        let secs = minutes as usize * 60;
+        if minutes == 0 || secs > self.max {
+            return Err(format!(
+                "the lifetime must be between 1 and {} minutes", self.max / 60
+            ));
+        }
+        Ok(secs)
+    }
+
+    // Parse a lifetime (in minutes) entered by a user.
+    pub fn parse(&self, value: &str) -> Result<usize, String> {
+        let minutes = value.trim().parse()
+            .map_err(|_| format!("lifetime `{value}` is not a whole number of minutes"))?;
+        self.lifetime(minutes)
+    }
+}
+
+// Keep the finished video of `target` for at least `lifetime` more seconds.
+// Videos are never kept for a shorter time than they already are. Returns
+// the remaining lifetime of the video, or `None` if there is no live video.
+pub async fn extend(
+    conn: &mut RedisConn,
+    target: Uuid,
+    lifetime: usize,
+) -> redis::RedisResult<Option<usize>> {
+    // The video, its metadata and its reference count share a TTL, see
+    // `render_cache`. The task record outlives them by the grace period.
+    // This is a script so they can't diverge.
+    redis::Script::new(r"
+        local task = redis.call('HMGET', KEYS[1], 'status', 'video')
+        if task[1] ~= ARGV[1] or not task[2] then
+            return false
+        end
+        local video = task[2]
+        local ttl = redis.call('TTL', video)
+        if ttl < 0 then
+            return false
+        end
+        ttl = math.max(ttl, tonumber(ARGV[4]))
+        local refs = ARGV[2] .. ':' .. video
+        local info = ARGV[3] .. ':' .. video
+        for _, key in ipairs({video, refs, info}) do
+            redis.call('EXPIRE', key, ttl)
+        end
+        redis.call('HSET', KEYS[1], 'expires_at', tonumber(ARGV[5]) + ttl)
+        redis.call('EXPIRE', KEYS[1], ttl + tonumber(ARGV[6]))
+        return ttl
+    ")
+        .key(task_key(target))
+        .arg(TaskStatus::Ready.as_str())
+        .arg(REFS_KEY_PREFIX)
+        .arg(INFO_KEY_PREFIX)
+        .arg(lifetime)
+        .arg(unix_now())
+        .arg(GRACE_PERIOD)
+        .invoke_async(conn.deref_mut()).await
+}
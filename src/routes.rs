@@ -1,7 +1,24 @@
+mod admin;
+mod api;
+mod archive;
 mod errors;
+mod events;
 mod health_check;
 mod save_file;
+// The module shares its name with the `load_file` endpoint it defines.
+// The endpoint is still re-exported as a value below.
+#[allow(hidden_glob_reexports)]
 mod load_file;
+#[allow(hidden_glob_reexports)]
+mod delete_file;
+#[allow(hidden_glob_reexports)]
+mod extend_file;
+pub use admin::*;
+pub use api::*;
+pub use archive::*;
+pub use events::*;
 pub use health_check::*;
 pub use save_file::*;
 pub use load_file::*;
+pub use delete_file::*;
+pub use extend_file::*;
@@ -0,0 +1,102 @@
+use actix_web::{web, get, HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use secrecy::{Secret, ExposeSecret};
+use uuid::Uuid;
+
+use crate::RedisPool;
+use crate::render_worker::registry;
+use crate::webhooks;
+use crate::utils::derive_error_chain_fmt;
+
+// Token required to use the admin API. `None` disables the admin API.
+pub struct AdminToken(pub Option<Secret<String>>);
+
+impl AdminToken {
+    // Check the request's `Authorization: Bearer <token>` header.
+    pub fn authorize(&self, req: &HttpRequest) -> Result<(), AdminError> {
+        let Some(expected) = &self.0 else {
+            return Err(AdminError::Disabled);
+        };
+        let given = req.headers().get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AdminError::Unauthorized)?;
+        if constant_time_eq(given.as_bytes(), expected.expose_secret().as_bytes()) {
+            Ok(())
+        } else {
+            Err(AdminError::Unauthorized)
+        }
+    }
+}
+
+// Compare two byte strings without leaking where they differ through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// List all registered render workers, their current tasks and
+// the workers whose heartbeat expired.
+#[get("/admin/workers")]
+pub async fn list_workers(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    redis_pool: web::Data<RedisPool>,
+) -> Result<HttpResponse, AdminError> {
+    admin_token.authorize(&req)?;
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    let workers = registry::list_workers(&mut conn).await?;
+    Ok(HttpResponse::Ok().json(workers))
+}
+
+// List the webhook delivery attempts recorded for an upload.
+#[get("/admin/webhooks/{progressId}")]
+pub async fn list_webhook_attempts(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    redis_pool: web::Data<RedisPool>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, AdminError> {
+    admin_token.authorize(&req)?;
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    let attempts = webhooks::attempts(&mut conn, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(attempts))
+}
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    /// No admin token is configured.
+    #[error("The admin API is disabled")]
+    Disabled,
+    /// The request didn't carry the admin token.
+    #[error("Missing or invalid admin token")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+derive_error_chain_fmt!(AdminError);
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::Disabled => StatusCode::NOT_FOUND,
+            AdminError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::Disabled => HttpResponse::NotFound().finish(),
+            AdminError::Unauthorized => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            // Internal errors are opaque to the client.
+            AdminError::UnexpectedError(_) => HttpResponse::InternalServerError()
+                .body("Database query error"),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use actix_web::web;
+
+mod errors;
+mod jobs;
+mod keys;
+mod openapi;
+
+pub use errors::ApiError;
+pub use jobs::*;
+pub use keys::*;
+pub use openapi::*;
+
+// Version 1 of the JSON API. It offers the same as the HTML pages: creating
+// render jobs (uploads), following their state, downloading their videos and
+// deleting them. All errors are JSON objects of the form
+// `{"error": {"code": "...", "message": "..."}}`. The API is described by
+// the OpenAPI document served at `GET /api/v1/openapi.json`, and all other
+// endpoints require an API key, see `api_keys`.
+pub fn api_v1() -> actix_web::Scope {
+    web::scope("/api/v1")
+        // Malformed paths and queries are answered with typed errors, too.
+        .app_data(web::PathConfig::default()
+            .error_handler(|e, _| ApiError::InvalidRequest(e.to_string()).into()))
+        .app_data(web::QueryConfig::default()
+            .error_handler(|e, _| ApiError::InvalidRequest(e.to_string()).into()))
+        .service(create_job)
+        .service(list_jobs)
+        .service(get_job)
+        .service(load_job_output)
+        .service(delete_job)
+        .service(create_batch)
+        .service(key_usage)
+        .service(openapi_document)
+}
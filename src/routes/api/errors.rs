@@ -0,0 +1,134 @@
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use serde::Serialize;
+
+use crate::utils::derive_error_chain_fmt;
+use crate::routes::{LoadFileError, SaveFileError};
+
+// Error returned by the endpoints of the JSON API. The `code`
+// of each error is stable, its message is meant for humans.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    /// The request is malformed or misses something.
+    #[error("{0}")]
+    InvalidRequest(String),
+    /// The request didn't carry a valid API key.
+    #[error("Missing or invalid API key")]
+    InvalidApiKey,
+    /// The request didn't carry the job's delete token.
+    #[error("Missing or invalid delete token")]
+    Unauthorized,
+    /// There is no such job.
+    #[error("{0}")]
+    NotFound(String),
+    /// The job was deleted or its video has expired.
+    #[error("{0}")]
+    Gone(String),
+    /// The uploaded assets exceed the limit of the API key.
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// An uploaded file has an unsupported type.
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    /// The options or assets of a job can't be rendered.
+    #[error("{0}")]
+    Unprocessable(String),
+    /// The API key used up one of its quotas.
+    #[error("{0}")]
+    QuotaExceeded(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+derive_error_chain_fmt!(ApiError);
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidApiKey => "invalid_api_key",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Gone(_) => "gone",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::QuotaExceeded(_) => "quota_exceeded",
+            ApiError::UnexpectedError(_) => "internal",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    error: ErrorBody<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidApiKey | ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // Internal errors are opaque to the client.
+        let message = match self {
+            ApiError::UnexpectedError(_) => "Internal server error".to_owned(),
+            _ => self.to_string(),
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::Unauthorized = self {
+            response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(ErrorResponse { error: ErrorBody { code: self.code(), message } })
+    }
+}
+
+impl From<SaveFileError> for ApiError {
+    fn from(e: SaveFileError) -> Self {
+        match e {
+            SaveFileError::UnexpectedMime(_) => ApiError::UnsupportedMediaType(e.to_string()),
+            SaveFileError::TooLarge(_) => ApiError::PayloadTooLarge(e.to_string()),
+            SaveFileError::MissingMime
+            | SaveFileError::MissingFile(_)
+            | SaveFileError::ReceiveError(_) => ApiError::InvalidRequest(e.to_string()),
+            SaveFileError::InvalidOption(_)
+            | SaveFileError::InvalidSubtitles(_)
+            | SaveFileError::UnreadableAudio => ApiError::Unprocessable(e.to_string()),
+            SaveFileError::QueryError(_)
+            | SaveFileError::WebError(_) => ApiError::UnexpectedError(anyhow::anyhow!("{e:?}")),
+        }
+    }
+}
+
+impl From<LoadFileError> for ApiError {
+    fn from(e: LoadFileError) -> Self {
+        match e {
+            LoadFileError::ResourceError(id) => {
+                ApiError::NotFound(format!("Job {id} has no video to download"))
+            },
+            LoadFileError::Gone(id) => {
+                ApiError::Gone(format!("The video of job {id} has been deleted or has expired"))
+            },
+            LoadFileError::InvalidLink
+            | LoadFileError::QueryError(_)
+            | LoadFileError::WebError(_) => ApiError::UnexpectedError(anyhow::anyhow!("{e:?}")),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use actix_web::{web, get, post, delete, HttpRequest, HttpResponse};
+use actix_web::http::header::LOCATION;
+use actix_multipart::Multipart;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::{RedisConn, RedisPool};
+use crate::api_keys::{self, Reservation};
+use crate::archive;
+use crate::priority::Priority;
+use crate::storage::VideoInfo;
+use crate::download_link::DownloadLinks;
+use crate::ffprobe::Ffprobe;
+use crate::progress_events::{self, ProgressEvent};
+use crate::purge::purge;
+use crate::retention::Retention;
+use crate::task_state::{self, TaskRecord, TaskStatus};
+use crate::utils::e500;
+use crate::routes::{create_upload, delete_token};
+use crate::routes::archive::NewBatch;
+use crate::routes::load_file::{finished_video, video_progress, video_response, QueueStatus};
+use super::ApiError;
+use super::keys::authenticate;
+
+// A render job (an upload) as returned by the API. Only the parts of
+// its task record which concern the client are sent.
+#[derive(Debug, Serialize)]
+pub(crate) struct Job {
+    id: Uuid,
+    status: TaskStatus,
+    created_at: u64,
+    updated_at: u64,
+    started_at: Option<u64>,
+    finished_at: Option<u64>,
+    attempts: u32,
+    // Why rendering failed, once the job failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    priority: Priority,
+    filename: Option<String>,
+    output: Option<VideoInfo>,
+    expires_at: Option<u64>,
+    // Where the task is in the queue, while it's pending.
+    queue: Option<QueueStatus>,
+    links: JobLinks,
+    // Token to delete the job. Only sent when the job is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobLinks {
+    #[serde(rename = "self")]
+    this: String,
+    // Download page of the job.
+    page: String,
+    // The finished video, once the job is ready.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+impl Job {
+    fn new(id: Uuid, task: TaskRecord, queue: Option<QueueStatus>) -> Self {
+        let output = (task.status == TaskStatus::Ready)
+            .then(|| format!("/api/v1/jobs/{id}/output"));
+        // Queued tasks keep the error of a failed attempt, which is no failure.
+        let reason = (task.status == TaskStatus::Failed)
+            .then_some(task.last_error)
+            .flatten();
+        Self {
+            id,
+            status: task.status,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            started_at: task.started_at,
+            finished_at: task.finished_at,
+            attempts: task.attempts,
+            reason,
+            priority: task.priority,
+            filename: task.filename,
+            output: task.output,
+            expires_at: task.expires_at,
+            queue,
+            links: JobLinks {
+                this: format!("/api/v1/jobs/{id}"),
+                page: format!("/done/{id}"),
+                output,
+            },
+            delete_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JobList {
+    jobs: Vec<Job>,
+    // Continues the listing after the last job, if there might be more jobs.
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListJobsParams {
+    // Only list the jobs of this batch.
+    batch: Option<Uuid>,
+    // Maximum number of jobs to list.
+    limit: Option<usize>,
+    // `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+// Number of jobs listed at once, unless the client asks for fewer or more.
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 100;
+
+// Look up the job of `target`, if there is one and it was created with
+// the API key `owner`.
+async fn load_job(
+    redis_pool: &RedisPool,
+    download_links: &DownloadLinks,
+    owner: Uuid,
+    target: Uuid,
+) -> Result<Option<Job>, ApiError> {
+    let mut conn = redis_pool.get().await.map_err(e500).map_err(unexpected)?;
+    let progress = video_progress(&mut conn, download_links, target).await
+        .map_err(unexpected)?;
+    Ok(progress.into_parts()
+        .filter(|(task, _)| task.api_key == Some(owner))
+        .map(|(task, queue)| Job::new(target, task, queue)))
+}
+
+// Make sure the job of `target` was created with the API key `owner`.
+// Jobs of other keys, and uploads made through the form, are not found.
+async fn check_owner(conn: &mut RedisConn, owner: Uuid, target: Uuid) -> Result<(), ApiError> {
+    let record = task_state::load(conn, target).await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    match record {
+        Some(record) if record.api_key == Some(owner) => Ok(()),
+        _ => Err(job_not_found(target)),
+    }
+}
+
+fn unexpected(e: actix_web::Error) -> ApiError {
+    ApiError::UnexpectedError(anyhow::anyhow!("{e:?}"))
+}
+
+fn job_not_found(target: Uuid) -> ApiError {
+    ApiError::NotFound(format!("There is no job {target}"))
+}
+
+// POST endpoint to create a render job. It takes the same multipart form
+// as `POST /save`; the options can also be sent as a JSON object in the
+// `options` part. Responds with the job and the token to delete it.
+// The job counts against the quotas of the request's API key.
+#[post("/jobs")]
+pub async fn create_job(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    retention: web::Data<Retention>,
+    download_links: web::Data<DownloadLinks>,
+    ffprobe: web::Data<Ffprobe>,
+    payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let target = Uuid::new_v4();
+    let mut conn = redis_pool.get().await.map_err(e500).map_err(unexpected)?;
+    let reservation = api_keys::reserve(&mut conn, &key, target).await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let day = match reservation {
+        Reservation::Reserved { day } => day,
+        Reservation::DailyLimitReached => return Err(ApiError::QuotaExceeded(format!(
+            "this API key may create {} jobs per day",
+            key.quotas.renders_per_day.unwrap_or_default(),
+        ))),
+        Reservation::ConcurrencyLimitReached => return Err(ApiError::QuotaExceeded(format!(
+            "this API key may have {} pending jobs at once",
+            key.quotas.max_concurrent_jobs.unwrap_or_default(),
+        ))),
+    };
+
+    match create_upload(&redis_pool, &retention, &download_links, &ffprobe, payload, target, Some(&key)).await {
+        // The job exists now, so failing to count its bytes mustn't fail
+        // the request. A client retrying it would create the job twice.
+        Ok(upload_size) => {
+            if let Err(e) = api_keys::record_upload(&mut conn, key.id, upload_size).await {
+                tracing::warn!("Failed to record the upload of job {target} for API key {}: {e:?}", key.id);
+            }
+        },
+        Err(e) => {
+            if let Err(e) = api_keys::release(&mut conn, key.id, target, day).await {
+                tracing::warn!("Failed to release job {target} of API key {}: {e:?}", key.id);
+            }
+            return Err(e.into());
+        },
+    }
+    drop(conn);
+    tracing::info!("Created job {target} with API key {}", key.id);
+
+    let mut job = load_job(&redis_pool, &download_links, key.id, target).await?
+        .ok_or_else(|| job_not_found(target))?;
+    job.delete_token = Some(download_links.delete_token(target));
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, job.links.this.clone()))
+        .json(job))
+}
+
+// POST endpoint to start a batch of jobs. Jobs join it by sending its
+// `batch` and `batch_token` when they are created.
+#[post("/batches")]
+pub async fn create_batch(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+) -> Result<HttpResponse, ApiError> {
+    authenticate(&req, &redis_pool).await?;
+    Ok(HttpResponse::Created().json(NewBatch::start(&download_links)))
+}
+
+// GET endpoint to list the jobs created with the request's API key, newest
+// first. They can be narrowed down to the jobs of a batch.
+#[get("/jobs")]
+pub async fn list_jobs(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    params: web::Query<ListJobsParams>,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidRequest(format!("`limit` must be between 1 and {MAX_PAGE_SIZE}")));
+    }
+    let mut cursor = match &params.cursor {
+        Some(cursor) => Some(cursor.parse::<u64>()
+            .map_err(|_| ApiError::InvalidRequest("`cursor` is not a cursor of this listing".to_owned()))?),
+        None => None,
+    };
+    let mut conn = redis_pool.get().await.map_err(e500).map_err(unexpected)?;
+    let batch = match params.batch {
+        Some(batch) => Some(archive::batch_uploads(&mut conn, batch).await
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?
+            .into_iter()
+            .collect::<HashSet<_>>()),
+        None => None,
+    };
+
+    // The key's jobs are walked page by page until enough of them are listed.
+    let mut jobs = Vec::with_capacity(limit);
+    loop {
+        let page = api_keys::jobs(&mut conn, key.id, cursor, limit).await
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+        let exhausted = page.len() < limit;
+        for (target, created) in page {
+            cursor = Some(created);
+            if batch.as_ref().is_some_and(|batch| !batch.contains(&target)) {
+                continue;
+            }
+            // Jobs whose records are gone are left out.
+            if let Some(job) = load_job(&redis_pool, &download_links, key.id, target).await? {
+                jobs.push(job);
+            }
+            if jobs.len() == limit {
+                break;
+            }
+        }
+        if jobs.len() == limit || exhausted {
+            break;
+        }
+    }
+    let next_cursor = (jobs.len() == limit).then(|| cursor.map(|c| c.to_string())).flatten();
+    Ok(HttpResponse::Ok().json(JobList { jobs, next_cursor }))
+}
+
+// GET endpoint for the state of a job.
+#[get("/jobs/{jobId}")]
+pub async fn get_job(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let target = path.into_inner();
+    let job = load_job(&redis_pool, &download_links, key.id, target).await?
+        .ok_or_else(|| job_not_found(target))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+// GET endpoint to download the video of a finished job. Byte ranges
+// are supported like by the signed download links.
+#[get("/jobs/{jobId}/output")]
+pub async fn load_job_output(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let target = path.into_inner();
+    let mut conn = redis_pool.get().await.map_err(e500).map_err(unexpected)?;
+    check_owner(&mut conn, key.id, target).await?;
+    let (video_key, filename) = finished_video(&mut conn, target).await?;
+    Ok(video_response(&req, &redis_pool, &mut conn, target, video_key, &filename).await?)
+}
+
+// DELETE endpoint to cancel a job and delete its assets and video. The
+// job's delete token is sent as `Authorization: Bearer <token>`.
+#[delete("/jobs/{jobId}")]
+pub async fn delete_job(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let target = path.into_inner();
+    let mut conn = redis_pool.get().await.map_err(e500).map_err(unexpected)?;
+    check_owner(&mut conn, key.id, target).await?;
+    let authorized = delete_token(&req)
+        .is_some_and(|token| download_links.verify_delete_token(target, &token));
+    if !authorized {
+        return Err(ApiError::Unauthorized);
+    }
+
+    if !purge(&mut conn, target).await? {
+        return Err(job_not_found(target));
+    }
+    tracing::info!("Deleted upload {target}");
+    progress_events::publish(&mut conn, target, ProgressEvent::State).await;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,57 @@
+use actix_web::{web, get, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::RedisPool;
+use crate::api_keys::{self, ApiKey, Usage};
+use super::ApiError;
+
+// Header carrying the API key of a request.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+// Number of days whose usage is returned if the request doesn't say.
+const DEFAULT_USAGE_DAYS: u64 = 7;
+// Largest number of days whose usage can be requested.
+const MAX_USAGE_DAYS: u64 = 31;
+
+// Look up the API key sent with `req`.
+pub(crate) async fn authenticate(req: &HttpRequest, redis_pool: &RedisPool) -> Result<ApiKey, ApiError> {
+    let token = req.headers().get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(ApiError::InvalidApiKey)?;
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    api_keys::authenticate(&mut conn, token.trim()).await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to look up API key"))?
+        .ok_or(ApiError::InvalidApiKey)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageParams {
+    days: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct UsageResponse {
+    key: ApiKey,
+    usage: Usage,
+}
+
+// GET endpoint for the quotas and usage of the API key of the request.
+#[get("/usage")]
+pub async fn key_usage(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    params: web::Query<UsageParams>,
+) -> Result<HttpResponse, ApiError> {
+    let key = authenticate(&req, &redis_pool).await?;
+    let days = params.days.unwrap_or(DEFAULT_USAGE_DAYS);
+    if days > MAX_USAGE_DAYS {
+        return Err(ApiError::InvalidRequest(
+            format!("the usage of at most {MAX_USAGE_DAYS} days is kept")
+        ));
+    }
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    let usage = api_keys::usage(&mut conn, key.id, days).await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to load API key usage"))?;
+    Ok(HttpResponse::Ok().json(UsageResponse { key, usage }))
+}
@@ -0,0 +1,13 @@
+use actix_web::{get, HttpResponse};
+use actix_web::http::header::CONTENT_TYPE;
+
+// OpenAPI description of version 1 of the API.
+pub const OPENAPI_DOCUMENT: &str = include_str!("openapi.json");
+
+// GET endpoint for the OpenAPI description of the API.
+#[get("/openapi.json")]
+pub async fn openapi_document() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "application/json"))
+        .body(OPENAPI_DOCUMENT)
+}
@@ -0,0 +1,172 @@
+use actix_web::{web, get, post, HttpResponse, ResponseError};
+use actix_web::body::SizedStream;
+use actix_web::http::StatusCode;
+use actix_web::http::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::RedisPool;
+use crate::archive::{self, ArchiveEntry};
+use crate::download_link::DownloadLinks;
+use crate::storage;
+use crate::task_state::{self, TaskStatus};
+use crate::utils::{derive_error_chain_fmt, e500};
+use crate::routes::errors::RedisQueryError;
+use super::load_file::{attachment, download_name};
+
+// Largest number of videos in a single archive.
+const MAX_ARCHIVE_VIDEOS: usize = 100;
+
+// Videos to put into an archive: either a comma separated
+// list of progress IDs or the ID of a batch of uploads.
+#[derive(Debug, Deserialize)]
+pub struct ArchiveParams {
+    ids: Option<String>,
+    batch: Option<Uuid>,
+}
+
+enum Videos {
+    Listed(Vec<Uuid>),
+    Batch(Uuid),
+}
+
+// A new batch of uploads and the token uploads need to join it.
+#[derive(Debug, Serialize)]
+pub(crate) struct NewBatch {
+    batch: Uuid,
+    batch_token: String,
+}
+
+impl NewBatch {
+    pub(crate) fn start(download_links: &DownloadLinks) -> Self {
+        let batch = Uuid::new_v4();
+        Self { batch, batch_token: download_links.batch_token(batch) }
+    }
+}
+
+// POST endpoint to start a batch of uploads. Uploads join it by sending
+// its `batch` and `batch_token`. Batches only exist through their uploads,
+// so nothing is stored yet.
+#[post("/batches")]
+pub async fn start_batch(download_links: web::Data<DownloadLinks>) -> HttpResponse {
+    HttpResponse::Created().json(NewBatch::start(&download_links))
+}
+
+// GET endpoint to download several finished videos as one ZIP archive.
+// Uploads which aren't ready (anymore) are left out. The archive is
+// streamed from redis chunk by chunk.
+#[get("/archive")]
+pub async fn load_archive(
+    redis_pool: web::Data<RedisPool>,
+    params: web::Query<ArchiveParams>,
+) -> Result<HttpResponse, ArchiveError> {
+    let videos = match (&params.ids, params.batch) {
+        (Some(ids), None) => Videos::Listed(parse_ids(ids)?),
+        (None, Some(batch)) => Videos::Batch(batch),
+        _ => return Err(ArchiveError::InvalidRequest(
+            "name either the `ids` or the `batch` of the videos".to_owned()
+        )),
+    };
+    let mut conn = redis_pool.get().await.map_err(e500)?;
+
+    let (targets, archive_name) = match videos {
+        Videos::Listed(ids) => (ids, "backdrop.zip".to_owned()),
+        Videos::Batch(batch) => {
+            let targets = archive::batch_uploads(&mut conn, batch).await
+                .map_err(RedisQueryError)?;
+            (targets, format!("backdrop-{batch}.zip"))
+        },
+    };
+    if targets.len() > MAX_ARCHIVE_VIDEOS {
+        return Err(ArchiveError::InvalidRequest(
+            format!("an archive can hold at most {MAX_ARCHIVE_VIDEOS} videos")
+        ));
+    }
+
+    let mut entries: Vec<ArchiveEntry> = Vec::with_capacity(targets.len());
+    for target in targets {
+        let record = task_state::load(&mut conn, target).await
+            .map_err(RedisQueryError)?;
+        let Some(record) = record.filter(|r| r.status == TaskStatus::Ready) else {
+            tracing::debug!("Leaving upload {target} out of the archive; it isn't ready");
+            continue;
+        };
+        let Some(video) = record.video else { continue };
+        // The video might have expired already.
+        let Some(size) = storage::video_size(&mut conn, &video).await.map_err(RedisQueryError)? else {
+            continue;
+        };
+        let taken: Vec<String> = entries.iter().map(|e| e.name.clone()).collect();
+        entries.push(ArchiveEntry {
+            name: archive::unique_name(&download_name(record.filename), &taken),
+            video,
+            size,
+            modified: record.finished_at.unwrap_or(record.updated_at),
+        });
+    }
+    if entries.is_empty() {
+        return Err(ArchiveError::ResourceError);
+    }
+    let size = archive::archive_size(&entries).ok_or_else(|| ArchiveError::InvalidRequest(
+        "the videos are too large for one archive".to_owned()
+    ))?;
+
+    drop(conn);
+    let stream = archive::stream_archive(redis_pool.get_ref().clone(), entries);
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "application/zip"))
+        .insert_header(attachment(&archive_name))
+        .body(SizedStream::new(size, stream)))
+}
+
+// Parse a comma separated list of progress IDs. Repeated IDs are ignored.
+fn parse_ids(ids: &str) -> Result<Vec<Uuid>, ArchiveError> {
+    let mut targets = Vec::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        let target = Uuid::parse_str(id)
+            .map_err(|_| ArchiveError::InvalidRequest(format!("`{id}` is not a progress ID")))?;
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    Ok(targets)
+}
+
+// Error returned by `load_archive` endpoint.
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Invalid archive request: {0}")]
+    InvalidRequest(String),
+    #[error("None of the requested videos is available")]
+    ResourceError,
+    #[error(transparent)]
+    QueryError(#[from] RedisQueryError),
+    #[error(transparent)]
+    WebError(#[from] actix_web::Error),
+}
+
+derive_error_chain_fmt!(ArchiveError);
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ArchiveError::ResourceError => StatusCode::NOT_FOUND,
+            ArchiveError::QueryError(e) => e.status_code(),
+            ArchiveError::WebError(e) => {
+                e.as_response_error().status_code()
+            },
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ArchiveError::InvalidRequest(_) => HttpResponse::BadRequest()
+                .body(self.to_string()),
+            ArchiveError::ResourceError => HttpResponse::NotFound()
+                .body(self.to_string()),
+            ArchiveError::QueryError(e) => e.error_response(),
+            ArchiveError::WebError(e) => e.error_response(),
+        }
+    }
+}
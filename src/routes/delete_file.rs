@@ -0,0 +1,101 @@
+use actix_web::{web, delete, HttpRequest, HttpResponse, ResponseError};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use uuid::Uuid;
+
+use crate::RedisPool;
+use crate::download_link::DownloadLinks;
+use crate::progress_events::{self, ProgressEvent};
+use crate::purge::purge;
+use crate::utils::derive_error_chain_fmt;
+
+// Name of the cookie holding the delete token of an upload.
+const DELETE_TOKEN_COOKIE: &str = "delete-token";
+
+// Cookie handing the delete token of an upload to its uploader. The cookie
+// is only sent to the upload's download page, which deletes it.
+pub fn delete_token_cookie(target: Uuid, token: String) -> Cookie<'static> {
+    Cookie::build(DELETE_TOKEN_COOKIE, token)
+        .path(format!("/done/{target}"))
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+// Read the delete token sent with `req`, either as
+// `Authorization: Bearer <token>` or in the delete token cookie.
+pub fn delete_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_owned);
+    bearer.or_else(|| req.cookie(DELETE_TOKEN_COOKIE).map(|c| c.value().to_owned()))
+}
+
+// Delete an upload right away: its video, its assets and its progress key.
+// Only the uploader, who holds the upload's delete token, may do so.
+#[delete("/done/{progressId}")]
+pub async fn delete_file(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, DeleteFileError> {
+    let target = path.into_inner();
+    let authorized = delete_token(&req)
+        .is_some_and(|token| download_links.verify_delete_token(target, &token));
+    if !authorized {
+        return Err(DeleteFileError::Unauthorized);
+    }
+
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    if !purge(&mut conn, target).await? {
+        return Err(DeleteFileError::ResourceError(target));
+    }
+    tracing::info!("Deleted upload {target}");
+    progress_events::publish(&mut conn, target, ProgressEvent::State).await;
+
+    // The token is of no use anymore.
+    let mut cookie = delete_token_cookie(target, String::new());
+    cookie.make_removal();
+    Ok(HttpResponse::NoContent().cookie(cookie).finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum DeleteFileError {
+    /// The request didn't carry the upload's delete token.
+    #[error("Missing or invalid delete token")]
+    Unauthorized,
+    /// There is no upload with the given ID (anymore).
+    #[error("Requested unavailable resource: id: {0}")]
+    ResourceError(Uuid),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+derive_error_chain_fmt!(DeleteFileError);
+
+impl ResponseError for DeleteFileError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DeleteFileError::Unauthorized => StatusCode::UNAUTHORIZED,
+            DeleteFileError::ResourceError(_) => StatusCode::NOT_FOUND,
+            DeleteFileError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            DeleteFileError::Unauthorized => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            DeleteFileError::ResourceError(_) => HttpResponse::NotFound()
+                .body("The requested resouce is not available"),
+            // Internal errors are opaque to the client.
+            DeleteFileError::UnexpectedError(_) => HttpResponse::InternalServerError()
+                .body("Database query error"),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use actix_web::{web, get, HttpResponse};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::web::Bytes;
+use anyhow::Context;
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::RedisPool;
+use crate::download_link::DownloadLinks;
+use crate::progress_events::{ProgressEvent, ProgressEvents};
+use super::load_file::{video_progress, ProgressResponse};
+
+// Interval in which the state is sent even if no change was published.
+// This keeps the queue position of a pending upload up to date and
+// stops proxies from closing an idle connection.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+// Server-sent events reporting the progress of an upload. A `state` event
+// with the same content as `GET /done/ready` is sent right away and again
+// whenever the state changes. While the video is rendered, `progress` events
+// carry the fraction which is done. The stream ends once the state is final.
+#[get("/done/events/{progressId}")]
+pub async fn progress_events(
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    progress_events: web::Data<ProgressEvents>,
+    path: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    let target = path.into_inner();
+    // Subscribe before reading the state, so no change goes unnoticed.
+    let events = progress_events.subscribe(target);
+    let refresh = stream::unfold((), |()| async {
+        tokio::time::sleep(REFRESH_INTERVAL).await;
+        Some((ProgressEvent::State, ()))
+    });
+    let updates = stream::once(async { ProgressEvent::State })
+        .chain(stream::select(events, refresh))
+        .boxed_local();
+
+    // The state is `None` once the final state was sent.
+    let body = stream::unfold(Some(updates), move |updates| {
+        let redis_pool = redis_pool.clone();
+        let download_links = download_links.clone();
+        async move {
+            let mut updates = updates?;
+            let event = updates.next().await?;
+            match encode_event(&redis_pool, &download_links, target, event).await {
+                Ok((message, is_final)) => Some((Ok(message), (!is_final).then_some(updates))),
+                Err(e) => {
+                    tracing::error!("Failed to send progress of {target}: {e:?}");
+                    Some((Err(e), None))
+                },
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(body))
+}
+
+// Encode `event` as a message of the event stream. Returns whether
+// the message carries the final state of the upload.
+async fn encode_event(
+    redis_pool: &RedisPool,
+    download_links: &DownloadLinks,
+    target: Uuid,
+    event: ProgressEvent,
+) -> anyhow::Result<(Bytes, bool)> {
+    match event {
+        ProgressEvent::Progress { .. } => Ok((message("progress", &event)?, false)),
+        ProgressEvent::State => {
+            let mut conn = redis_pool.get().await
+                .context("failed to acquire redis connection")?;
+            let progress = video_progress(&mut conn, download_links, target).await
+                .map_err(|e| anyhow::anyhow!("failed to look up progress: {e}"))?;
+            let is_final = progress.is_final();
+            Ok((message("state", &ProgressResponse::from(progress))?, is_final))
+        },
+    }
+}
+
+// A server-sent event named `event` carrying `data` as JSON.
+fn message(event: &str, data: &impl Serialize) -> serde_json::Result<Bytes> {
+    let data = serde_json::to_string(data)?;
+    Ok(Bytes::from(format!("event: {event}\ndata: {data}\n\n")))
+}
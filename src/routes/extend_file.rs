@@ -0,0 +1,136 @@
+use actix_web::{web, post, HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{RedisPool, RedisConn};
+use crate::api_keys;
+use crate::download_link::DownloadLinks;
+use crate::progress_events::{self, ProgressEvent};
+use crate::retention::{self, Retention};
+use crate::routes::delete_token;
+use crate::task_state;
+use crate::utils::{derive_error_chain_fmt, unix_now};
+
+// Parameters of a lifetime extension.
+#[derive(Debug, Deserialize)]
+pub struct ExtendParams {
+    // Minutes the video is kept from now on. Defaults to the longest allowed lifetime.
+    lifetime: Option<u32>,
+}
+
+// Response to a lifetime extension.
+#[derive(Debug, Serialize)]
+struct ExtendResponse {
+    // Seconds until the video is deleted.
+    expires_in: u64,
+    // Signed links which stay valid for the extended lifetime.
+    download_url: String,
+    poster_url: String,
+}
+
+// Keep a finished video longer than the lifetime chosen on upload. Like
+// deleting it, this requires the upload's delete token. Videos are never
+// deleted earlier because of an extension. Uploads made with an API key
+// are kept no longer than the key allows.
+#[post("/done/{progressId}/extend")]
+pub async fn extend_file(
+    req: HttpRequest,
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    retention: web::Data<Retention>,
+    path: web::Path<Uuid>,
+    params: web::Query<ExtendParams>,
+) -> Result<HttpResponse, ExtendFileError> {
+    let target = path.into_inner();
+    let authorized = delete_token(&req)
+        .is_some_and(|token| download_links.verify_delete_token(target, &token));
+    if !authorized {
+        return Err(ExtendFileError::Unauthorized);
+    }
+    let mut lifetime = match params.lifetime {
+        Some(minutes) => retention.lifetime(minutes).map_err(ExtendFileError::InvalidLifetime)?,
+        None => retention.max_lifetime(),
+    };
+
+    let mut conn = redis_pool.get().await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to acquire redis connection"))?;
+    if let Some(max) = max_lifetime(&mut conn, target).await? {
+        if params.lifetime.is_some() && lifetime > max {
+            return Err(ExtendFileError::InvalidLifetime(
+                format!("the lifetime of this upload is at most {} minutes", max / 60)
+            ));
+        }
+        lifetime = lifetime.min(max);
+    }
+    let expires_in = retention::extend(&mut conn, target, lifetime).await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to extend video lifetime"))?
+        .ok_or(ExtendFileError::ResourceError(target))?;
+    tracing::info!("Extended lifetime of {target} to {expires_in} seconds");
+    // Other pages of the upload show the new lifetime.
+    progress_events::publish(&mut conn, target, ProgressEvent::State).await;
+
+    let expires = unix_now() + expires_in as u64;
+    Ok(HttpResponse::Ok().json(ExtendResponse {
+        expires_in: expires_in as u64,
+        download_url: download_links.url(target, expires),
+        poster_url: download_links.poster_url(target, expires),
+    }))
+}
+
+// Longest lifetime (in seconds) allowed by the API key the upload of
+// `target` was made with, if it was made with one which limits it.
+async fn max_lifetime(conn: &mut RedisConn, target: Uuid) -> anyhow::Result<Option<usize>> {
+    let record = task_state::load(conn, target).await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to load task record"))?;
+    let Some(key_id) = record.and_then(|r| r.api_key) else {
+        return Ok(None);
+    };
+    let key = api_keys::load(conn, key_id).await
+        .map_err(|e| anyhow::anyhow!(e).context("failed to load API key"))?;
+    Ok(key.and_then(|k| k.quotas.max_lifetime))
+}
+
+#[derive(thiserror::Error)]
+pub enum ExtendFileError {
+    /// The request didn't carry the upload's delete token.
+    #[error("Missing or invalid delete token")]
+    Unauthorized,
+    /// The requested lifetime exceeds the allowed range.
+    #[error("Invalid lifetime: {0}")]
+    InvalidLifetime(String),
+    /// There is no finished video with the given ID (anymore).
+    #[error("Requested unavailable resource: id: {0}")]
+    ResourceError(Uuid),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+derive_error_chain_fmt!(ExtendFileError);
+
+impl ResponseError for ExtendFileError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ExtendFileError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ExtendFileError::InvalidLifetime(_) => StatusCode::BAD_REQUEST,
+            ExtendFileError::ResourceError(_) => StatusCode::NOT_FOUND,
+            ExtendFileError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ExtendFileError::Unauthorized => HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            ExtendFileError::InvalidLifetime(_) => HttpResponse::BadRequest()
+                .body(self.to_string()),
+            ExtendFileError::ResourceError(_) => HttpResponse::NotFound()
+                .body("The requested resouce is not available"),
+            // Internal errors are opaque to the client.
+            ExtendFileError::UnexpectedError(_) => HttpResponse::InternalServerError()
+                .body("Database query error"),
+        }
+    }
+}
@@ -1,5 +1,37 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::ops::DerefMut;
 
-pub async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().finish()
-}
\ No newline at end of file
+use crate::configuration::RunMode;
+use crate::RedisPool;
+
+// Body of the API's health check response.
+#[derive(Debug, Serialize)]
+struct HealthReport {
+    mode: &'static str,
+    redis: &'static str,
+}
+
+// Report the mode the process runs in and whether the API can reach redis.
+// Responds with `503 Service Unavailable` if redis can't be reached.
+pub async fn health_check(
+    run_mode: web::Data<RunMode>,
+    redis_pool: web::Data<RedisPool>,
+) -> HttpResponse {
+    let redis_ok = match redis_pool.get().await {
+        Ok(mut conn) => redis::cmd("PING")
+            .query_async::<_, String>(conn.deref_mut()).await
+            .is_ok(),
+        Err(_) => false,
+    };
+    let report = HealthReport {
+        mode: run_mode.as_str(),
+        redis: if redis_ok { "ok" } else { "unavailable" },
+    };
+
+    if redis_ok {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
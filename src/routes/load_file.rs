@@ -1,38 +1,204 @@
 use actix_web::{web, get, HttpResponse, HttpRequest, ResponseError, Responder};
 use actix_web::http::StatusCode;
-use actix_web::http::header::{ContentDisposition, CONTENT_TYPE};
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    Charset, ContentDisposition, ContentRange, ContentRangeSpec, DispositionParam,
+    DispositionType, ExtendedValue, Range, ACCEPT_RANGES, CONTENT_TYPE, RANGE,
+};
 use tera::{Tera, Context};
 use redis::AsyncCommands;
 use uuid::Uuid;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::utils::{e500, derive_error_chain_fmt};
+use crate::storage::{self, VideoInfo};
+use crate::download_link::DownloadLinks;
+use crate::retention::Retention;
+use crate::render_stats::RenderStats;
+use crate::render_worker::{registry, queue::{self, TaskLocation}};
+use crate::utils::unix_now;
 use crate::routes::errors::{TeraError, RedisQueryError};
-use crate::{RedisPool, PENDING, GONE, READY, REDIS_TTL_EXPIRED};
+use crate::routes::{DEFAULT_NAME, delete_token};
+use crate::task_state::{self, TaskRecord, TaskStatus, Transition};
+use crate::{RedisPool, RedisConn, PENDING, GONE, READY, FAILED, REDIS_TTL_EXPIRED};
 
-// The name of a rendered file
-const FILE_NAME: &str = "backdrop.mp4";
+// Parameters of a signed download link.
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    // Unix timestamp (in seconds) after which the link is invalid.
+    expires: u64,
+    // Signature over the progress ID and `expires`.
+    signature: String,
+}
 
-// GET endpoint to download any pending file from redis.
-// The `GET /done/ready` endpoint will return a vaild key to the
-// video data for a given process ID, once a video is done rendering.
-#[get("/load/{videoKey}")]
+// GET endpoint to download a finished video from redis.
+// The `GET /done/ready` endpoint will return a signed link to this endpoint
+// for a given process ID, once a video is done rendering.
+// Single byte ranges (`Range: bytes=...`) are supported, so players can
+// seek and interrupted downloads can be resumed. The video is streamed
+// from redis chunk by chunk.
+#[get("/load/{progressId}")]
 pub async fn load_file(
+    req: HttpRequest,
     redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
     path: web::Path<Uuid>,
+    params: web::Query<DownloadParams>,
+) -> Result<HttpResponse, LoadFileError> {
+    let target = path.into_inner();
+    if !download_links.verify(target, params.expires, &params.signature) {
+        return Err(LoadFileError::InvalidLink);
+    }
+    let mut conn = redis_pool.get().await.map_err(e500)?;
+    let (video_key, filename) = finished_video(&mut conn, target).await?;
+    video_response(&req, &redis_pool, &mut conn, target, video_key, &filename).await
+}
+
+// Respond to `req` with the video of `target` stored under `video_key`,
+// which is downloaded as `filename`. The video is streamed with
+// connections from `redis_pool`, so `conn` isn't held while downloading.
+pub(crate) async fn video_response(
+    req: &HttpRequest,
+    redis_pool: &RedisPool,
+    conn: &mut RedisConn,
+    target: Uuid,
+    video_key: String,
+    filename: &str,
 ) -> Result<HttpResponse, LoadFileError> {
-    let mut conn = redis_pool.get().await.map_err(|e| e500(e))?;
-    let video_key = path.into_inner().to_string();
+    let size = storage::video_size(conn, &video_key).await
+        .map_err(e500)?;
+    let Some(size) = size else {
+        return Err(LoadFileError::ResourceError(target.to_string()));
+    };
 
-    let data: Vec<u8> = conn.get(&video_key).await
-        .map_err(|e| e500(e))?;  // opaque error to make it harder to use
-                                 // this endpoint for random queries.
-                                 // TODO: Add auth to this endpoint.
+    let (mut response, start, end) = match requested_range(req, size) {
+        // An empty video has no bytes to stream.
+        RequestedRange::Full if size == 0 => {
+            return Ok(HttpResponse::Ok()
+                .insert_header((CONTENT_TYPE, "video/mp4"))
+                .insert_header(attachment(filename))
+                .insert_header((ACCEPT_RANGES, "bytes"))
+                .finish());
+        },
+        RequestedRange::Full => (HttpResponse::Ok(), 0, size - 1),
+        RequestedRange::Partial(start, end) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(size),
+            }));
+            (response, start, end)
+        },
+        RequestedRange::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                }))
+                .finish());
+        },
+    };
 
-    Ok(HttpResponse::Ok()
+    let stream = storage::stream_video(redis_pool.clone(), video_key, start, end);
+    Ok(response
         .insert_header((CONTENT_TYPE, "video/mp4"))
-        .insert_header(ContentDisposition::attachment(FILE_NAME))
-        .body(data))
+        .insert_header(attachment(filename))
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .body(SizedStream::new(end - start + 1, stream)))
+}
+
+// GET endpoint to load the poster image of a finished video. It's
+// accessed through the same signed links as the video itself.
+#[get("/poster/{progressId}")]
+pub async fn load_poster(
+    redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
+    path: web::Path<Uuid>,
+    params: web::Query<DownloadParams>,
+) -> Result<HttpResponse, LoadFileError> {
+    let target = path.into_inner();
+    if !download_links.verify(target, params.expires, &params.signature) {
+        return Err(LoadFileError::InvalidLink);
+    }
+    let mut conn = redis_pool.get().await.map_err(e500)?;
+    let (video_key, _) = finished_video(&mut conn, target).await?;
+
+    let poster = storage::video_poster(&mut conn, &video_key).await
+        .map_err(e500)?;
+    let Some(poster) = poster else {
+        return Err(LoadFileError::ResourceError(target.to_string()));
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "image/jpeg"))
+        .body(poster))
+}
+
+// Return the key of the finished video of `target` and the name it's
+// downloaded as. Only ready tasks have a video which could be accessed.
+pub(crate) async fn finished_video(conn: &mut RedisConn, target: Uuid) -> Result<(String, String), LoadFileError> {
+    let record = task_state::load(conn, target).await
+        .map_err(RedisQueryError)?
+        .ok_or(LoadFileError::ResourceError(target.to_string()))?;
+    match (record.status, record.video) {
+        (TaskStatus::Ready, Some(video_key)) => {
+            Ok((video_key, download_name(record.filename)))
+        },
+        (TaskStatus::Cancelled | TaskStatus::Expired, _) => {
+            Err(LoadFileError::Gone(target.to_string()))
+        },
+        _ => Err(LoadFileError::ResourceError(target.to_string())),
+    }
+}
+
+// Name a video named `filename` by its uploader is downloaded as.
+pub(crate) fn download_name(filename: Option<String>) -> String {
+    filename.unwrap_or_else(|| DEFAULT_NAME.to_owned())
+}
+
+// `Content-Disposition` header to download a file as `filename`. Names
+// which aren't plain ASCII are also sent as an extended (RFC 5987) value,
+// with an ASCII version as fallback for older clients.
+pub(crate) fn attachment(filename: &str) -> ContentDisposition {
+    let fallback = filename.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' })
+        .collect();
+    let mut parameters = vec![DispositionParam::Filename(fallback)];
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_owned()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        }));
+    }
+    ContentDisposition { disposition: DispositionType::Attachment, parameters }
+}
+
+// Part of a video requested by a download.
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    Full,
+    // First and last byte of the requested part.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Read the byte range requested by `req` of a video of `size` bytes.
+// Requests for several ranges at once are answered with the full video.
+fn requested_range(req: &HttpRequest, size: u64) -> RequestedRange {
+    let Some(Ok(header)) = req.headers().get(RANGE).map(|h| h.to_str()) else {
+        return RequestedRange::Full;
+    };
+    // Malformed headers are ignored.
+    let Ok(Range::Bytes(specs)) = header.parse::<Range>() else {
+        return RequestedRange::Full;
+    };
+    match specs.as_slice() {
+        [spec] => match spec.to_satisfiable_range(size) {
+            Some((start, end)) => RequestedRange::Partial(start, end),
+            None => RequestedRange::Unsatisfiable,
+        },
+        _ => RequestedRange::Full,
+    }
 }
 
 // TODO: Error propagation if rendering fails or if query fails.
@@ -42,7 +208,9 @@ pub async fn load_file(
 // so the page can check whether the video is ready or not dynamically.
 #[get("/done/{progressId}")]
 pub async fn load_file_page(
+    req: HttpRequest,
     tera: web::Data<Tera>,
+    retention: web::Data<Retention>,
     path: web::Path<Uuid>,
 ) -> Result<HttpResponse, LoadFilePageError>  {
     let progress_id = path.into_inner().to_string();
@@ -50,103 +218,287 @@ pub async fn load_file_page(
     let mut ctx = Context::new();
     // Endpoint to download form with the ID of the video file to download
     ctx.insert("progress_id", &progress_id);
-    // Name of the video file to download.
-    ctx.insert("filename", FILE_NAME);  
+    // Name of the video file to download until the actual name is known.
+    ctx.insert("filename", DEFAULT_NAME);
     // `ready_`, `gone_` and `pending_msg` are used to evaluate the responses
     // from `GET /done/ready`. This endpont will responsd with the same constants (`READY`, ...) 
     // depending on the progress of the video.
     ctx.insert("pending_msg", PENDING);
     ctx.insert("gone_msg", GONE);
     ctx.insert("ready_msg", READY);
+    ctx.insert("failed_msg", FAILED);
+    // Only the uploader can delete the upload. The token itself
+    // is sent along with the delete request.
+    ctx.insert("can_delete", &delete_token(&req).is_some());
+    // The uploader can also keep the video for up to this many minutes.
+    ctx.insert("max_lifetime", &(retention.max_lifetime() / 60));
     // The following headings and info elements are used to switch up
     // the content displayed on the page at different steps in the rendering progress.
     ctx.insert("pending_heading", "Your video is being rendered!");
     ctx.insert("pending_info", "This might take a few seconds. You can download the result once it is ready.");
     ctx.insert("ready_heading", "Download your backdrop video!");
-    ctx.insert("ready_info", "Your video has successfully finished rendering. It will be deleted from the \
-        server in a few minutes");
+    ctx.insert("ready_info", "Your video has successfully finished rendering.");
     ctx.insert("gone_heading", "Assets are deleted");
     ctx.insert("gone_info", "The requested video and all assets used to create this video have been deleted.");
+    ctx.insert("failed_heading", "Your video could not be rendered");
+    ctx.insert("failed_info", "Rendering your video failed and all assets used to create it have been deleted.");
 
     let html = tera.render("file_load.html", &ctx)
-        .map_err(|e| TeraError(e))?;
+        .map_err(TeraError)?;
     Ok(HttpResponse::Ok().body(html))
 }
 
 // Check whether a task is done rendering and the
-// video is ready for download.
+// video is ready for download. Unknown uploads are answered with
+// `404 Not Found`, deleted or expired ones with `410 Gone`.
 #[get("/done/ready/{progressId}")]
 async fn check_resource_state(
     redis_pool: web::Data<RedisPool>,
+    download_links: web::Data<DownloadLinks>,
     path: web::Path<Uuid>,
 ) -> actix_web::Result<impl actix_web::Responder> {
-    let mut conn = redis_pool.get().await.map_err(|e| e500(e))?;
-    let progress_id = path.into_inner().to_string();
+    let mut conn = redis_pool.get().await.map_err(e500)?;
+    video_progress(&mut conn, &download_links, path.into_inner()).await
+}
 
-    let progress: String = conn.get(&progress_id).await
-        .map_err(|e| e500(e))?;
+// Look up how far the upload of `target` has come.
+pub(crate) async fn video_progress(
+    conn: &mut RedisConn,
+    download_links: &DownloadLinks,
+    target: Uuid,
+) -> actix_web::Result<VideoProgress> {
+    let record = task_state::load(conn, target).await
+        .map_err(e500)?;
+    // Records of gone uploads are kept for a while, so without a
+    // record the upload never existed (or is long gone).
+    let Some(mut record) = record else {
+        return Ok(VideoProgress::Unknown);
+    };
 
-    // If `progress` is set to `PENDING`, the video has not yet finished
-    // rendering. The client should wait and try again.
-    if progress == PENDING {
-        return Ok(VideoProgress::Pending);
+    match record.status {
+        // The video has not yet finished rendering.
+        // The client should wait and try again.
+        TaskStatus::Queued | TaskStatus::Rendering => {
+            // The status is only informational, so polling keeps working without it.
+            let status = queue_status(conn, target).await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to determine queue status of {target}: {e:?}");
+                    None
+                });
+            return Ok(VideoProgress::Pending(status, record));
+        },
+        TaskStatus::Cancelled | TaskStatus::Expired => return Ok(VideoProgress::Gone(record)),
+        TaskStatus::Failed if !record.is_expired(unix_now()) => return Ok(VideoProgress::Failed(record)),
+        TaskStatus::Failed | TaskStatus::Ready => {},
     }
 
-    // If `progress` is not set to `PENDING` it contains the key of the
-    // finished video.
-    let video_key = progress;
     // Now we can check if the video is still available.
-    // We return the video key if this is the case. Otherwise, the `GONE`
-    // message is returned to the client to indicate that the video is deleted now.
-    let video_lifetime: i32 = conn.ttl(&video_key).await
-        .map_err(|e| e500(e))?;
+    // We return a download link if this is the case. Otherwise, the
+    // task is marked as expired to indicate that the video is deleted now.
+    let video_lifetime: i32 = match &record.video {
+        Some(video_key) if record.status == TaskStatus::Ready => conn.ttl(video_key).await
+            .map_err(e500)?,
+        _ => REDIS_TTL_EXPIRED,
+    };
 
     if video_lifetime == REDIS_TTL_EXPIRED {
-        // This endpoint should not be called anymore after the `GONE`
-        // response was sent once. Because of this the progress key should
-        // now get deleted too.
-        conn.del(&progress_id).await
-            .map_err(|e| e500(e))?;
+        // Another request might have marked the task already.
+        let _ = task_state::transition(conn, target, &Transition::expired()).await
+            .map_err(e500)?;
+        record.status = TaskStatus::Expired;
         // Indicate to the client that the video is no longer available.
-        Ok(VideoProgress::Gone)
-    } else {
-        Ok(VideoProgress::Ready(video_key))
+        return Ok(VideoProgress::Gone(record));
+    }
+
+    // Links expire together with the video.
+    let expires = unix_now() + video_lifetime.max(0) as u64;
+    let video_key = record.video.as_deref().unwrap_or_default();
+    // Videos rendered before metadata was recorded have none.
+    let info = storage::video_info(conn, video_key).await
+        .map_err(e500)?;
+    Ok(VideoProgress::Ready(ReadyVideo {
+        filename: download_name(record.filename.clone()),
+        download_url: download_links.url(target, expires),
+        poster_url: download_links.poster_url(target, expires),
+        info,
+        expires_in: video_lifetime.max(0) as u64,
+        task: record,
+    }))
+}
+
+// Determine where the task of `target` is in the queue and estimate
+// how long it takes until its video is ready.
+async fn queue_status(conn: &mut RedisConn, target: Uuid) -> anyhow::Result<Option<QueueStatus>> {
+    let workers = registry::list_workers(conn).await?.alive;
+    let worker_ids: Vec<Uuid> = workers.iter().map(|w| w.id).collect();
+
+    match queue::locate(conn, target, &worker_ids).await? {
+        TaskLocation::Queued { task, ahead } => {
+            let profiles = ahead.iter().chain([&task])
+                .map(|t| t.options.profile(t.subtitles.is_some()));
+            let stats = RenderStats::load(conn, profiles).await?;
+            // Tasks ahead are rendered in parallel by all workers.
+            let wait: f64 = ahead.iter().map(|t| stats.estimate(t)).sum::<f64>()
+                / workers.len().max(1) as f64;
+            Ok(Some(QueueStatus {
+                queue_position: ahead.len() + 1,
+                rendering: false,
+                eta: (wait + stats.estimate(&task)).round() as u64,
+            }))
+        },
+        TaskLocation::Processing { task, worker_id } => {
+            let profile = task.options.profile(task.subtitles.is_some());
+            let stats = RenderStats::load(conn, [profile]).await?;
+            // The worker's heartbeat may not mention the task yet.
+            let elapsed = workers.iter()
+                .find(|w| w.id == worker_id && w.current_task == Some(target))
+                .and_then(|w| w.task_started_at)
+                .map_or(0, |started_at| unix_now().saturating_sub(started_at));
+            Ok(Some(QueueStatus {
+                queue_position: 0,
+                rendering: true,
+                eta: (stats.estimate(&task).round() as u64).saturating_sub(elapsed),
+            }))
+        },
+        TaskLocation::Unknown => Ok(None),
     }
 }
 
 #[derive(Debug)]
-enum VideoProgress {
-    Pending,
-    Gone,
-    Ready(String),
+pub(crate) enum VideoProgress {
+    // There is no upload with the ID.
+    Unknown,
+    Pending(Option<QueueStatus>, TaskRecord),
+    // The upload was deleted or its video has expired.
+    Gone(TaskRecord),
+    Ready(ReadyVideo),
+    Failed(TaskRecord),
+}
+
+impl VideoProgress {
+    // Whether the progress can't change anymore.
+    pub(crate) fn is_final(&self) -> bool {
+        matches!(self, VideoProgress::Unknown | VideoProgress::Gone(_) | VideoProgress::Failed(_))
+    }
+
+    // The task record of the upload and where its task is in the queue.
+    // Returns `None` for unknown uploads.
+    pub(crate) fn into_parts(self) -> Option<(TaskRecord, Option<QueueStatus>)> {
+        match self {
+            VideoProgress::Unknown => None,
+            VideoProgress::Pending(status, task) => Some((task, status)),
+            VideoProgress::Gone(task) | VideoProgress::Failed(task) => Some((task, None)),
+            VideoProgress::Ready(video) => Some((video.task, None)),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            VideoProgress::Unknown => StatusCode::NOT_FOUND,
+            VideoProgress::Gone(_) => StatusCode::GONE,
+            _ => StatusCode::OK,
+        }
+    }
+}
+
+impl From<VideoProgress> for ProgressResponse {
+    fn from(progress: VideoProgress) -> Self {
+        let response = |progress: &str, task: Option<TaskRecord>| ProgressResponse {
+            progress: progress.to_owned(),
+            download_url: None,
+            filename: None,
+            reason: None,
+            status: None,
+            poster_url: None,
+            video: None,
+            expires_in: None,
+            task,
+        };
+        match progress {
+            // Unknown uploads look gone to the download page.
+            VideoProgress::Unknown => response(GONE, None),
+            VideoProgress::Pending(status, task) => ProgressResponse {
+                status,
+                ..response(PENDING, Some(task))
+            },
+            VideoProgress::Gone(task) => response(GONE, Some(task)),
+            VideoProgress::Ready(video) => ProgressResponse {
+                download_url: Some(video.download_url),
+                filename: Some(video.filename),
+                poster_url: Some(video.poster_url),
+                video: video.info,
+                expires_in: Some(video.expires_in),
+                ..response(READY, Some(video.task))
+            },
+            VideoProgress::Failed(task) => ProgressResponse {
+                reason: task.last_error.clone(),
+                ..response(FAILED, Some(task))
+            },
+        }
+    }
 }
 
 impl Responder for VideoProgress {
     type Body = actix_web::body::EitherBody<String>;
 
     fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
-        match self {
-            VideoProgress::Pending => web::Json(ProgressResponse {
-                progress: PENDING.to_owned(),
-                video_key: None,
-            }).respond_to(req),
-            VideoProgress::Gone => web::Json(ProgressResponse {
-                progress: GONE.to_owned(),
-                video_key: None,
-            }).respond_to(req),
-            VideoProgress::Ready(key) => web::Json(ProgressResponse {
-                progress: READY.to_owned(),
-                video_key: Some(key),
-            }).respond_to(req),
-        }
+        let status = self.status_code();
+        let mut response = web::Json(ProgressResponse::from(self)).respond_to(req);
+        *response.status_mut() = status;
+        response
     }
 }
 
 // Struct used by `VideoProgress` to create JSON responses.
 #[derive(Debug, Serialize)]
-struct ProgressResponse {
+pub(crate) struct ProgressResponse {
     progress: String,
-    video_key: Option<String>,
+    // Signed link to download the finished video.
+    download_url: Option<String>,
+    // Name of the finished video file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    // Why rendering failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    // Where a pending task is in the queue, if it's known.
+    #[serde(flatten)]
+    status: Option<QueueStatus>,
+    // Signed link to the poster image of the finished video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poster_url: Option<String>,
+    // Metadata of the finished video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    video: Option<VideoInfo>,
+    // Seconds until the finished video is deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_in: Option<u64>,
+    // Record of the upload's task.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<TaskRecord>,
+}
+
+// A finished video and the links to access it.
+#[derive(Debug)]
+pub(crate) struct ReadyVideo {
+    filename: String,
+    download_url: String,
+    poster_url: String,
+    info: Option<VideoInfo>,
+    // Seconds until the video is deleted.
+    expires_in: u64,
+    task: TaskRecord,
+}
+
+// Progress of a pending task.
+#[derive(Debug, Serialize)]
+pub(crate) struct QueueStatus {
+    // Position of the task in the queue, starting at 1 for the next task
+    // to be rendered. 0 if the task is being rendered.
+    queue_position: usize,
+    rendering: bool,
+    // Estimated number of seconds until the video is ready.
+    eta: u64,
 }
 
 // Error returned by `load_file` endpoint.
@@ -154,6 +506,10 @@ struct ProgressResponse {
 pub enum LoadFileError {
     #[error("Requested unavailable resource: id: {0}")]
     ResourceError(String),
+    #[error("Requested deleted or expired resource: id: {0}")]
+    Gone(String),
+    #[error("Invalid or expired download link")]
+    InvalidLink,
     #[error(transparent)]
     QueryError(#[from] RedisQueryError),
     #[error(transparent)]
@@ -166,6 +522,8 @@ impl ResponseError for LoadFileError {
     fn status_code(&self) -> StatusCode {
         match self {
             LoadFileError::ResourceError(_) => StatusCode::NOT_FOUND,
+            LoadFileError::Gone(_) => StatusCode::GONE,
+            LoadFileError::InvalidLink => StatusCode::FORBIDDEN,
             LoadFileError::QueryError(e) => e.status_code(),
             LoadFileError::WebError(e) => {
                 e.as_response_error().status_code()
@@ -179,6 +537,14 @@ impl ResponseError for LoadFileError {
                 HttpResponse::NotFound()
                     .body("The requested resouce is not available")
             }
+            LoadFileError::Gone(_) => {
+                HttpResponse::Gone()
+                    .body("The requested resource has been deleted or has expired")
+            }
+            LoadFileError::InvalidLink => {
+                HttpResponse::Forbidden()
+                    .body("The download link is invalid or has expired")
+            }
             LoadFileError::QueryError(e) => e.error_response(),
             LoadFileError::WebError(e) => e.error_response(),
         }
@@ -1,6 +1,13 @@
 mod post;
 mod get;
+mod options;
+mod output_name;
+mod subtitles;
 
 pub use post::save_file;
+pub(crate) use post::{create_upload, SaveFileError};
 pub use post::RenderTask;
 pub use get::save_file_page;
+pub use options::{RenderOptions, SubtitleMode, SubtitleStyle, SubtitlePosition, parse_timestamp};
+pub use output_name::{output_name, DEFAULT_NAME};
+pub use subtitles::{Cue, parse_subtitles, to_srt};
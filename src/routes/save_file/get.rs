@@ -1,12 +1,37 @@
 use actix_web::{web, HttpResponse};
 use tera::{Tera, Context};
 
+use crate::RedisPool;
+use crate::capabilities::Features;
+use crate::retention::Retention;
 use crate::routes::errors::TeraError;
+use crate::render_worker::registry::available_features;
 
 // Page with form to upload a file.
-pub async fn save_file_page(tera: web::Data<Tera>) -> Result<HttpResponse, TeraError> {
+pub async fn save_file_page(
+    tera: web::Data<Tera>,
+    redis_pool: web::Data<RedisPool>,
+    retention: web::Data<Retention>,
+) -> Result<HttpResponse, TeraError> {
     let mut ctx = Context::new();
     ctx.insert("endpoint", "/save");
+    // Only offer the options the running render workers support.
+    // The upload itself is checked again, so the page falls back to
+    // offering all options if the workers can't be queried.
+    let features = match redis_pool.get().await {
+        Ok(mut conn) => available_features(&mut conn).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to query render worker features: {e:?}");
+            Features::default()
+        }),
+        Err(e) => {
+            tracing::warn!("Failed to query render worker features: {e:?}");
+            Features::default()
+        },
+    };
+    ctx.insert("features", &features);
+    // Lifetimes (in minutes) uploaders can choose from.
+    ctx.insert("default_lifetime", &(retention.default_lifetime() / 60));
+    ctx.insert("max_lifetime", &(retention.max_lifetime() / 60));
 
     let html = tera.render("file_save.html", &ctx)?;
     Ok(HttpResponse::Ok().body(html))
@@ -0,0 +1,238 @@
+use serde::{Serialize, Deserialize};
+
+use super::post::SaveFileError;
+
+// Options controlling how a render task is turned into a video.
+// They are received as plain text fields of the upload form
+// and travel to the render worker as part of the `RenderTask`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RenderOptions {
+    // Offset (in seconds) into the audio where the video starts.
+    pub trim_start: Option<f64>,
+    // Offset (in seconds) into the audio where the video ends.
+    pub trim_end: Option<f64>,
+    // Duration (in seconds) of the video if the (trimmed) audio is looped.
+    pub loop_duration: Option<f64>,
+    // Length (in seconds) of the crossfade at the seam of the loop.
+    pub loop_crossfade: Option<f64>,
+    // How uploaded subtitles are added to the video.
+    #[serde(default)]
+    pub subtitle_mode: SubtitleMode,
+    // Style of burned-in subtitles.
+    #[serde(default)]
+    pub subtitle_style: SubtitleStyle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleMode {
+    // Render the text into the video frames.
+    #[default]
+    Burn,
+    // Embed the text as a subtitle track which players can toggle.
+    Soft,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubtitleStyle {
+    pub font_size: u32,
+    // Text color as `#rrggbb`.
+    pub color: String,
+    pub position: SubtitlePosition,
+}
+
+impl Default for SubtitleStyle {
+    fn default() -> Self {
+        Self {
+            font_size: 24,
+            color: "#ffffff".to_owned(),
+            position: SubtitlePosition::Bottom,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitlePosition {
+    Top,
+    Middle,
+    #[default]
+    Bottom,
+}
+
+// Longest video (in seconds) that can be created by looping audio.
+const MAX_LOOP_DURATION: f64 = 12.0 * 60.0 * 60.0;  // 12h
+// Longest (trimmed) audio (in seconds) that can be looped. The render worker
+// keeps the decoded audio of the loop in memory, which is about 400 KB per
+// second of stereo audio.
+const MAX_LOOP_SEGMENT: f64 = 5.0 * 60.0;  // 5min
+
+impl RenderOptions {
+    // Set the option with the given form field name to the given value.
+    // Empty values leave the option unset, so optional form inputs
+    // which were left blank are simply ignored.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), SaveFileError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        match name {
+            "trim-start" => self.trim_start = Some(parse_option_timestamp(name, value)?),
+            "trim-end" => self.trim_end = Some(parse_option_timestamp(name, value)?),
+            "loop-duration" => self.loop_duration = Some(parse_option_timestamp(name, value)?),
+            "loop-crossfade" => self.loop_crossfade = Some(parse_option_timestamp(name, value)?),
+            "subtitle-mode" => self.subtitle_mode = match value {
+                "burn" => SubtitleMode::Burn,
+                "soft" => SubtitleMode::Soft,
+                other => return Err(SaveFileError::InvalidOption(
+                    format!("unknown subtitle mode `{other}`; use `burn` or `soft`")
+                )),
+            },
+            "subtitle-size" => self.subtitle_style.font_size = value.parse()
+                .ok()
+                .filter(|size| (8..=96).contains(size))
+                .ok_or_else(|| SaveFileError::InvalidOption(
+                    format!("subtitle size `{value}` is not a number between 8 and 96")
+                ))?,
+            "subtitle-color" => {
+                let is_hex_color = value.len() == 7
+                    && value.starts_with('#')
+                    && value[1..].chars().all(|c| c.is_ascii_hexdigit());
+                if !is_hex_color {
+                    return Err(SaveFileError::InvalidOption(
+                        format!("subtitle color `{value}` is not a `#rrggbb` color")
+                    ));
+                }
+                self.subtitle_style.color = value.to_ascii_lowercase();
+            },
+            "subtitle-position" => self.subtitle_style.position = match value {
+                "top" => SubtitlePosition::Top,
+                "middle" => SubtitlePosition::Middle,
+                "bottom" => SubtitlePosition::Bottom,
+                other => return Err(SaveFileError::InvalidOption(
+                    format!("unknown subtitle position `{other}`")
+                )),
+            },
+            other => return Err(SaveFileError::InvalidOption(
+                format!("unknown option `{other}`")
+            )),
+        }
+        Ok(())
+    }
+
+    // Check the options make sense for audio of the given duration (in seconds).
+    pub fn validate(&self, audio_duration: f64) -> Result<(), SaveFileError> {
+        let start = self.trim_start.unwrap_or(0.0);
+        let end = self.trim_end.unwrap_or(audio_duration);
+
+        if start >= audio_duration {
+            return Err(SaveFileError::InvalidOption(format!(
+                "trim start {start}s is not before the end of the audio ({audio_duration}s)"
+            )));
+        }
+        if end > audio_duration {
+            return Err(SaveFileError::InvalidOption(format!(
+                "trim end {end}s is past the end of the audio ({audio_duration}s)"
+            )));
+        }
+        if start >= end {
+            return Err(SaveFileError::InvalidOption(format!(
+                "trim start {start}s must be before trim end {end}s"
+            )));
+        }
+
+        if let Some(target) = self.loop_duration {
+            if target <= 0.0 || target > MAX_LOOP_DURATION {
+                return Err(SaveFileError::InvalidOption(format!(
+                    "loop duration must be between 0s and {MAX_LOOP_DURATION}s"
+                )));
+            }
+            if end - start > MAX_LOOP_SEGMENT {
+                return Err(SaveFileError::InvalidOption(format!(
+                    "looped audio ({}s) must not be longer than {MAX_LOOP_SEGMENT}s",
+                    end - start,
+                )));
+            }
+        }
+        if let Some(crossfade) = self.loop_crossfade {
+            if self.loop_duration.is_none() {
+                return Err(SaveFileError::InvalidOption(
+                    "a loop crossfade requires a loop duration".to_owned()
+                ));
+            }
+            // The seam is built from both ends of the audio
+            // which therefore must not overlap.
+            let segment = end - start;
+            if crossfade <= 0.0 || crossfade * 2.0 >= segment {
+                return Err(SaveFileError::InvalidOption(format!(
+                    "loop crossfade must be longer than 0s and shorter than half \
+                    of the looped audio ({segment}s)"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Name of the most expensive kind of render these options (and
+    // possibly subtitles) require. Used to label render metrics.
+    pub fn profile(&self, has_subtitles: bool) -> &'static str {
+        match (has_subtitles, self.subtitle_mode) {
+            (true, SubtitleMode::Burn) => "burned_subtitles",
+            _ if self.loop_duration.is_some() => "looped",
+            (true, SubtitleMode::Soft) => "soft_subtitles",
+            _ if self.is_trimmed() => "trimmed",
+            _ => "plain",
+        }
+    }
+
+    // Whether the audio has to be cut before rendering.
+    pub fn is_trimmed(&self) -> bool {
+        self.trim_start.is_some() || self.trim_end.is_some()
+    }
+
+    // Duration (in seconds) of the part of the audio used in the video.
+    pub fn trimmed_duration(&self, audio_duration: f64) -> f64 {
+        self.trim_end.unwrap_or(audio_duration) - self.trim_start.unwrap_or(0.0)
+    }
+
+    // Duration (in seconds) of the finished video.
+    pub fn output_duration(&self, audio_duration: f64) -> f64 {
+        self.loop_duration.unwrap_or_else(|| self.trimmed_duration(audio_duration))
+    }
+}
+
+// Parse a timestamp given as either plain seconds (`75.5`)
+// or in clock notation (`1:15.5`, `0:01:15.5`).
+pub fn parse_timestamp(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut secs = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        // Only the seconds may have a fractional part.
+        let value: f64 = if is_last {
+            part.parse().ok()?
+        } else {
+            f64::from(part.parse::<u32>().ok()?)
+        };
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        // Minutes and seconds after a colon must stay below 60.
+        if i > 0 && value >= 60.0 {
+            return None;
+        }
+        secs = secs * 60.0 + value;
+    }
+    Some(secs)
+}
+
+fn parse_option_timestamp(name: &str, value: &str) -> Result<f64, SaveFileError> {
+    parse_timestamp(value).ok_or_else(|| SaveFileError::InvalidOption(
+        format!("`{value}` is not a valid timestamp for `{name}`")
+    ))
+}
@@ -0,0 +1,54 @@
+use std::path::Path;
+
+// Downloaded videos are named after the uploaded audio. A name chosen at
+// upload time is used as is. Otherwise the name is built from the audio's
+// title and artist tags, or from the audio's file name if it has no title.
+// Names are sanitized, so they are valid file names on all platforms.
+
+// Name of videos nothing better is known about.
+pub const DEFAULT_NAME: &str = "backdrop.mp4";
+const EXTENSION: &str = "mp4";
+// Maximum length (in characters) of a name without its extension.
+const MAX_NAME_LENGTH: usize = 100;
+
+// Derive the file name of a video from the name chosen by the
+// user, the audio's tags and the name of the uploaded audio file.
+pub fn output_name(
+    custom: Option<&str>,
+    title: Option<&str>,
+    artist: Option<&str>,
+    audio_filename: Option<&str>,
+) -> String {
+    let from_tags = match (artist, title) {
+        (Some(artist), Some(title)) => Some(format!("{artist} - {title}")),
+        (None, Some(title)) => Some(title.to_owned()),
+        _ => None,
+    };
+    let from_file = audio_filename
+        .and_then(|name| Path::new(name).file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned());
+    let custom = custom.map(|name| strip_extension(name).to_owned());
+
+    [custom, from_tags, from_file].into_iter()
+        .flatten()
+        .find_map(|name| sanitize(&name))
+        .map_or_else(|| DEFAULT_NAME.to_owned(), |name| format!("{name}.{EXTENSION}"))
+}
+
+// Remove the video extension from names given with one.
+fn strip_extension(name: &str) -> &str {
+    let name = name.trim();
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if extension.eq_ignore_ascii_case(EXTENSION) => stem,
+        _ => name,
+    }
+}
+
+// Turn `name` into a valid file name. Returns `None` if nothing is left of it.
+fn sanitize(name: &str) -> Option<String> {
+    let name = sanitize_filename::sanitize(name);
+    let name: String = name.trim().chars().take(MAX_NAME_LENGTH).collect();
+    // Names must not end in a dot or space on Windows.
+    let name = name.trim_end_matches(['.', ' ']);
+    (!name.is_empty()).then(|| name.to_owned())
+}
@@ -10,42 +10,131 @@ use serde::{Serialize, Deserialize};
 
 use crate::utils::{derive_error_chain_fmt, e500};
 use crate::routes::errors::RedisQueryError;
-use crate::{RedisPool, RedisConn, PENDING, RENDER_QUEUE_KEY};
+use crate::routes::delete_token_cookie;
+use crate::download_link::DownloadLinks;
+use crate::ffprobe::Ffprobe;
+use crate::capabilities::Features;
+use crate::render_worker::registry::available_features;
+use crate::render_cache::{self, RenderCache};
+use crate::archive;
+use crate::retention::Retention;
+use crate::storage;
+use crate::task_state::{self, TaskRecord, TaskStatus, Transition};
+use crate::metrics;
+use crate::webhooks::{self, parse_callback_url, Delivery, WebhookEvent};
+use super::options::{RenderOptions, SubtitleMode};
+use super::subtitles::parse_subtitles;
+use super::output_name::output_name;
+use crate::{RedisPool, RedisConn};
+use crate::priority::Priority;
+use crate::api_keys::ApiKey;
+use crate::utils::unix_now;
 use crate::REDIS_DISCARD;
 
+// Name of the form field carrying subtitles. Subtitle files are also recognized
+// by this name because browsers often don't know a mime type for LRC files.
+const SUBTITLES_FIELD: &str = "source-subtitles";
+// Name of the form field carrying the options as a JSON object.
+const OPTIONS_FIELD: &str = "options";
+
 // POST endpoint to upload any file to redis.
 pub async fn save_file(
     redis_pool: web::Data<RedisPool>,
+    retention: web::Data<Retention>,
+    download_links: web::Data<DownloadLinks>,
+    ffprobe: web::Data<Ffprobe>,
     payload: Multipart,
 ) -> Result<HttpResponse, SaveFileError> {
-    let mut conn = redis_pool.get().await.map_err(|e| e500(e))?;
+    let target = Uuid::new_v4();
+    create_upload(&redis_pool, &retention, &download_links, &ffprobe, payload, target, None).await?;
+    Ok(redirect_to_download(target, &download_links))
+}
+
+// Store the assets and options of the upload `target` and queue its task,
+// unless its video is cached already. Uploads made with an API key are held
+// to its limits. Returns the size (in bytes) of the received assets.
+pub(crate) async fn create_upload(
+    redis_pool: &RedisPool,
+    retention: &Retention,
+    download_links: &DownloadLinks,
+    ffprobe: &Ffprobe,
+    payload: Multipart,
+    target: Uuid,
+    api_key: Option<&ApiKey>,
+) -> Result<u64, SaveFileError> {
+    let mut conn = redis_pool.get().await.map_err(e500)?;
+
+    // Only accept options the running render workers can render. Workers
+    // which can't render a task leave it to others or fail it, so uploads
+    // are still accepted if the workers can't be looked up.
+    let features = available_features(&mut conn).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to look up the features of the render workers: {e:?}");
+        Features::default()
+    });
 
     // Start redis transaction to save the assets.
     redis::cmd("MULTI")
-        .query_async(conn.deref_mut()).await
-        .map_err(|e| RedisQueryError(e))?;
+        .query_async::<_, ()>(conn.deref_mut()).await
+        .map_err(RedisQueryError)?;
 
     // Receive and store the assets in the multipart form.
-    let render_task = match RenderTask::build_from_form(
+    let (render_task, upload_size) = match RenderTask::build_from_form(
         &mut conn,
         payload,
+        features,
+        retention,
+        download_links,
+        ffprobe,
+        RenderTaskBuilder::new(target, api_key),
     ).await {
-        Ok(render_task) => render_task,
+        Ok(received) => received,
         Err(e) => {
-            redis::cmd(REDIS_DISCARD)
-                .query_async(conn.deref_mut()).await
-                .map_err(|e| RedisQueryError(e))?;
+            discard(&mut conn).await?;
             return Err(e);
         },
     };
 
+    // Resolve the upload to an identical video which was rendered before.
+    // The received assets aren't needed then. This uses its own connection
+    // because `conn` is inside of the transaction, which is discarded on
+    // every way out of this block.
+    if let Some(cache_key) = &render_task.cache_key {
+        let cached = async {
+            let mut cache_conn = redis_pool.get().await.map_err(e500)?;
+            let cached = RenderCache::new(render_task.lifetime(retention))
+                .reference(&mut cache_conn, cache_key).await
+                .map_err(RedisQueryError)?;
+            Ok::<_, SaveFileError>(cached.map(|(video_key, ttl)| (cache_conn, video_key, ttl)))
+        }.await;
+        let cached = match cached {
+            Ok(cached) => cached,
+            Err(e) => {
+                discard(&mut conn).await?;
+                return Err(e);
+            },
+        };
+        if let Some((mut cache_conn, video_key, ttl)) = cached {
+            let resolved = async {
+                discard(&mut conn).await?;
+                resolve_to_cached(&mut cache_conn, &render_task, retention, &video_key, ttl).await
+            }.await;
+            if let Err(e) = resolved {
+                // Nothing refers to the video through this upload then.
+                if let Err(e) = render_cache::release(&mut cache_conn, &video_key).await {
+                    tracing::warn!("Failed to release cached video {video_key}: {e:?}");
+                }
+                return Err(e);
+            }
+            tracing::info!("Resolved upload {} to a cached render", render_task.target);
+            return Ok(upload_size);
+        }
+    }
+
     // Add a render task for the received assets to the render queue.
-    let queued_target_id = match render_task.queue(&mut conn).await {
-        Ok(queued_id) => queued_id,
+    match render_task.queue(&mut conn, batch_ttl(retention)).await {
+        Ok(_) => {},
         Err(e) => {
-            redis::cmd(REDIS_DISCARD)
-                .query_async(conn.deref_mut()).await
-                .map_err(|e| RedisQueryError(e))?;
+            discard(&mut conn).await?;
             return Err(e);
         },
     };
@@ -53,15 +142,84 @@ pub async fn save_file(
     // Commit the assets and the task to redis now the request
     // has been processed without any errors.
     redis::cmd("EXEC")
-        .query_async(conn.deref_mut()).await
-        .map_err(|e| RedisQueryError(e))?;
+        .query_async::<_, ()>(conn.deref_mut()).await
+        .map_err(RedisQueryError)?;
+
+    Ok(upload_size)
+}
+
+// Abort the transaction `conn` is in, so it doesn't go back to the pool with one.
+async fn discard(conn: &mut RedisConn) -> Result<(), SaveFileError> {
+    redis::cmd(REDIS_DISCARD)
+        .query_async::<_, ()>(conn.deref_mut()).await
+        .map_err(RedisQueryError)?;
+    Ok(())
+}
+
+// Make the upload of `render_task` ready right away with the cached video
+// under `video_key`, which it already holds a reference to. It expires
+// along with the cached video after `ttl` seconds, which might outlive
+// the lifetime chosen for this upload.
+async fn resolve_to_cached(
+    conn: &mut RedisConn,
+    render_task: &RenderTask,
+    retention: &Retention,
+    video_key: &str,
+    ttl: usize,
+) -> Result<(), SaveFileError> {
+    let info = storage::video_info(conn, video_key).await
+        .map_err(RedisQueryError)?;
+    let target = render_task.target;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    task_state::create_in(&mut pipe, target, &TaskRecord::queued(render_task));
+    task_state::transition_in(
+        &mut pipe, target, TaskStatus::Queued, &Transition::ready(video_key, info.as_ref(), ttl)
+    );
+    if let Some(url) = &render_task.callback_url {
+        webhooks::enqueue_in(
+            &mut pipe,
+            &Delivery::new(url.clone(), target, WebhookEvent::Ready, None),
+        );
+    }
+    if let Some(batch) = render_task.batch {
+        archive::add_to_batch_in(&mut pipe, batch, target, batch_ttl(retention));
+    }
+    let _: () = pipe.query_async(conn.deref_mut()).await
+        .map_err(RedisQueryError)?;
+    Ok(())
+}
 
-    // Redirect the caller to the download page for the queued video render.
-    let redirect_url = format!("/done/{queued_target_id}");
-    Ok(HttpResponse::SeeOther()
+// Read options sent as a JSON object. Every member is treated like the
+// form field of the same name, so `{"trim-start": "0:30", "lifetime": 60}`
+// sets the same options as the form fields would.
+fn parse_json_options(data: &[u8]) -> Result<Vec<(String, String)>, SaveFileError> {
+    let options: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(data)
+        .map_err(|e| SaveFileError::InvalidOption(format!("options are not a JSON object: {e}")))?;
+    options.into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => Ok((name, value)),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => Ok((name, value.to_string())),
+            _ => Err(SaveFileError::InvalidOption(format!("value of `{name}` is not a string or number"))),
+        })
+        .collect()
+}
+
+// Amount of time (in seconds) a batch is kept after its last upload. It's
+// kept until the upload's video expires, even if it waits in the queue long.
+fn batch_ttl(retention: &Retention) -> usize {
+    task_state::ACTIVE_TTL + retention.max_lifetime()
+}
+
+// Redirect the caller to the download page for the video render.
+// The uploader receives the token to delete the upload along with it.
+fn redirect_to_download(target: Uuid, download_links: &DownloadLinks) -> HttpResponse {
+    let redirect_url = format!("/done/{target}");
+    HttpResponse::SeeOther()
         .insert_header((LOCATION, redirect_url))
+        .cookie(delete_token_cookie(target, download_links.delete_token(target)))
         .finish()
-    )
 }
 
 // Render task used by the render worker to create a
@@ -71,30 +229,114 @@ pub struct RenderTask {
     pub target: Uuid,
     pub audio: Uuid,
     pub image: Uuid,
+    // Duration (in seconds) of the uploaded audio.
+    #[serde(default)]
+    pub audio_duration: Option<f64>,
+    // Key of the parsed subtitle cues (stored as JSON) if any were uploaded.
+    #[serde(default)]
+    pub subtitles: Option<Uuid>,
+    #[serde(default)]
+    pub options: RenderOptions,
+    // Lane of the render queue this task is put in.
+    #[serde(default)]
+    pub priority: Priority,
+    // Unix timestamp (in seconds) at which the task was first queued.
+    #[serde(default)]
+    pub queued_at: Option<u64>,
+    // Key identifying renders of the same assets with the same options.
+    #[serde(default)]
+    pub cache_key: Option<String>,
+    // Name of the video file when it's downloaded.
+    #[serde(default)]
+    pub filename: Option<String>,
+    // Amount of time (in seconds) the finished video is kept. The
+    // default lifetime applies if the uploader didn't choose one.
+    #[serde(default)]
+    pub lifetime: Option<usize>,
+    // URL which is sent a webhook once the task is ready or failed.
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    // Batch of uploads the task's upload belongs to.
+    #[serde(default)]
+    pub batch: Option<Uuid>,
+    // ID of the API key the upload was made with.
+    #[serde(default)]
+    pub api_key: Option<Uuid>,
 }
 
 impl RenderTask {
+    // Amount of time (in seconds) the finished video is kept.
+    pub fn lifetime(&self, retention: &Retention) -> usize {
+        self.lifetime.unwrap_or(retention.default_lifetime())
+    }
+
     // Receive a multipart form and store it in redis.
     // Create a new instance of self using the received assets.
+    // Returns it along with the size (in bytes) of the assets.
     async fn build_from_form(
         conn: &mut RedisConn,
         mut payload: Multipart,
-    ) -> Result<Self, SaveFileError> {
-        let mut builder = RenderTaskBuilder::new(conn).await?;
+        features: Features,
+        retention: &Retention,
+        download_links: &DownloadLinks,
+        ffprobe: &Ffprobe,
+        mut builder: RenderTaskBuilder,
+    ) -> Result<(Self, u64), SaveFileError> {
+        let max_upload_size = builder.api_key.as_ref()
+            .and_then(|key| key.quotas.max_upload_size);
+        let mut upload_size = 0;
 
         while let Some(field) = payload.try_next().await? {
+            // Optional file inputs which were left empty are sent without a file name.
+            if field.content_disposition().get_filename() == Some("") {
+                Self::receive_field(field).await?;
+                continue;
+            }
+
+            // All options can also be sent at once as a JSON object.
+            if field.name() == OPTIONS_FIELD && field.content_type() == Some(&mime::APPLICATION_JSON) {
+                let data = Self::receive_field(field).await?;
+                for (name, value) in parse_json_options(&data)? {
+                    builder.set_field(&name, &value, retention)?;
+                }
+                continue;
+            }
+
+            // Fields without a file name are plain form inputs carrying render options.
+            if field.content_disposition().get_filename().is_none() {
+                let name = field.name().to_owned();
+                let data = Self::receive_field(field).await?;
+                let value = String::from_utf8(data).map_err(|_| {
+                    SaveFileError::InvalidOption(format!("value of `{name}` is not valid UTF-8"))
+                })?;
+                builder.set_field(&name, &value, retention)?;
+                continue;
+            }
+
             // Check for a valid mime type in the current context before starting to receive.
             // If the mime is valid the redis key to store the data is returned.
-            let asset_id = builder.validate_type(field.content_type())?;
+            let asset_id = builder.validate_type(field.name(), field.content_type())?;
+            if Some(asset_id) == builder.audio {
+                builder.audio_filename = field.content_disposition().get_filename().map(str::to_owned);
+            }
 
             // Receive and store the data in self.
-            let data = Self::receive_field(field).await?;
-            conn.set(asset_id.to_string(), data).await
-                .map_err(|e| RedisQueryError(e))?;
+            let mut data = Self::receive_asset(field, &mut upload_size, max_upload_size).await?;
+            if Some(asset_id) == builder.audio {
+                builder.probe_audio(ffprobe, &data).await?;
+            }
+            if Some(asset_id) == builder.subtitles {
+                data = Self::parse_subtitles_field(&data)?;
+            }
+            builder.hashes.push((asset_id, render_cache::hash(&data)));
+            metrics::WRITTEN_BYTES.with_label_values(&["asset"]).inc_by(data.len() as u64);
+            let _: () = conn.set(asset_id.to_string(), data).await
+                .map_err(RedisQueryError)?;
         }
+        metrics::UPLOAD_SIZE.observe(upload_size as f64);
 
         // Build asserts that all required assets are present
-        builder.build()
+        Ok((builder.build(features, retention, download_links)?, upload_size))
     }
 
     // Stream a single multipart form field and store it in a `Vec<u8>` buffer.
@@ -102,16 +344,58 @@ impl RenderTask {
         use std::io::Write;
         let mut buf: Vec<u8> = Vec::with_capacity(1<<19);  // 500kB buffer
         while let Some(chunk) = field.try_next().await? {
-            buf.write_all(&chunk).map_err(|e| e500(e))?;
+            buf.write_all(&chunk).map_err(e500)?;
         }
         Ok(buf)
     }
 
-    // Add `self` to the render worker task queue.
-    pub async fn queue(self, conn: &mut RedisConn) -> Result<String, SaveFileError> {
-        let ser = serde_json::to_string(&self).map_err(|e| e500(e))?;
-        conn.lpush(RENDER_QUEUE_KEY, ser).await
-            .map_err(|e| RedisQueryError(e))?;
+    // Stream an uploaded asset into a buffer, counting its bytes towards
+    // `upload_size`. Receiving stops as soon as `max_upload_size` is exceeded.
+    async fn receive_asset(
+        mut field: Field,
+        upload_size: &mut u64,
+        max_upload_size: Option<u64>,
+    ) -> Result<Vec<u8>, SaveFileError> {
+        let mut buf: Vec<u8> = Vec::with_capacity(1<<19);  // 500kB buffer
+        while let Some(chunk) = field.try_next().await? {
+            *upload_size += chunk.len() as u64;
+            if let Some(max) = max_upload_size.filter(|max| *upload_size > *max) {
+                return Err(SaveFileError::TooLarge(max));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(buf)
+    }
+
+    // Parse a received subtitle file and return the cues to store instead.
+    fn parse_subtitles_field(data: &[u8]) -> Result<Vec<u8>, SaveFileError> {
+        let content = std::str::from_utf8(data).map_err(|_| {
+            SaveFileError::InvalidSubtitles("the file is not valid UTF-8 text".to_owned())
+        })?;
+        let cues = parse_subtitles(content).map_err(SaveFileError::InvalidSubtitles)?;
+        serde_json::to_vec(&cues).map_err(|e| e500(e).into())
+    }
+
+    // Add `self` to the render worker task queue lane of its priority,
+    // create its task record and add it to its batch. The callback URL
+    // is kept next to the task to report its cancellation.
+    pub async fn queue(mut self, conn: &mut RedisConn, batch_ttl: usize) -> Result<String, SaveFileError> {
+        self.queued_at.get_or_insert_with(unix_now);
+        let ser = serde_json::to_string(&self).map_err(e500)?;
+        let _: () = conn.lpush(self.priority.queue_key(), ser).await
+            .map_err(RedisQueryError)?;
+        let mut pipe = redis::pipe();
+        task_state::create_in(&mut pipe, self.target, &TaskRecord::queued(&self));
+        if let Some(batch) = self.batch {
+            archive::add_to_batch_in(&mut pipe, batch, self.target, batch_ttl);
+        }
+        let _: () = pipe.query_async(conn.deref_mut()).await
+            .map_err(RedisQueryError)?;
+        if let Some(url) = &self.callback_url {
+            // Expires with the task record if the task is never finished.
+            let _: () = conn.set_ex(webhooks::callback_key(self.target), url, task_state::ACTIVE_TTL).await
+                .map_err(RedisQueryError)?;
+        }
         Ok(self.target.to_string())
     }
 }
@@ -121,21 +405,107 @@ pub struct RenderTaskBuilder {
     target: Uuid, // redis key of target entry
     audio: Option<Uuid>,  // redis key of audio file
     image: Option<Uuid>,  // redis key of image file
+    subtitles: Option<Uuid>,  // redis key of subtitle cues
+    audio_duration: Option<f64>,  // duration of the audio file in seconds
+    options: RenderOptions,
+    priority: Option<Priority>,  // priority chosen by the user
+    hashes: Vec<(Uuid, String)>,  // hashes of the received assets
+    audio_filename: Option<String>,  // name of the uploaded audio file
+    audio_tags: (Option<String>, Option<String>),  // title and artist of the audio
+    custom_name: Option<String>,  // name of the video chosen by the user
+    lifetime: Option<usize>,  // seconds the video is kept as chosen by the user
+    callback_url: Option<String>,  // URL to send a webhook to once the video is done
+    batch: Option<Uuid>,  // batch of uploads the upload belongs to
+    batch_token: Option<String>,  // token allowing to join the batch
+    api_key: Option<ApiKey>,  // API key the upload is made with
 }
 
 impl RenderTaskBuilder {
-    // Create new instance with target_id entry in redis.
-    async fn new(conn: &mut RedisConn) -> Result<Self, RedisQueryError> {
-        // Key of the redis entry to later store the finished video.
-        let target_id = Uuid::new_v4();
-        conn.set(target_id.to_string(), PENDING).await
-            .map_err(|e| RedisQueryError(e))?;
-
-        Ok(Self {
-            target: target_id,
+    // Create new instance for the new upload `target`, which
+    // identifies its task record.
+    fn new(target: Uuid, api_key: Option<&ApiKey>) -> Self {
+        Self {
+            target,
             audio: None,
             image: None,
-        })
+            subtitles: None,
+            audio_duration: None,
+            options: RenderOptions::default(),
+            priority: None,
+            hashes: Vec::new(),
+            audio_filename: None,
+            audio_tags: (None, None),
+            custom_name: None,
+            lifetime: None,
+            callback_url: None,
+            batch: None,
+            batch_token: None,
+            api_key: api_key.cloned(),
+        }
+    }
+
+    // Set the value of a plain (non-file) form field.
+    fn set_field(
+        &mut self,
+        name: &str,
+        value: &str,
+        retention: &Retention,
+    ) -> Result<(), SaveFileError> {
+        match name {
+            "priority" => {
+                self.priority = Some(Priority::try_from(value.trim().to_owned())
+                    .map_err(SaveFileError::InvalidOption)?);
+                Ok(())
+            },
+            // The name doesn't change the video, so it's no render option.
+            "filename" => {
+                let value = value.trim();
+                self.custom_name = (!value.is_empty()).then(|| value.to_owned());
+                Ok(())
+            },
+            "callback_url" => {
+                if !value.trim().is_empty() {
+                    self.callback_url = Some(parse_callback_url(value)
+                        .map_err(SaveFileError::InvalidOption)?);
+                }
+                Ok(())
+            },
+            // Nor does the batch the upload belongs to.
+            "batch" => {
+                let value = value.trim();
+                if !value.is_empty() {
+                    self.batch = Some(Uuid::parse_str(value).map_err(|_| {
+                        SaveFileError::InvalidOption(format!("batch `{value}` is not a UUID"))
+                    })?);
+                }
+                Ok(())
+            },
+            "batch_token" => {
+                let value = value.trim();
+                self.batch_token = (!value.is_empty()).then(|| value.to_owned());
+                Ok(())
+            },
+            // Neither does the time the video is kept.
+            "lifetime" => {
+                if !value.trim().is_empty() {
+                    self.lifetime = Some(retention.parse(value)
+                        .map_err(SaveFileError::InvalidOption)?);
+                }
+                Ok(())
+            },
+            _ => self.options.set(name, value),
+        }
+    }
+
+    // Determine the duration and tags of the received audio data. Uploads which
+    // `ffprobe` cannot read are rejected because they can't be rendered either.
+    async fn probe_audio(&mut self, ffprobe: &Ffprobe, data: &[u8]) -> Result<(), SaveFileError> {
+        let probe = ffprobe.probe_audio(data).await
+            .map_err(e500)?
+            .ok_or(SaveFileError::UnreadableAudio)?;
+        self.audio_duration = Some(probe.duration);
+        self.audio_tags = (probe.title, probe.artist);
+        Ok(())
     }
 
     // Check the given mime type is valid in the current state of the
@@ -145,8 +515,26 @@ impl RenderTaskBuilder {
     // passed to the function call.
     fn validate_type(
         &mut self,
+        field_name: &str,
         mime_opt: Option<&mime::Mime>,
     ) -> Result<Uuid, SaveFileError> {
+        let is_subtitles = field_name == SUBTITLES_FIELD
+            || mime_opt.is_some_and(|mt| {
+                mt.type_() == mime::TEXT || mt.essence_str() == "application/x-subrip"
+            });
+        if is_subtitles {
+            return match self.subtitles {
+                Some(_) => Err(SaveFileError::UnexpectedMime(
+                    "received more than one subtitle file".to_owned()
+                )),
+                None => {
+                    let subtitles_id = Uuid::new_v4();
+                    self.subtitles = Some(subtitles_id);
+                    Ok(subtitles_id)
+                }
+            };
+        }
+
         let mime_type = match mime_opt {
             Some(mt) => mt,
             None => return Err(SaveFileError::MissingMime),
@@ -187,17 +575,103 @@ impl RenderTaskBuilder {
     // Create a `RenderTask` instance from the assets keys
     // collected in self. This method will never fail if
     // `validate_type` was called *twice* successfully before
-    // calling this method.
-    fn build(self) -> Result<RenderTask, SaveFileError> {
+    // calling this method, the options only use supported `features`
+    // and stay within the limits of the API key the upload is made with.
+    fn build(
+        mut self,
+        features: Features,
+        retention: &Retention,
+        download_links: &DownloadLinks,
+    ) -> Result<RenderTask, SaveFileError> {
         let audio_id = self.audio
             .ok_or(SaveFileError::MissingFile("audio"))?;
         let image_id = self.image
             .ok_or(SaveFileError::MissingFile("image"))?;
 
+        // The audio is always probed once it is received.
+        if let Some(duration) = self.audio_duration {
+            self.options.validate(duration)?;
+        }
+        // The subtitle mode means nothing without subtitles. It's reset so
+        // the upload shares its cache key with the same upload without a mode.
+        if self.subtitles.is_none() {
+            self.options.subtitle_mode = SubtitleMode::default();
+        }
+        features.check(&self.options, self.subtitles.is_some())
+            .map_err(SaveFileError::InvalidOption)?;
+        // Batches are started by the server, so only uploads
+        // holding the batch's token can join it.
+        if let Some(batch) = self.batch {
+            let token = self.batch_token.as_deref().ok_or_else(|| SaveFileError::InvalidOption(
+                "joining a batch requires its `batch_token`".to_owned()
+            ))?;
+            if !download_links.verify_batch_token(batch, token) {
+                return Err(SaveFileError::InvalidOption(
+                    format!("`batch_token` doesn't belong to batch {batch}")
+                ));
+            }
+        }
+
+        // Uploads made with an API key are rendered with its tier unless they
+        // choose a lower priority. Their videos are kept up to its limit.
+        let mut priority = self.priority.unwrap_or_default();
+        let mut lifetime = self.lifetime;
+        let mut callback_url = self.callback_url;
+        if let Some(key) = &self.api_key {
+            priority = self.priority.unwrap_or(key.tier);
+            if priority.is_above(key.tier) {
+                return Err(SaveFileError::InvalidOption(format!(
+                    "the priority of this API key is at most `{}`", key.tier.as_str()
+                )));
+            }
+            if let Some(max) = key.quotas.max_lifetime {
+                match lifetime {
+                    Some(chosen) if chosen > max => return Err(SaveFileError::InvalidOption(
+                        format!("the lifetime of this API key is at most {} minutes", max / 60)
+                    )),
+                    Some(_) => {},
+                    None => lifetime = Some(retention.default_lifetime().min(max)),
+                }
+            }
+            callback_url = callback_url.or_else(|| key.callback_url.clone());
+        } else if priority.is_above(Priority::default()) {
+            // Anyone can post the form, so jumping the queue takes an API key.
+            return Err(SaveFileError::InvalidOption(
+                "`high` priority requires an API key".to_owned()
+            ));
+        }
+
+        let hash_of = |id: Option<Uuid>| self.hashes.iter()
+            .find(|(asset_id, _)| Some(*asset_id) == id)
+            .map(|(_, hash)| hash.as_str());
+        let cache_key = match (hash_of(Some(audio_id)), hash_of(Some(image_id))) {
+            (Some(audio), Some(image)) => Some(render_cache::cache_key(
+                audio, image, hash_of(self.subtitles), &self.options
+            )),
+            _ => None,
+        };
+        let filename = output_name(
+            self.custom_name.as_deref(),
+            self.audio_tags.0.as_deref(),
+            self.audio_tags.1.as_deref(),
+            self.audio_filename.as_deref(),
+        );
+
         Ok(RenderTask {
             target: self.target,
             audio: audio_id,
             image: image_id,
+            audio_duration: self.audio_duration,
+            subtitles: self.subtitles,
+            options: self.options,
+            priority,
+            queued_at: None,
+            cache_key,
+            filename: Some(filename),
+            lifetime,
+            callback_url,
+            batch: self.batch,
+            api_key: self.api_key.map(|key| key.id),
         })
     }
 }
@@ -214,6 +688,18 @@ pub enum SaveFileError {
     /// The render task was missing a file entry when trying to add it to the queue.
     #[error("Missing file for render: {0}")]
     MissingFile(&'static str),  // type of the file
+    /// A render option was unknown, malformed or doesn't fit the uploaded assets.
+    #[error("Invalid render option: {0}")]
+    InvalidOption(String),
+    /// The uploaded subtitle file could not be parsed.
+    #[error("Invalid subtitles: {0}")]
+    InvalidSubtitles(String),
+    /// The uploaded audio could not be read by `ffprobe`.
+    #[error("Unreadable audio file")]
+    UnreadableAudio,
+    /// The uploaded assets exceed the limit (in bytes) of the API key.
+    #[error("Upload exceeds the limit of {0} bytes")]
+    TooLarge(u64),
     /// Error for all errors raised while receiving the mutlipart payload.
     #[error(transparent)]
     ReceiveError(#[from] actix_multipart::MultipartError),
@@ -233,6 +719,10 @@ impl ResponseError for SaveFileError {
             },
             SaveFileError::MissingMime => StatusCode::BAD_REQUEST,
             SaveFileError::MissingFile(_) => StatusCode::BAD_REQUEST,
+            SaveFileError::InvalidOption(_)
+            | SaveFileError::InvalidSubtitles(_)
+            | SaveFileError::UnreadableAudio => StatusCode::UNPROCESSABLE_ENTITY,
+            SaveFileError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
             SaveFileError::ReceiveError(multipart_err) => {
                 multipart_err.status_code()
             },
@@ -260,6 +750,22 @@ impl ResponseError for SaveFileError {
                 HttpResponse::BadRequest()
                     .body(format!("Reqest is missing a file: {file_type}"))
             },
+            SaveFileError::InvalidOption(msg) => {
+                HttpResponse::UnprocessableEntity()
+                    .body(format!("Invalid render option: {msg}"))
+            },
+            SaveFileError::InvalidSubtitles(msg) => {
+                HttpResponse::UnprocessableEntity()
+                    .body(format!("Invalid subtitle file: {msg}"))
+            },
+            SaveFileError::UnreadableAudio => {
+                HttpResponse::UnprocessableEntity()
+                    .body("The uploaded audio file could not be read")
+            },
+            SaveFileError::TooLarge(_) => {
+                HttpResponse::PayloadTooLarge()
+                    .body(self.to_string())
+            },
             SaveFileError::ReceiveError(_)
             | SaveFileError::WebError(_)
             | SaveFileError::QueryError(_)
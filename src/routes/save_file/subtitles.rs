@@ -0,0 +1,221 @@
+use serde::{Serialize, Deserialize};
+use std::fmt::Write;
+
+use super::options::parse_timestamp;
+
+// Timed lyrics or subtitles are accepted as LRC, SRT or WebVTT files.
+// All of them are parsed into a list of cues when they are uploaded,
+// which is what's stored and later handed to the render worker.
+
+// A single piece of text shown over a time range of the audio.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cue {
+    // Time (in seconds) at which the text appears.
+    pub start: f64,
+    // Time (in seconds) at which the text disappears.
+    // `None` means the text stays until the end of the audio.
+    pub end: Option<f64>,
+    pub text: String,
+}
+
+// Supported subtitle file formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    Lrc,
+    Srt,
+    WebVtt,
+}
+
+// Parse the given subtitle file. The format is detected from the content.
+// Errors describe what's wrong with the file, including the line number.
+pub fn parse_subtitles(content: &str) -> Result<Vec<Cue>, String> {
+    let content = content.trim_start_matches('\u{feff}');
+    let cues = match detect_format(content)? {
+        SubtitleFormat::Lrc => parse_lrc(content)?,
+        SubtitleFormat::Srt => parse_blocks(content, SubtitleFormat::Srt)?,
+        SubtitleFormat::WebVtt => parse_blocks(content, SubtitleFormat::WebVtt)?,
+    };
+    if cues.is_empty() {
+        return Err("the file doesn't contain any cues".to_owned());
+    }
+    Ok(cues)
+}
+
+fn detect_format(content: &str) -> Result<SubtitleFormat, String> {
+    let first_line = content.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .ok_or("the file is empty")?;
+
+    if first_line.starts_with("WEBVTT") {
+        Ok(SubtitleFormat::WebVtt)
+    } else if first_line.starts_with('[') {
+        Ok(SubtitleFormat::Lrc)
+    } else if content.contains("-->") {
+        Ok(SubtitleFormat::Srt)
+    } else {
+        Err("unknown subtitle format; use LRC, SRT or WebVTT".to_owned())
+    }
+}
+
+// Parse an LRC file. Each line holds one or more `[mm:ss.xx]` tags
+// followed by the text. A line lasts until the next line starts.
+fn parse_lrc(content: &str) -> Result<Vec<Cue>, String> {
+    let mut offset = 0.0;
+    let mut lines: Vec<(f64, String)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let mut rest = line.trim();
+        let mut starts = Vec::new();
+        while let Some(tag_end) = rest.strip_prefix('[').and_then(|r| r.find(']')) {
+            let tag = &rest[1..=tag_end];
+            rest = rest[tag_end + 2..].trim_start();
+            if let Some(ms) = tag.strip_prefix("offset:") {
+                // A positive offset shows the lyrics earlier.
+                let ms: f64 = ms.trim().parse()
+                    .map_err(|_| format!("line {}: invalid offset `{ms}`", i + 1))?;
+                offset = ms / 1000.0;
+            } else if tag.starts_with(|c: char| c.is_ascii_digit()) {
+                let start = parse_timestamp(tag)
+                    .ok_or_else(|| format!("line {}: invalid timestamp `[{tag}]`", i + 1))?;
+                starts.push(start);
+            }
+            // Other tags (`[ar:...]`, `[ti:...]`, ...) are metadata.
+        }
+        if starts.is_empty() && !rest.is_empty() {
+            return Err(format!("line {}: text without a timestamp", i + 1));
+        }
+        let text = strip_tags(rest);
+        lines.extend(starts.into_iter().map(|s| (s, text.clone())));
+    }
+
+    lines.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let ends: Vec<Option<f64>> = lines.iter().skip(1).map(|(s, _)| Some(*s))
+        .chain(std::iter::once(None))
+        .collect();
+    Ok(lines.into_iter()
+        .zip(ends)
+        // Empty lines only end the previous line.
+        .filter(|((_, text), _)| !text.is_empty())
+        .map(|((start, text), end)| Cue {
+            start: (start - offset).max(0.0),
+            end: end.map(|e| (e - offset).max(0.0)),
+            text,
+        })
+        .collect())
+}
+
+// Parse an SRT or WebVTT file. Both consist of blocks separated by
+// blank lines, where each cue block has a `start --> end` timing line.
+fn parse_blocks(content: &str, format: SubtitleFormat) -> Result<Vec<Cue>, String> {
+    let mut cues = Vec::new();
+    let mut lines = content.lines().enumerate().peekable();
+
+    // Skip the `WEBVTT` header block.
+    if format == SubtitleFormat::WebVtt {
+        for (_, line) in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+    }
+
+    while lines.peek().is_some() {
+        let block: Vec<(usize, &str)> = lines.by_ref()
+            .skip_while(|(_, l)| l.trim().is_empty())
+            .take_while(|(_, l)| !l.trim().is_empty())
+            .collect();
+        let Some(&(first_no, first)) = block.first() else {
+            break;
+        };
+        if format == SubtitleFormat::WebVtt
+            && ["NOTE", "STYLE", "REGION"].iter().any(|k| first.starts_with(k))
+        {
+            continue;
+        }
+
+        // The timing line may be preceded by a cue identifier.
+        let timing_pos = block.iter()
+            .take(2)
+            .position(|(_, l)| l.contains("-->"))
+            .ok_or_else(|| format!("line {}: missing `start --> end` timing", first_no + 1))?;
+        let (line_no, timing) = block[timing_pos];
+        let (start, end) = parse_timing(timing)
+            .ok_or_else(|| format!("line {}: invalid timing `{}`", line_no + 1, timing.trim()))?;
+        if end <= start {
+            return Err(format!("line {}: cue ends before it starts", line_no + 1));
+        }
+
+        let text = block[timing_pos + 1..].iter()
+            .map(|(_, l)| match format {
+                SubtitleFormat::WebVtt => strip_tags(l),
+                _ => l.trim().to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !text.is_empty() {
+            cues.push(Cue { start, end: Some(end), text });
+        }
+    }
+    Ok(cues)
+}
+
+// Parse a `00:01:02,500 --> 00:01:04.000 [settings]` timing line.
+fn parse_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    // WebVTT allows cue settings after the end timestamp.
+    let end = rest.split_whitespace().next()?;
+    let parse = |s: &str| parse_timestamp(&s.trim().replace(',', "."));
+    Some((parse(start)?, parse(end)?))
+}
+
+// Remove markup like `<v Singer>` or LRC word timings (`<00:12.30>`) from text.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {},
+        }
+    }
+    out.trim().to_owned()
+}
+
+// Write the cues as an SRT file to be read by `ffmpeg`.
+// Cue times are shifted by `offset` seconds (e.g. to account for
+// trimmed audio) and cues outside of `0..duration` are dropped.
+pub fn to_srt(cues: &[Cue], offset: f64, duration: f64) -> String {
+    let mut srt = String::new();
+    let mut index = 1;
+    for cue in cues {
+        let start = (cue.start - offset).max(0.0);
+        let end = cue.end.map_or(duration, |e| e - offset).min(duration);
+        if end <= start {
+            continue;
+        }
+        // Writing to a `String` never fails.
+        let _ = write!(
+            srt,
+            "{index}\n{} --> {}\n{}\n\n",
+            srt_timestamp(start),
+            srt_timestamp(end),
+            cue.text,
+        );
+        index += 1;
+    }
+    srt
+}
+
+fn srt_timestamp(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+    )
+}
@@ -0,0 +1,51 @@
+use tokio::sync::watch;
+
+// Handle to find out whether the process was asked to shut down.
+// All clones of a handle observe the same shutdown.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    // Create a handle which is triggered on SIGTERM or SIGINT (Ctrl-C).
+    pub fn on_signals() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            tracing::info!("Received shutdown signal");
+            let _ = sender.send(true);
+            // Keep the sender alive so receivers don't see it closing.
+            std::future::pending::<()>().await;
+        });
+        Self { receiver }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Wait until shutdown is triggered.
+    pub async fn triggered(&mut self) {
+        // An error means the sender is gone and shutdown can't be triggered anymore.
+        if self.receiver.wait_for(|triggered| *triggered).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())
+        .expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c().await.expect("Failed to install Ctrl-C handler");
+}
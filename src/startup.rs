@@ -1,9 +1,9 @@
 use std::net::TcpListener;
 use actix_web::{web, App, HttpServer};
-use actix_web::dev::Server;
+use actix_web::dev::{Server, ServerHandle};
 use tracing_actix_web::TracingLogger;
 use crate::routes;
-use crate::configuration::Settings;
+use crate::configuration::{RunMode, Settings};
 use secrecy::{Secret, ExposeSecret};
 use mobc::Pool;
 use mobc_redis::RedisConnectionManager;
@@ -12,6 +12,11 @@ use tera::Tera;
 
 use crate::RedisPool;
 use crate::content_length_limit::ContentLengthLimit;
+use crate::retention::Retention;
+use crate::ffprobe::Ffprobe;
+use crate::progress_events::ProgressEvents;
+use crate::download_link::DownloadLinks;
+use crate::metrics::{self, RequestMetrics};
 
 pub struct Application {
     port: u16,
@@ -20,6 +25,9 @@ pub struct Application {
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        let progress_events = ProgressEvents::new(
+            redis::Client::open(configuration.redis_uri.expose_secret().as_ref())?
+        );
         let redis_pool = get_redis_pool(configuration.redis_uri).await?;
 
         let tera = Tera::new("templates/**/*").expect("Failed to load page templates");
@@ -35,6 +43,18 @@ impl Application {
             listener,
             redis_pool,
             tera,
+            configuration.application.admin_token,
+            DownloadLinks::new(configuration.application.download_secret),
+            Retention::new(
+                configuration.render_worker.lifetime,
+                configuration.render_worker.max_lifetime,
+            ),
+            progress_events,
+            Ffprobe::next_to(
+                &configuration.render_worker.ffmpeg_path,
+                configuration.render_worker.limits.clone(),
+            ),
+            configuration.run_mode,
         ).await?;
 
         Ok(Self{ port, server })
@@ -44,6 +64,13 @@ impl Application {
         self.port
     }
 
+    // Handle to gracefully stop the server. Stopping it closes the
+    // listener, so no new uploads are accepted, and waits for
+    // requests which are still in progress.
+    pub fn handle(&self) -> ServerHandle {
+        self.server.handle()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -57,26 +84,58 @@ pub async fn get_redis_pool(
     Ok(Pool::builder().max_open(100).build(manager))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     redis_pool: RedisPool,
     tera: Tera,
+    admin_token: Option<Secret<String>>,
+    download_links: DownloadLinks,
+    retention: Retention,
+    progress_events: ProgressEvents,
+    ffprobe: Ffprobe,
+    run_mode: RunMode,
 ) -> Result<Server, anyhow::Error> {
     let redis_pool = web::Data::new(redis_pool);
     let tera = web::Data::new(tera);
+    let admin_token = web::Data::new(routes::AdminToken(admin_token));
+    let download_links = web::Data::new(download_links);
+    let retention = web::Data::new(retention);
+    let progress_events = web::Data::new(progress_events);
+    let ffprobe = web::Data::new(ffprobe);
+    let run_mode = web::Data::new(run_mode);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(RequestMetrics)
             .wrap(TracingLogger::default())
             .wrap(ContentLengthLimit::default())
             .route("/health_check", web::get().to(routes::health_check))
+            .route("/metrics", web::get().to(metrics::metrics))
             .route("/", web::get().to(routes::save_file_page))
             .route("/save", web::post().to(routes::save_file))
             .service(routes::load_file_page)  // Page to download any file
-            .service(routes::load_file)  // GET any file by ID
+            .service(routes::load_file)  // GET a finished video by signed link
+            .service(routes::load_poster)  // GET the poster of a video
+            .service(routes::load_archive)  // GET several finished videos as ZIP
+            .service(routes::start_batch)  // Start a batch of uploads
             .service(routes::check_resource_state)  // Check if a file is ready
+            .service(routes::progress_events)  // Push the progress of a file
+            .service(routes::delete_file)  // Delete an upload right away
+            .service(routes::extend_file)  // Keep a finished video longer
+            .service(routes::list_workers)  // Admin: list render workers
+            .service(routes::list_webhook_attempts)  // Admin: list webhook attempts
+            .service(routes::api_v1())  // JSON API under /api/v1
             .app_data(redis_pool.clone())
             .app_data(tera.clone())
+            .app_data(admin_token.clone())
+            .app_data(download_links.clone())
+            .app_data(retention.clone())
+            .app_data(progress_events.clone())
+            .app_data(ffprobe.clone())
+            .app_data(run_mode.clone())
     })
+    // Shutdown signals are handled by the caller via `Application::handle`.
+    .disable_signals()
     .listen(listener)?
     .run();
 
@@ -0,0 +1,161 @@
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use redis::AsyncCommands;
+use serde::{Serialize, Deserialize};
+use std::ops::{DerefMut, Range};
+
+use crate::{RedisConn, RedisPool};
+
+// Rendered videos can be far bigger than what fits in a single redis
+// string (512MB) or what should be held in memory at once. They are
+// therefore stored as a redis list of fixed-size chunks under the
+// video's key. Only the last chunk may be shorter than `CHUNK_SIZE`.
+
+// Size (in bytes) of a single chunk of video data.
+pub const CHUNK_SIZE: usize = 1 << 20;  // 1MB
+
+// Amount of time (in seconds) a video which is still being written
+// is kept after its last chunk was appended. This ensures videos
+// of crashed renders are deleted eventually.
+const PARTIAL_VIDEO_LIFETIME: usize = 60 * 60;
+
+// Prefix of the redis hashes holding the metadata and poster of a video.
+// They share the TTL of their video.
+pub const INFO_KEY_PREFIX: &str = "video-info";
+
+pub fn info_key(video_key: &str) -> String {
+    format!("{INFO_KEY_PREFIX}:{video_key}")
+}
+
+// Metadata of a rendered video.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VideoInfo {
+    // Duration in seconds.
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // Size in bytes.
+    pub size: u64,
+}
+
+// Append a chunk of data to the video stored under `key`.
+pub async fn append_chunk(
+    conn: &mut RedisConn,
+    key: &str,
+    chunk: &[u8],
+) -> redis::RedisResult<()> {
+    redis::pipe()
+        .atomic()
+        .rpush(key, chunk).ignore()
+        .expire(key, PARTIAL_VIDEO_LIFETIME).ignore()
+        .query_async(conn.deref_mut()).await
+}
+
+// Size (in bytes) of the video stored under `key`, or `None` if there is no such video.
+pub async fn video_size(
+    conn: &mut RedisConn,
+    key: &str,
+) -> redis::RedisResult<Option<u64>> {
+    // All chunks but the last one are full.
+    let (chunks, last): (u64, Option<Vec<u8>>) = redis::pipe()
+        .atomic()
+        .llen(key)
+        .lindex(key, -1)
+        .query_async(conn.deref_mut()).await?;
+    Ok(last.map(|last| (chunks - 1) * CHUNK_SIZE as u64 + last.len() as u64))
+}
+
+// Split the byte range `start..=end` of a video into the indices of the chunks
+// holding it and the range of bytes to take from each of these chunks.
+pub fn chunk_slices(start: u64, end: u64) -> impl Iterator<Item = (u64, Range<usize>)> {
+    let chunk_size = CHUNK_SIZE as u64;
+    (start / chunk_size..=end / chunk_size).map(move |index| {
+        let chunk_start = index * chunk_size;
+        let from = start.saturating_sub(chunk_start);
+        let to = (end - chunk_start + 1).min(chunk_size);
+        (index, from as usize..to as usize)
+    })
+}
+
+// Stream the bytes `start..=end` of the video stored under `key` one chunk
+// at a time, so only a single chunk is held in memory.
+pub fn stream_video(
+    redis_pool: RedisPool,
+    key: String,
+    start: u64,
+    end: u64,
+) -> impl Stream<Item = redis::RedisResult<Bytes>> {
+    let slices = chunk_slices(start, end);
+    futures_util::stream::try_unfold((redis_pool, key, slices), |(redis_pool, key, mut slices)| async move {
+        let Some((index, range)) = slices.next() else {
+            return Ok(None);
+        };
+        let mut conn = chunk_conn(&redis_pool).await?;
+        let chunk = video_chunk(&mut conn, &key, index, range).await?;
+        Ok(Some((chunk, (redis_pool, key, slices))))
+    })
+}
+
+// Acquire a connection to read a single chunk. Streams take one per chunk,
+// so a slow download doesn't hold on to a pooled connection throughout.
+pub async fn chunk_conn(redis_pool: &RedisPool) -> redis::RedisResult<RedisConn> {
+    redis_pool.get().await.map_err(|e| match e {
+        mobc::Error::Inner(e) => e,
+        e => redis::RedisError::from((
+            redis::ErrorKind::IoError, "failed to acquire redis connection", e.to_string(),
+        )),
+    })
+}
+
+// Read the bytes in `range` of the chunk at `index` of the video stored under `key`.
+pub async fn video_chunk(
+    conn: &mut RedisConn,
+    key: &str,
+    index: u64,
+    range: Range<usize>,
+) -> redis::RedisResult<Bytes> {
+    let chunk: Option<Vec<u8>> = conn.lindex(key, index as isize).await?;
+    // The video might expire while it's being streamed.
+    let chunk = chunk.ok_or_else(|| redis::RedisError::from(
+        (redis::ErrorKind::ResponseError, "video chunk is missing")
+    ))?;
+    let range = range.start.min(chunk.len())..range.end.min(chunk.len());
+    Ok(Bytes::from(chunk).slice(range))
+}
+
+// Store the metadata and poster image of the video under `video_key`.
+// They expire after `ttl` seconds.
+pub async fn store_info(
+    conn: &mut RedisConn,
+    video_key: &str,
+    info: &VideoInfo,
+    poster: Option<&[u8]>,
+    ttl: usize,
+) -> redis::RedisResult<()> {
+    let key = info_key(video_key);
+    let info = serde_json::to_string(info).expect("video info is serializable");
+    let mut pipe = redis::pipe();
+    pipe.hset(&key, "info", info).ignore();
+    if let Some(poster) = poster {
+        pipe.hset(&key, "poster", poster).ignore();
+    }
+    pipe.expire(&key, ttl).ignore()
+        .query_async(conn.deref_mut()).await
+}
+
+// Load the metadata of the video under `video_key`, if there is any.
+pub async fn video_info(
+    conn: &mut RedisConn,
+    video_key: &str,
+) -> redis::RedisResult<Option<VideoInfo>> {
+    let info: Option<String> = conn.hget(info_key(video_key), "info").await?;
+    Ok(info.and_then(|info| serde_json::from_str(&info).ok()))
+}
+
+// Load the poster image (JPEG) of the video under `video_key`, if there is one.
+pub async fn video_poster(
+    conn: &mut RedisConn,
+    video_key: &str,
+) -> redis::RedisResult<Option<Vec<u8>>> {
+    conn.hget(info_key(video_key), "poster").await
+}
@@ -0,0 +1,398 @@
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use crate::RedisConn;
+use crate::priority::Priority;
+use crate::routes::{RenderOptions, RenderTask};
+use crate::storage::VideoInfo;
+use crate::utils::unix_now;
+
+// Every upload has a task record, a redis hash holding the state of its task
+// along with when it changed, how often rendering was attempted, why it last
+// failed, the options it's rendered with and the video it resulted in.
+//
+//   queued ──> rendering ──> ready ──> expired
+//     │  <──────  │  │         │
+//     │           │  └──> failed ──> expired
+//     └───────────┴──────────┴─────────┴──> cancelled
+//
+// A queued task can also become ready right away if its video is cached.
+// Tasks are rendered again if their worker dies, so a rendering task can
+// start rendering again. State changes which aren't in the graph are refused.
+// Cancelled and expired tasks are kept for `GRACE_PERIOD` before their record
+// is deleted, so their uploads are reported as gone instead of unknown.
+
+// Prefix of the redis keys of the task records.
+pub const TASK_KEY_PREFIX: &str = "task";
+// Amount of time (in seconds) the record of an unfinished task is kept. It's
+// refreshed on every state change, so only records of lost tasks expire.
+pub const ACTIVE_TTL: usize = 7 * 24 * 60 * 60;
+// Amount of time (in seconds) the record of an upload is kept once its video
+// or failure has expired or it was cancelled.
+pub const GRACE_PERIOD: usize = 24 * 60 * 60;
+
+// Redis key of the record of the task of `target`.
+pub fn task_key(target: Uuid) -> String {
+    format!("{TASK_KEY_PREFIX}:{target}")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Queued,
+    Rendering,
+    Ready,
+    Failed,
+    Cancelled,
+    Expired,
+}
+
+impl TaskStatus {
+    pub const ALL: [TaskStatus; 6] = [
+        TaskStatus::Queued,
+        TaskStatus::Rendering,
+        TaskStatus::Ready,
+        TaskStatus::Failed,
+        TaskStatus::Cancelled,
+        TaskStatus::Expired,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Queued => "queued",
+            TaskStatus::Rendering => "rendering",
+            TaskStatus::Ready => "ready",
+            TaskStatus::Failed => "failed",
+            TaskStatus::Cancelled => "cancelled",
+            TaskStatus::Expired => "expired",
+        }
+    }
+
+    // Whether a task in this state may change to `next`.
+    pub fn can_become(&self, next: TaskStatus) -> bool {
+        use TaskStatus::*;
+        matches!(
+            (self, next),
+            (Queued, Rendering | Ready | Failed | Cancelled)
+                | (Rendering, Queued | Rendering | Ready | Failed | Cancelled)
+                | (Ready, Expired | Cancelled)
+                | (Failed, Expired | Cancelled)
+        )
+    }
+
+    // Whether the state can't change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, TaskStatus::Cancelled | TaskStatus::Expired)
+    }
+}
+
+impl TryFrom<&str> for TaskStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::ALL.into_iter()
+            .find(|status| status.as_str() == value)
+            .ok_or_else(|| format!("{value} is not a task status"))
+    }
+}
+
+// The record of a task as stored in redis. Timestamps are unix timestamps (in seconds).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskRecord {
+    pub status: TaskStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    // When rendering last started.
+    pub started_at: Option<u64>,
+    // When the task became ready, failed or was cancelled.
+    pub finished_at: Option<u64>,
+    // Number of times a worker started rendering the task.
+    pub attempts: u32,
+    // Why the last attempt failed, if it did.
+    pub last_error: Option<String>,
+    pub options: RenderOptions,
+    pub priority: Priority,
+    // Name of the video file when it's downloaded.
+    pub filename: Option<String>,
+    // Metadata of the finished video.
+    pub output: Option<VideoInfo>,
+    // When the finished video or the failure expires.
+    pub expires_at: Option<u64>,
+    // ID of the API key the upload was made with. Records are shown to
+    // anyone holding the progress ID, so it's never sent along.
+    #[serde(skip)]
+    pub api_key: Option<Uuid>,
+    // Redis key of the finished video.
+    #[serde(skip)]
+    pub video: Option<String>,
+}
+
+impl TaskRecord {
+    // Record of a task which was just queued.
+    pub fn queued(task: &RenderTask) -> Self {
+        let now = unix_now();
+        Self {
+            status: TaskStatus::Queued,
+            created_at: now,
+            updated_at: now,
+            started_at: None,
+            finished_at: None,
+            attempts: 0,
+            last_error: None,
+            options: task.options.clone(),
+            priority: task.priority,
+            filename: task.filename.clone(),
+            output: None,
+            expires_at: None,
+            api_key: task.api_key,
+            video: None,
+        }
+    }
+
+    // Whether the video or the failure of the task outlived its lifetime.
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.status, TaskStatus::Ready | TaskStatus::Failed)
+            && self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("status", self.status.as_str().to_owned()),
+            ("created_at", self.created_at.to_string()),
+            ("updated_at", self.updated_at.to_string()),
+            ("attempts", self.attempts.to_string()),
+            ("options", serde_json::to_string(&self.options).expect("options are serializable")),
+            ("priority", self.priority.as_str().to_owned()),
+        ];
+        let optional = [
+            ("started_at", self.started_at.map(|t| t.to_string())),
+            ("finished_at", self.finished_at.map(|t| t.to_string())),
+            ("last_error", self.last_error.clone()),
+            ("filename", self.filename.clone()),
+            ("output", self.output.as_ref().and_then(|o| serde_json::to_string(o).ok())),
+            ("expires_at", self.expires_at.map(|t| t.to_string())),
+            ("api_key", self.api_key.map(|id| id.to_string())),
+            ("video", self.video.clone()),
+        ];
+        fields.extend(optional.into_iter().filter_map(|(name, value)| Some((name, value?))));
+        fields
+    }
+
+    // Read a record from the fields of its hash. Returns `None` if
+    // there are none, or if the status is missing or unknown.
+    fn from_fields(mut fields: HashMap<String, String>) -> Option<Self> {
+        let status = TaskStatus::try_from(fields.get("status")?.as_str()).ok()?;
+        let number = |name: &str| fields.get(name).and_then(|v| v.parse::<u64>().ok());
+        let (created_at, updated_at) = (number("created_at").unwrap_or(0), number("updated_at").unwrap_or(0));
+        let (started_at, finished_at, expires_at) = (number("started_at"), number("finished_at"), number("expires_at"));
+        let attempts = number("attempts").unwrap_or(0) as u32;
+        Some(Self {
+            status,
+            created_at,
+            updated_at,
+            started_at,
+            finished_at,
+            attempts,
+            last_error: fields.remove("last_error"),
+            options: fields.get("options")
+                .and_then(|o| serde_json::from_str(o).ok())
+                .unwrap_or_default(),
+            priority: fields.remove("priority")
+                .and_then(|p| Priority::try_from(p).ok())
+                .unwrap_or_default(),
+            filename: fields.remove("filename"),
+            output: fields.get("output").and_then(|o| serde_json::from_str(o).ok()),
+            expires_at,
+            api_key: fields.get("api_key").and_then(|id| Uuid::parse_str(id).ok()),
+            video: fields.remove("video"),
+        })
+    }
+}
+
+// Load the record of the task of `target`, if there is one.
+pub async fn load(conn: &mut RedisConn, target: Uuid) -> redis::RedisResult<Option<TaskRecord>> {
+    let fields: HashMap<String, String> = conn.hgetall(task_key(target)).await?;
+    Ok(TaskRecord::from_fields(fields))
+}
+
+// Add storing `record` as the record of the task of `target` to `pipe`.
+// This is not atomic, so it can be part of a transaction.
+pub fn create_in(pipe: &mut redis::Pipeline, target: Uuid, record: &TaskRecord) {
+    let key = task_key(target);
+    pipe.del(&key).ignore()
+        .hset_multiple(&key, &record.fields()).ignore()
+        .expire(&key, ACTIVE_TTL).ignore();
+}
+
+// A change of the state of a task.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    to: TaskStatus,
+    fields: Vec<(&'static str, String)>,
+    // New TTL (in seconds) of the record. The TTL is kept if this is `None`.
+    ttl: Option<usize>,
+    // Whether this starts another attempt at rendering the task.
+    counts_attempt: bool,
+}
+
+impl Transition {
+    fn new(to: TaskStatus) -> Self {
+        let now = unix_now().to_string();
+        let mut fields = vec![("status", to.as_str().to_owned()), ("updated_at", now.clone())];
+        if matches!(to, TaskStatus::Ready | TaskStatus::Failed | TaskStatus::Cancelled) {
+            fields.push(("finished_at", now));
+        }
+        Self { to, fields, ttl: None, counts_attempt: false }
+    }
+
+    // A worker started rendering the task.
+    pub fn rendering() -> Self {
+        let mut transition = Self::new(TaskStatus::Rendering);
+        transition.fields.push(("started_at", unix_now().to_string()));
+        transition.ttl = Some(ACTIVE_TTL);
+        transition.counts_attempt = true;
+        transition
+    }
+
+    // The task was put back into the queue, because rendering it failed
+    // with `error` or its worker stopped.
+    pub fn queued(error: Option<&str>) -> Self {
+        let mut transition = Self::new(TaskStatus::Queued);
+        if let Some(error) = error {
+            transition.fields.push(("last_error", error.to_owned()));
+        }
+        transition.ttl = Some(ACTIVE_TTL);
+        transition
+    }
+
+    // The task's video `video_key` is ready and expires in `ttl` seconds.
+    pub fn ready(video_key: &str, output: Option<&VideoInfo>, ttl: usize) -> Self {
+        let mut transition = Self::new(TaskStatus::Ready);
+        transition.fields.push(("video", video_key.to_owned()));
+        if let Some(output) = output.and_then(|o| serde_json::to_string(o).ok()) {
+            transition.fields.push(("output", output));
+        }
+        transition.expires_in(ttl)
+    }
+
+    // The task can never be rendered because of `reason`. The failure
+    // is reported for `ttl` seconds, just like a video would be kept.
+    pub fn failed(reason: &str, ttl: usize) -> Self {
+        let mut transition = Self::new(TaskStatus::Failed);
+        transition.fields.push(("last_error", reason.to_owned()));
+        transition.expires_in(ttl)
+    }
+
+    // The upload was deleted.
+    pub fn cancelled() -> Self {
+        let mut transition = Self::new(TaskStatus::Cancelled);
+        transition.ttl = Some(GRACE_PERIOD);
+        transition
+    }
+
+    // The video or the failure of the task has expired. The record itself
+    // already expires `GRACE_PERIOD` after that.
+    pub fn expired() -> Self {
+        Self::new(TaskStatus::Expired)
+    }
+
+    fn expires_in(mut self, ttl: usize) -> Self {
+        self.fields.push(("expires_at", (unix_now() + ttl as u64).to_string()));
+        self.ttl = Some(ttl + GRACE_PERIOD);
+        self
+    }
+}
+
+// Lua function changing the state of the task record under `key` unless the
+// change is not allowed. Its arguments are added by `transition_args`,
+// starting at `ARGV[offset]`. Returns 1 if the record was changed. Scripts
+// which change more than the record along with it embed this function.
+pub(crate) const TRANSITION_LUA: &str = r"
+    local function apply_transition(key, offset)
+        local status = redis.call('HGET', key, 'status')
+        if not status then
+            return 0
+        end
+        local from = tonumber(ARGV[offset + 2])
+        local allowed = false
+        for i = offset + 3, offset + 2 + from do
+            if ARGV[i] == status then
+                allowed = true
+            end
+        end
+        if not allowed then
+            return 0
+        end
+        redis.call('HSET', key, unpack(ARGV, offset + 3 + from))
+        if ARGV[offset + 1] == '1' then
+            redis.call('HINCRBY', key, 'attempts', 1)
+        end
+        if tonumber(ARGV[offset]) > 0 then
+            redis.call('EXPIRE', key, ARGV[offset])
+        end
+        return 1
+    end
+";
+
+// Add the arguments of `apply_transition` (see `TRANSITION_LUA`) for
+// `transition` to `invocation`. They must be its last arguments.
+pub(crate) fn transition_args(invocation: &mut redis::ScriptInvocation, transition: &Transition) {
+    let from: Vec<&str> = TaskStatus::ALL.into_iter()
+        .filter(|status| status.can_become(transition.to))
+        .map(|status| status.as_str())
+        .collect();
+    invocation
+        .arg(transition.ttl.unwrap_or(0))
+        .arg(u8::from(transition.counts_attempt))
+        .arg(from.len())
+        .arg(from);
+    for (name, value) in &transition.fields {
+        invocation.arg(*name).arg(value);
+    }
+}
+
+// Change the state of the task of `target` unless the change is not allowed.
+// Returns whether it was changed. Tasks without a record can't be changed.
+pub async fn transition(
+    conn: &mut RedisConn,
+    target: Uuid,
+    transition: &Transition,
+) -> redis::RedisResult<bool> {
+    // This is a script so the state can't change in between
+    // checking and changing it.
+    let script = redis::Script::new(&format!("{TRANSITION_LUA}
+        return apply_transition(KEYS[1], 1)
+    "));
+    let mut invocation = script.prepare_invoke();
+    invocation.key(task_key(target));
+    transition_args(&mut invocation, transition);
+    let changed: u8 = invocation.invoke_async(conn.deref_mut()).await?;
+    Ok(changed == 1)
+}
+
+// Add changing the state of the task of `target` from `current` to `pipe`.
+// This is meant for transactions which watch the record, so it can't change
+// before the transaction is executed. Returns `false` without adding anything
+// if the change is not allowed.
+pub fn transition_in(
+    pipe: &mut redis::Pipeline,
+    target: Uuid,
+    current: TaskStatus,
+    transition: &Transition,
+) -> bool {
+    if !current.can_become(transition.to) {
+        return false;
+    }
+    let key = task_key(target);
+    pipe.hset_multiple(&key, &transition.fields).ignore();
+    if transition.counts_attempt {
+        pipe.hincr(&key, "attempts", 1).ignore();
+    }
+    if let Some(ttl) = transition.ttl {
+        pipe.expire(&key, ttl).ignore();
+    }
+    true
+}
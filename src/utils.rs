@@ -40,6 +40,28 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+/// Current time as a unix timestamp (in seconds).
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date,
+/// see http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
 /// Spawn a blocking task in a new thread without switching the active tracing span.
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
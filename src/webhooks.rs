@@ -0,0 +1,387 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use redis::AsyncCommands;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::DerefMut;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{RedisConn, RedisPool};
+use crate::configuration::WebhookSettings;
+use crate::utils::unix_now;
+
+// Uploads can name a callback URL, which is sent a webhook once the upload's
+// task is ready, failed or was cancelled by deleting the upload. Webhooks are
+// queued in redis in the same step which changes the task's state, and render
+// workers deliver them in the background. Failed deliveries are retried with
+// an exponential backoff, and every attempt is recorded for the upload.
+//
+// Payloads are JSON, signed with HMAC-SHA256 over `<timestamp>.<body>`. The
+// signature is sent as `X-Backdrop-Signature: t=<timestamp>,v1=<hex>`.
+//
+// Callback URLs may only reach public addresses, so uploaders can't make
+// workers send requests to internal services. Hosts are resolved before
+// every attempt and the request is pinned to the checked addresses.
+
+// Redis sorted set of the deliveries, scored by when they are due.
+pub const DELIVERY_QUEUE_KEY: &str = "webhook-deliveries";
+// Prefix of the redis keys holding the callback URL of a pending upload.
+const CALLBACK_KEY_PREFIX: &str = "webhook-callback";
+// Prefix of the redis lists recording the delivery attempts of an upload.
+const ATTEMPTS_KEY_PREFIX: &str = "webhook-attempts";
+// Amount of time (in seconds) delivery attempts are recorded for.
+const ATTEMPTS_TTL: usize = 7 * 24 * 60 * 60;
+// Longest delay (in seconds) between two attempts.
+const MAX_RETRY_DELAY: u64 = 60 * 60;
+// Number of deliveries a worker claims at once.
+const CLAIM_BATCH: u64 = 10;
+// Amount of time (in seconds) between looking for due deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const SIGNATURE_HEADER: &str = "X-Backdrop-Signature";
+pub const DELIVERY_HEADER: &str = "X-Backdrop-Delivery";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Redis key of the callback URL of the upload of `target` while it's pending.
+pub fn callback_key(target: Uuid) -> String {
+    format!("{CALLBACK_KEY_PREFIX}:{target}")
+}
+
+fn attempts_key(target: Uuid) -> String {
+    format!("{ATTEMPTS_KEY_PREFIX}:{target}")
+}
+
+// Check a callback URL given by a user. Hosts which are names are only
+// resolved when webhooks are sent, since their addresses can change.
+pub fn parse_callback_url(value: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(value.trim())
+        .map_err(|e| format!("callback URL `{value}` is invalid: {e}"))?;
+    let Some(host) = url.host_str().filter(|_| matches!(url.scheme(), "http" | "https")) else {
+        return Err(format!("callback URL `{value}` is not an HTTP(S) URL"));
+    };
+    let is_local_name = host == "localhost" || host.ends_with(".localhost");
+    if is_local_name || ip_literal(host).is_some_and(|ip| !is_public_address(ip)) {
+        return Err(format!("callback URL `{value}` doesn't point to a public address"));
+    }
+    Ok(url.to_string())
+}
+
+// The address of a URL host which is an IP address.
+fn ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+// Whether `ip` is reachable on the internet, rather than being a loopback,
+// private, link-local or otherwise reserved address.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // 0.0.0.0/8 is "this network", 100.64.0.0/10 is shared by carriers.
+            let reserved = first == 0 || (first == 100 && second & 0xc0 == 64);
+            !(reserved || ip.is_private() || ip.is_loopback() || ip.is_link_local()
+                || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation())
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(ip.into()),
+            None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                || ip.is_unique_local() || ip.is_unicast_link_local()),
+        },
+    }
+}
+
+// States of a task which are reported by webhooks.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Ready,
+    Failed,
+    Cancelled,
+}
+
+// A webhook waiting to be delivered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    // Identifies the webhook across attempts, so receivers can ignore duplicates.
+    pub id: Uuid,
+    pub url: String,
+    pub target: Uuid,
+    pub event: WebhookEvent,
+    // Why the task failed, if it did.
+    #[serde(default)]
+    pub reason: Option<String>,
+    // Unix timestamp (in seconds) at which the task reached the state.
+    pub occurred_at: u64,
+    // Number of attempts made so far.
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl Delivery {
+    pub fn new(url: String, target: Uuid, event: WebhookEvent, reason: Option<String>) -> Self {
+        Self { id: Uuid::new_v4(), url, target, event, reason, occurred_at: unix_now(), attempts: 0 }
+    }
+
+    // Body of the webhook.
+    pub fn payload(&self) -> Payload<'_> {
+        Payload {
+            id: self.id,
+            event: self.event,
+            progress_id: self.target,
+            occurred_at: self.occurred_at,
+            reason: self.reason.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    pub id: Uuid,
+    pub event: WebhookEvent,
+    pub progress_id: Uuid,
+    pub occurred_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+}
+
+// Queue `delivery` in `pipe` and forget the callback URL of its upload, which is
+// only kept to report a cancellation. Meant to be part of the transaction which
+// moves the task into the delivered state.
+pub fn enqueue_in(pipe: &mut redis::Pipeline, delivery: &Delivery) {
+    let raw = serde_json::to_string(delivery).expect("deliveries are serializable");
+    pipe.zadd(DELIVERY_QUEUE_KEY, raw, unix_now()).ignore()
+        .del(callback_key(delivery.target)).ignore();
+}
+
+// Signature of a webhook `body` sent at the unix timestamp `timestamp`.
+pub fn signature(secret: &Secret<String>, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Result of a single delivery attempt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub delivery: Uuid,
+    pub event: WebhookEvent,
+    pub url: String,
+    // Number of the attempt, starting at 1.
+    pub attempt: u32,
+    // Unix timestamp (in seconds) of the attempt.
+    pub at: u64,
+    // HTTP status the receiver answered with, if it answered.
+    #[serde(default)]
+    pub status: Option<u16>,
+    // Why the attempt failed, if it did.
+    #[serde(default)]
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+// Delivery attempts recorded for the upload of `target`, oldest first.
+pub async fn attempts(conn: &mut RedisConn, target: Uuid) -> anyhow::Result<Vec<Attempt>> {
+    let raw: Vec<String> = conn.lrange(attempts_key(target), 0, -1).await
+        .context("failed to query webhook attempts")?;
+    Ok(raw.iter().filter_map(|a| serde_json::from_str(a).ok()).collect())
+}
+
+// Sends webhooks.
+pub struct Webhooks {
+    client: reqwest::Client,
+    secret: Secret<String>,
+    allow_private_addresses: bool,
+    max_attempts: u32,
+    retry_delay: u64,
+    timeout: u64,
+}
+
+impl Webhooks {
+    pub fn new(settings: &WebhookSettings) -> anyhow::Result<Self> {
+        let client = client_builder(settings.timeout.into()).build()
+            .context("failed to build webhook client")?;
+        Ok(Self {
+            client,
+            secret: settings.secret.clone(),
+            allow_private_addresses: settings.allow_private_addresses,
+            max_attempts: settings.max_attempts.max(1),
+            retry_delay: settings.retry_delay.into(),
+            timeout: settings.timeout.into(),
+        })
+    }
+
+    // Send `delivery` once and report how it went.
+    pub async fn send(&self, delivery: &Delivery) -> Attempt {
+        let body = serde_json::to_vec(&delivery.payload()).expect("payloads are serializable");
+        let timestamp = unix_now();
+        let signature = signature(&self.secret, timestamp, &body);
+        let response = match self.client_for(&delivery.url).await {
+            Ok(client) => client.post(&delivery.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, format!("t={timestamp},v1={signature}"))
+                .header(DELIVERY_HEADER, delivery.id.to_string())
+                .body(body)
+                .send().await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        let (status, error) = match response {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("the receiver answered with {}", response.status())),
+            ),
+            Err(e) => (None, Some(e)),
+        };
+        Attempt {
+            delivery: delivery.id,
+            event: delivery.event,
+            url: delivery.url.clone(),
+            attempt: delivery.attempts + 1,
+            at: timestamp,
+            status,
+            delivered: error.is_none(),
+            error,
+        }
+    }
+
+    // Client to send a webhook to `url` with. Unless private addresses are
+    // allowed, the host is resolved here, and the client only connects to
+    // its addresses if they are all public.
+    async fn client_for(&self, url: &str) -> Result<reqwest::Client, String> {
+        if self.allow_private_addresses {
+            return Ok(self.client.clone());
+        }
+        let url = reqwest::Url::parse(url).map_err(|e| format!("invalid callback URL: {e}"))?;
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Err("the callback URL has no host".to_owned());
+        };
+        let addresses: Vec<SocketAddr> = match ip_literal(host) {
+            Some(ip) => vec![SocketAddr::new(ip, port)],
+            None => tokio::net::lookup_host((host, port)).await
+                .map_err(|e| format!("failed to resolve {host}: {e}"))?
+                .collect(),
+        };
+        if let Some(address) = addresses.iter().find(|a| !is_public_address(a.ip())) {
+            return Err(format!("{host} resolves to the non-public address {}", address.ip()));
+        }
+        if ip_literal(host).is_some() {
+            return Ok(self.client.clone());
+        }
+        client_builder(self.timeout)
+            .resolve_to_addrs(host, &addresses)
+            .build()
+            .map_err(|e| format!("failed to build webhook client: {e}"))
+    }
+
+    // Seconds to wait before the next attempt after `attempts` failed ones,
+    // or `None` if the webhook is given up on.
+    pub fn retry_delay(&self, attempts: u32) -> Option<u64> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Some(self.retry_delay.saturating_mul(factor).min(MAX_RETRY_DELAY))
+    }
+
+    // Keep delivering due webhooks. Errors are only logged, since
+    // claimed deliveries are sent again once their lease ends.
+    pub async fn run(&self, redis_pool: &RedisPool) -> anyhow::Result<()> {
+        loop {
+            match self.deliver_due(redis_pool).await {
+                Ok(0) => tokio::time::sleep(POLL_INTERVAL).await,
+                Ok(_) => {},
+                Err(e) => {
+                    tracing::warn!("Failed to deliver webhooks: {e:?}");
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                },
+            }
+        }
+    }
+
+    // Deliver the webhooks which are due. Returns how many there were.
+    async fn deliver_due(&self, redis_pool: &RedisPool) -> anyhow::Result<usize> {
+        let mut conn = redis_pool.get().await
+            .context("failed to acquire redis connection")?;
+        let due = self.claim(&mut conn).await?;
+        let count = due.len();
+        for raw in due {
+            self.deliver(&mut conn, raw).await?;
+        }
+        Ok(count)
+    }
+
+    // Take the due deliveries. They are pushed back by a lease instead of
+    // being removed, so they are sent again if this worker dies meanwhile.
+    // The lease lasts until every claimed delivery could have timed out,
+    // since they are sent one after the other.
+    async fn claim(&self, conn: &mut RedisConn) -> anyhow::Result<Vec<String>> {
+        let now = unix_now();
+        redis::Script::new(r"
+            local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[3])
+            for _, delivery in ipairs(due) do
+                redis.call('ZADD', KEYS[1], ARGV[2], delivery)
+            end
+            return due
+        ")
+            .key(DELIVERY_QUEUE_KEY)
+            .arg(now)
+            .arg(now + self.timeout * CLAIM_BATCH + 30)
+            .arg(CLAIM_BATCH)
+            .invoke_async(conn.deref_mut()).await
+            .context("failed to claim webhook deliveries")
+    }
+
+    // Send the claimed delivery `raw`, record the attempt and
+    // schedule the next one if it failed.
+    async fn deliver(&self, conn: &mut RedisConn, raw: String) -> anyhow::Result<()> {
+        let Ok(mut delivery) = serde_json::from_str::<Delivery>(&raw) else {
+            tracing::warn!("Dropping malformed webhook delivery {raw}");
+            let _: () = conn.zrem(DELIVERY_QUEUE_KEY, &raw).await
+                .context("failed to drop webhook delivery")?;
+            return Ok(());
+        };
+        let attempt = self.send(&delivery).await;
+        delivery.attempts = attempt.attempt;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zrem(DELIVERY_QUEUE_KEY, &raw).ignore()
+            .rpush(attempts_key(delivery.target), serde_json::to_string(&attempt)?).ignore()
+            .expire(attempts_key(delivery.target), ATTEMPTS_TTL).ignore();
+        if attempt.delivered {
+            tracing::info!("Delivered {:?} webhook of {}", delivery.event, delivery.target);
+        } else {
+            match self.retry_delay(delivery.attempts) {
+                Some(delay) => {
+                    tracing::warn!(
+                        "Webhook of {} failed ({:?}); retrying in {delay}s",
+                        delivery.target, attempt.error,
+                    );
+                    pipe.zadd(DELIVERY_QUEUE_KEY, serde_json::to_string(&delivery)?, unix_now() + delay)
+                        .ignore();
+                },
+                None => tracing::error!(
+                    "Giving up on webhook of {} after {} attempts ({:?})",
+                    delivery.target, delivery.attempts, attempt.error,
+                ),
+            }
+        }
+        pipe.query_async::<_, ()>(conn.deref_mut()).await
+            .context("failed to record webhook attempt")
+    }
+}
+
+fn client_builder(timeout: u64) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout))
+        .redirect(reqwest::redirect::Policy::none())
+}
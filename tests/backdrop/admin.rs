@@ -0,0 +1,108 @@
+use backdrop::capabilities::Features;
+use backdrop::purge::purge;
+use backdrop::render_worker::queue::{self, TaskLocation};
+use backdrop::render_worker::registry::{self, WorkerInfo};
+use backdrop::routes::RenderTask;
+use backdrop::task_state::{self, TaskRecord, TaskStatus, Transition};
+use backdrop::utils::unix_now;
+use mobc_redis::redis;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::helper::{get_redis_pool, TestApp, ADMIN_TOKEN};
+
+#[tokio::test]
+async fn admin_api_rejects_requests_without_token() {
+    let app = TestApp::spawn().await;
+
+    let response = app.get_route("admin/workers").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+}
+
+#[tokio::test]
+async fn admin_api_rejects_wrong_token() {
+    let app = TestApp::spawn().await;
+
+    for token in ["Bearer wrong-token", ADMIN_TOKEN, &format!("Basic {ADMIN_TOKEN}")] {
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/workers", app.address))
+            .header("Authorization", token)
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(response.status().as_u16(), 401, "accepted `{token}`");
+    }
+}
+
+fn worker_info() -> WorkerInfo {
+    WorkerInfo {
+        id: Uuid::new_v4(),
+        started_at: unix_now(),
+        current_task: None,
+        task_started_at: None,
+        last_heartbeat: unix_now(),
+        ffmpeg_version: "test".to_owned(),
+        features: Features::default(),
+    }
+}
+
+#[tokio::test]
+async fn admin_api_lists_alive_and_dead_workers() {
+    let app = TestApp::spawn().await;
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (alive, dead) = (worker_info(), worker_info());
+    registry::heartbeat(&mut conn, &alive, 60).await.unwrap();
+    registry::heartbeat(&mut conn, &dead, 1).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/workers", app.address))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let workers: serde_json::Value = response.json().await.unwrap();
+    let listed = |list: &str, id: Uuid| workers[list].as_array().unwrap().iter()
+        .any(|worker| worker == &serde_json::json!(id) || worker["id"] == serde_json::json!(id));
+    assert!(listed("alive", alive.id));
+    assert!(listed("dead", dead.id));
+    assert!(!listed("alive", dead.id));
+
+    for worker in [alive.id, dead.id] {
+        registry::deregister(&mut conn, worker).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tasks_of_dead_workers_are_requeued() {
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (worker_id, target) = (Uuid::new_v4(), Uuid::new_v4());
+    let task: RenderTask = serde_json::from_value(serde_json::json!({
+        "target": target,
+        "audio": Uuid::new_v4(),
+        "image": Uuid::new_v4(),
+    })).unwrap();
+
+    // The worker took the task from the queue and started rendering it.
+    let mut pipe = redis::pipe();
+    task_state::create_in(&mut pipe, target, &TaskRecord::queued(&task));
+    pipe.rpush(queue::processing_key(worker_id), serde_json::to_string(&task).unwrap()).ignore();
+    let _: () = pipe.query_async(&mut *conn).await.unwrap();
+    assert!(task_state::transition(&mut conn, target, &Transition::rendering()).await.unwrap());
+    let location = queue::locate(&mut conn, target, &[worker_id]).await.unwrap();
+    assert!(matches!(location, TaskLocation::Processing { worker_id: w, .. } if w == worker_id));
+
+    assert_eq!(1, queue::requeue_abandoned(&mut conn, worker_id).await.unwrap());
+    let location = queue::locate(&mut conn, target, &[worker_id]).await.unwrap();
+    assert!(matches!(location, TaskLocation::Queued { .. }));
+    let record = task_state::load(&mut conn, target).await.unwrap().unwrap();
+    assert_eq!(TaskStatus::Queued, record.status);
+    // The task is only requeued once, even if several workers reap the dead one.
+    assert_eq!(0, queue::requeue_abandoned(&mut conn, worker_id).await.unwrap());
+
+    assert!(purge(&mut conn, target).await.unwrap());
+}
@@ -0,0 +1,361 @@
+use backdrop::api_keys::{self, ApiKey, Quotas};
+use backdrop::archive;
+use backdrop::priority::Priority;
+use backdrop::routes::{RenderOptions, API_KEY_HEADER};
+use backdrop::storage;
+use backdrop::task_state::{self, TaskRecord, TaskStatus};
+use mobc_redis::redis;
+use uuid::Uuid;
+
+use crate::helper::{get_redis_pool, TestApp};
+
+// Read the code of a typed API error.
+async fn error_code(response: reqwest::Response) -> String {
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["error"]["code"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_jobs() {
+    let test_app = TestApp::spawn().await;
+
+    let response = test_app.get_route("api/v1/openapi.json").await;
+    assert!(response.status().is_success());
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("3.0.3", document["openapi"]);
+    for path in ["/jobs", "/jobs/{jobId}", "/jobs/{jobId}/output", "/batches"] {
+        assert!(document["paths"][path].is_object(), "missing path {path}");
+    }
+}
+
+#[tokio::test]
+async fn malformed_requests_get_typed_errors() {
+    let test_app = TestApp::spawn().await;
+
+    for route in ["api/v1/jobs/not-a-job", "api/v1/jobs/not-a-job/output", "api/v1/jobs?batch=not-a-batch"] {
+        let response = test_app.get_route(route).await;
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status(), "route: {route}");
+        assert_eq!("invalid_request", error_code(response).await);
+    }
+}
+
+#[tokio::test]
+async fn jobs_require_an_api_key() {
+    let test_app = TestApp::spawn().await;
+    let id = Uuid::new_v4();
+
+    for route in [format!("api/v1/jobs/{id}"), format!("api/v1/jobs?batch={id}"), "api/v1/usage".to_owned()] {
+        let response = test_app.get_route(&route).await;
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status(), "route: {route}");
+        assert_eq!("invalid_api_key", error_code(response).await);
+    }
+
+    // Even the uploader of a job needs a key to delete it through the API.
+    let url = format!("{}/api/v1/jobs/{id}", test_app.address);
+    let response = reqwest::Client::new().delete(&url)
+        .bearer_auth("not-the-token")
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!("invalid_api_key", error_code(response).await);
+}
+
+// Create an API key and return its ID and the key to send.
+async fn create_key(name: &str) -> (Uuid, String) {
+    let key = ApiKey::new(name.to_owned(), Priority::Normal, Quotas::default(), None);
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let token = api_keys::create(&mut conn, &key).await.unwrap();
+    (key.id, token)
+}
+
+#[tokio::test]
+async fn jobs_are_only_visible_to_their_api_key() {
+    let test_app = TestApp::spawn().await;
+    let (owner, owner_token) = create_key("owner").await;
+    let (_, other_token) = create_key("other").await;
+
+    // A queued job of the first key, in a batch.
+    let (target, batch) = (Uuid::new_v4(), Uuid::new_v4());
+    let record = TaskRecord {
+        status: TaskStatus::Queued,
+        created_at: 0,
+        updated_at: 0,
+        started_at: None,
+        finished_at: None,
+        attempts: 0,
+        last_error: None,
+        options: RenderOptions::default(),
+        priority: Priority::Normal,
+        filename: None,
+        output: None,
+        expires_at: None,
+        api_key: Some(owner),
+        video: None,
+    };
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let key = api_keys::load(&mut conn, owner).await.unwrap().unwrap();
+    api_keys::reserve(&mut conn, &key, target).await.unwrap();
+    let mut pipe = redis::pipe();
+    task_state::create_in(&mut pipe, target, &record);
+    archive::add_to_batch_in(&mut pipe, batch, target, 60);
+    let _: () = pipe.query_async(&mut *conn).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let get = |route: String, token: &str| client
+        .get(format!("{}/api/v1/{route}", test_app.address))
+        .header(API_KEY_HEADER, token)
+        .send();
+
+    let response = get(format!("jobs/{target}"), &owner_token).await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    let response = get(format!("jobs?batch={batch}"), &owner_token).await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1, body["jobs"].as_array().unwrap().len());
+
+    for route in [format!("jobs/{target}"), format!("jobs/{target}/output")] {
+        let response = get(route.clone(), &other_token).await.unwrap();
+        assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status(), "route: {route}");
+        assert_eq!("not_found", error_code(response).await);
+    }
+    let response = get(format!("jobs?batch={batch}"), &other_token).await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(0, body["jobs"].as_array().unwrap().len());
+
+    let response = client.delete(format!("{}/api/v1/jobs/{target}", test_app.address))
+        .header(API_KEY_HEADER, &other_token)
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+    assert!(task_state::load(&mut conn, target).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn jobs_are_listed_page_by_page() {
+    let test_app = TestApp::spawn().await;
+    let (owner, token) = create_key("listing").await;
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let key = api_keys::load(&mut conn, owner).await.unwrap().unwrap();
+
+    let mut targets = Vec::new();
+    for _ in 0..3 {
+        let target = Uuid::new_v4();
+        api_keys::reserve(&mut conn, &key, target).await.unwrap();
+        let record = TaskRecord {
+            status: TaskStatus::Queued,
+            created_at: 0,
+            updated_at: 0,
+            started_at: None,
+            finished_at: None,
+            attempts: 0,
+            last_error: None,
+            options: RenderOptions::default(),
+            priority: Priority::Normal,
+            filename: None,
+            output: None,
+            expires_at: None,
+            api_key: Some(owner),
+            video: None,
+        };
+        let mut pipe = redis::pipe();
+        task_state::create_in(&mut pipe, target, &record);
+        let _: () = pipe.query_async(&mut *conn).await.unwrap();
+        targets.push(target.to_string());
+    }
+
+    let client = reqwest::Client::new();
+    let list = |query: String| client
+        .get(format!("{}/api/v1/jobs?{query}", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .send();
+    let ids = |body: &serde_json::Value| body["jobs"].as_array().unwrap().iter()
+        .map(|job| job["id"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+
+    // The newest jobs come first.
+    let first: serde_json::Value = list("limit=2".to_owned()).await.unwrap().json().await.unwrap();
+    assert_eq!(vec![targets[2].clone(), targets[1].clone()], ids(&first));
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let second: serde_json::Value = list(format!("limit=2&cursor={cursor}")).await.unwrap()
+        .json().await.unwrap();
+    assert_eq!(vec![targets[0].clone()], ids(&second));
+    assert!(second["next_cursor"].is_null());
+
+    let response = list("limit=0".to_owned()).await.unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn oversized_uploads_are_refused_and_not_counted() {
+    let test_app = TestApp::spawn().await;
+    let quotas = Quotas { max_upload_size: Some(1024), ..Quotas::default() };
+    let key = ApiKey::new("small".to_owned(), Priority::Normal, quotas, None);
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let token = api_keys::create(&mut conn, &key).await.unwrap();
+
+    let audio = reqwest::multipart::Part::bytes(vec![0u8; 64 * 1024])
+        .file_name("audio.mp3")
+        .mime_str("audio/mpeg").unwrap();
+    let client = reqwest::Client::new();
+    let response = client.post(format!("{}/api/v1/jobs", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .multipart(reqwest::multipart::Form::new().part("source-audio", audio))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    assert_eq!("payload_too_large", error_code(response).await);
+
+    // The refused job was released again.
+    let usage = api_keys::usage(&mut conn, key.id, 1).await.unwrap();
+    assert_eq!(0, usage.renders);
+    assert_eq!(0, usage.pending_jobs);
+}
+
+// A second of silence as 8kHz mono WAV, which `ffprobe` can read.
+fn silent_wav() -> Vec<u8> {
+    let samples = vec![0u8; 8000 * 2];
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel, 8000 samples of two bytes each per second.
+    for value in [1u16, 1] {
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav.extend_from_slice(&8000u32.to_le_bytes());
+    wav.extend_from_slice(&16000u32.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&samples);
+    wav
+}
+
+#[tokio::test]
+async fn jobs_are_created_looked_up_and_deleted() {
+    let test_app = TestApp::spawn().await;
+    let (_, token) = create_key("lifecycle").await;
+    let client = reqwest::Client::new();
+
+    let form = reqwest::multipart::Form::new()
+        .part("source-audio", reqwest::multipart::Part::bytes(silent_wav())
+            .file_name("Song.wav")
+            .mime_str("audio/wav").unwrap())
+        .part("source-image", reqwest::multipart::Part::bytes(vec![0u8; 64])
+            .file_name("cover.png")
+            .mime_str("image/png").unwrap())
+        .part("options", reqwest::multipart::Part::text(r#"{"priority": "low"}"#)
+            .mime_str("application/json").unwrap());
+    let response = client.post(format!("{}/api/v1/jobs", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .multipart(form)
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::CREATED, response.status());
+    let location = response.headers()[reqwest::header::LOCATION].to_str().unwrap().to_owned();
+    let job: serde_json::Value = response.json().await.unwrap();
+    let id = job["id"].as_str().unwrap().to_owned();
+    assert_eq!(format!("/api/v1/jobs/{id}"), location);
+    assert_eq!("queued", job["status"]);
+    assert_eq!("low", job["priority"]);
+    assert_eq!("Song.mp4", job["filename"]);
+    let delete_token = job["delete_token"].as_str().unwrap().to_owned();
+
+    let get = || client.get(format!("{}{location}", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .send();
+    let job: serde_json::Value = get().await.unwrap().json().await.unwrap();
+    assert_eq!("queued", job["status"]);
+    assert!(job["queue"].is_object());
+    // Only what concerns the client is sent.
+    for internal in ["api_key", "options", "last_error", "delete_token", "video"] {
+        assert!(job.get(internal).is_none(), "job has `{internal}`");
+    }
+
+    let delete = |delete_token: &str| client.delete(format!("{}{location}", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .bearer_auth(delete_token)
+        .send();
+    let response = delete("not-the-token").await.unwrap();
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!("unauthorized", error_code(response).await);
+    let response = delete(&delete_token).await.unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+
+    let job: serde_json::Value = get().await.unwrap().json().await.unwrap();
+    assert_eq!("cancelled", job["status"]);
+    let response = delete(&delete_token).await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn finished_jobs_are_downloaded() {
+    let test_app = TestApp::spawn().await;
+    let (owner, token) = create_key("download").await;
+    let mut conn = get_redis_pool().get().await.unwrap();
+
+    // A finished job whose video spans two chunks.
+    let (target, video) = (Uuid::new_v4(), format!("test-video:{}", Uuid::new_v4()));
+    let data = (0..storage::CHUNK_SIZE + 10).map(|i| i as u8).collect::<Vec<u8>>();
+    for chunk in data.chunks(storage::CHUNK_SIZE) {
+        storage::append_chunk(&mut conn, &video, chunk).await.unwrap();
+    }
+    let record = TaskRecord {
+        status: TaskStatus::Ready,
+        created_at: 0,
+        updated_at: 0,
+        started_at: None,
+        finished_at: None,
+        attempts: 1,
+        last_error: None,
+        options: RenderOptions::default(),
+        priority: Priority::Normal,
+        filename: Some("Song.mp4".to_owned()),
+        output: None,
+        expires_at: None,
+        api_key: Some(owner),
+        video: Some(video),
+    };
+    let mut pipe = redis::pipe();
+    task_state::create_in(&mut pipe, target, &record);
+    let _: () = pipe.query_async(&mut *conn).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let output = format!("{}/api/v1/jobs/{target}/output", test_app.address);
+    let response = client.get(&output)
+        .header(API_KEY_HEADER, &token)
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    assert_eq!(data, response.bytes().await.unwrap());
+
+    // Ranges may span chunks.
+    let start = storage::CHUNK_SIZE - 5;
+    let response = client.get(&output)
+        .header(API_KEY_HEADER, &token)
+        .header(reqwest::header::RANGE, format!("bytes={start}-"))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::PARTIAL_CONTENT, response.status());
+    assert_eq!(&data[start..], response.bytes().await.unwrap());
+
+    let job: serde_json::Value = client.get(format!("{}/api/v1/jobs/{target}", test_app.address))
+        .header(API_KEY_HEADER, &token)
+        .send().await.unwrap()
+        .json().await.unwrap();
+    assert_eq!("ready", job["status"]);
+    assert_eq!(format!("/api/v1/jobs/{target}/output"), job["links"]["output"]);
+}
+
+#[tokio::test]
+async fn high_priority_requires_an_api_key() {
+    let test_app = TestApp::spawn().await;
+    let form = reqwest::multipart::Form::new()
+        .part("source-audio", reqwest::multipart::Part::bytes(silent_wav())
+            .file_name("Song.wav")
+            .mime_str("audio/wav").unwrap())
+        .part("source-image", reqwest::multipart::Part::bytes(vec![0u8; 64])
+            .file_name("cover.png")
+            .mime_str("image/png").unwrap())
+        .text("priority", "high");
+    let response = reqwest::Client::new()
+        .post(format!("{}/save", test_app.address))
+        .multipart(form)
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+}
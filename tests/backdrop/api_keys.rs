@@ -0,0 +1,68 @@
+use backdrop::api_keys::{generate_token, hash_token, Quotas};
+use backdrop::api_keys::command::KeyCommand;
+use backdrop::priority::Priority;
+
+fn parse(args: &str) -> Result<KeyCommand, String> {
+    KeyCommand::parse(args.split_whitespace().map(str::to_owned))
+}
+
+#[test]
+fn keys_are_random_and_stored_hashed() {
+    let (first, second) = (generate_token(), generate_token());
+    assert_ne!(first, second);
+    assert!(first.starts_with("bd_"));
+    assert_eq!(hash_token(&first), hash_token(&first));
+    assert_ne!(hash_token(&first), hash_token(&second));
+    assert!(!hash_token(&first).contains(&first[3..]));
+}
+
+#[test]
+fn keys_are_created_with_their_quotas() {
+    let command = parse(
+        "create team-a --tier high --renders-per-day 100 --max-upload-size=10485760 \
+        --max-concurrent-jobs 3 --max-lifetime 60 --callback-url https://example.com/hook"
+    ).unwrap();
+    let KeyCommand::Create(key) = command else {
+        panic!("expected a create command, got {command:?}");
+    };
+    assert_eq!("team-a", key.name);
+    assert_eq!(Priority::High, key.tier);
+    assert_eq!(Quotas {
+        renders_per_day: Some(100),
+        max_upload_size: Some(10 << 20),
+        max_concurrent_jobs: Some(3),
+        // Lifetimes are given in minutes.
+        max_lifetime: Some(60 * 60),
+    }, key.quotas);
+    assert_eq!(Some("https://example.com/hook".to_owned()), key.callback_url);
+
+    // Keys without quotas are unlimited.
+    let KeyCommand::Create(key) = parse("create team-b").unwrap() else { panic!() };
+    assert_eq!(Quotas::default(), key.quotas);
+    assert_eq!(Priority::Normal, key.tier);
+}
+
+#[test]
+fn malformed_key_commands_are_rejected() {
+    for args in [
+        "",
+        "create",
+        "create a b",
+        "create a --tier urgent",
+        "create a --renders-per-day many",
+        "create a --max-lifetime",
+        "create a --callback-url ftp://example.com",
+        "create a --unknown 1",
+        "list --days 3",
+        "revoke not-an-id",
+        "usage not-an-id",
+        "rotate",
+    ] {
+        assert!(parse(args).is_err(), "args: {args}");
+    }
+
+    let id = uuid::Uuid::new_v4();
+    assert_eq!(Ok(KeyCommand::Usage { id, days: 30 }), parse(&format!("usage {id} --days 30")));
+    assert_eq!(Ok(KeyCommand::Revoke(id)), parse(&format!("revoke {id}")));
+    assert_eq!(Ok(KeyCommand::List), parse("list"));
+}
@@ -0,0 +1,156 @@
+use actix_web::web::Bytes;
+use backdrop::archive::{archive_size, unique_name, ArchiveEntry, ZipWriter};
+use backdrop::priority::Priority;
+use backdrop::routes::RenderOptions;
+use backdrop::storage;
+use backdrop::task_state::{self, TaskRecord, TaskStatus};
+use mobc_redis::redis;
+use std::io::{Cursor, Read};
+use uuid::Uuid;
+
+use crate::helper::{get_redis_pool, TestApp};
+
+// Write an archive of `files` the way it's streamed, in pieces of `piece` bytes.
+fn write_archive(files: &[(&str, &[u8])], piece: usize) -> Vec<u8> {
+    let mut writer = ZipWriter::new();
+    let mut archive = Vec::new();
+    for (name, data) in files {
+        archive.extend_from_slice(&writer.start_entry(name, 1_700_000_000));
+        for chunk in data.chunks(piece) {
+            archive.extend_from_slice(&writer.write(Bytes::copy_from_slice(chunk)));
+        }
+        archive.extend_from_slice(&writer.finish_entry());
+    }
+    archive.extend_from_slice(&writer.finish());
+    archive
+}
+
+#[test]
+fn archives_can_be_extracted() {
+    let first = vec![7u8; 2500];
+    let second = b"second video".to_vec();
+    let files: [(&str, &[u8]); 3] = [("Artist - Title.mp4", &first), ("Tëst.mp4", &second), ("empty.mp4", &[])];
+    let archive = write_archive(&files, 1000);
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    assert_eq!(3, zip.len());
+    for (i, (name, data)) in files.iter().enumerate() {
+        let mut file = zip.by_index(i).unwrap();
+        assert_eq!(*name, file.name());
+        let mut content = Vec::new();
+        // Reading checks the CRC, too.
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(*data, content.as_slice());
+    }
+}
+
+#[test]
+fn archive_sizes_are_known_upfront() {
+    let first = vec![1u8; 4096];
+    let files: [(&str, &[u8]); 2] = [("a.mp4", &first), ("Backdrop video.mp4", b"1234")];
+    let entries: Vec<ArchiveEntry> = files.iter()
+        .map(|(name, data)| ArchiveEntry {
+            name: name.to_string(),
+            video: String::new(),
+            size: data.len() as u64,
+            modified: 0,
+        })
+        .collect();
+    assert_eq!(Some(write_archive(&files, 1000).len() as u64), archive_size(&entries));
+
+    let huge = ArchiveEntry { name: "huge.mp4".to_owned(), video: String::new(), size: u32::MAX as u64, modified: 0 };
+    assert_eq!(None, archive_size(&[huge]));
+}
+
+#[test]
+fn repeated_names_are_numbered() {
+    let mut taken = Vec::new();
+    for _ in 0..3 {
+        taken.push(unique_name("Video.mp4", &taken));
+    }
+    assert_eq!(vec!["Video.mp4", "Video (2).mp4", "Video (3).mp4"], taken);
+    assert_eq!("README (2)", unique_name("README", &["README".to_owned()]));
+}
+
+#[tokio::test]
+async fn archives_need_either_ids_or_a_batch() {
+    let test_app = TestApp::spawn().await;
+    let id = Uuid::new_v4();
+
+    for query in ["", &format!("?ids={id}&batch={id}"), "?ids=not-an-id"] {
+        let response = test_app.get_route(&format!("archive{query}")).await;
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status(), "query: {query}");
+    }
+}
+
+#[tokio::test]
+async fn batches_are_started_by_the_server() {
+    let test_app = TestApp::spawn().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/batches", test_app.address))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::CREATED, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let batch = Uuid::parse_str(body["batch"].as_str().unwrap()).unwrap();
+    let token = body["batch_token"].as_str().unwrap();
+    assert_eq!(64, token.len());
+    assert_ne!(Uuid::nil(), batch);
+}
+
+#[tokio::test]
+async fn archives_are_streamed_from_redis() {
+    let test_app = TestApp::spawn().await;
+    let mut conn = get_redis_pool().get().await.unwrap();
+
+    // Two finished videos, the first one spanning several chunks.
+    let first = (0..storage::CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect::<Vec<u8>>();
+    let second = b"second video".to_vec();
+    let mut targets = Vec::new();
+    for (filename, data) in [("Clip.mp4", &first), ("Clip.mp4", &second)] {
+        let (target, video) = (Uuid::new_v4(), format!("test-video:{}", Uuid::new_v4()));
+        for chunk in data.chunks(storage::CHUNK_SIZE) {
+            storage::append_chunk(&mut conn, &video, chunk).await.unwrap();
+        }
+        let record = TaskRecord {
+            status: TaskStatus::Ready,
+            created_at: 0,
+            updated_at: 0,
+            started_at: None,
+            finished_at: Some(1_700_000_000),
+            attempts: 1,
+            last_error: None,
+            options: RenderOptions::default(),
+            priority: Priority::Normal,
+            filename: Some(filename.to_owned()),
+            output: None,
+            expires_at: None,
+            api_key: None,
+            video: Some(video),
+        };
+        let mut pipe = redis::pipe();
+        task_state::create_in(&mut pipe, target, &record);
+        let _: () = pipe.query_async(&mut *conn).await.unwrap();
+        targets.push(target);
+    }
+    // An unknown upload is left out.
+    targets.push(Uuid::new_v4());
+    drop(conn);
+
+    let ids = targets.iter().map(Uuid::to_string).collect::<Vec<_>>().join(",");
+    let response = test_app.get_route(&format!("archive?ids={ids}")).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    let size = response.content_length();
+    let archive = response.bytes().await.unwrap();
+    assert_eq!(Some(archive.len() as u64), size);
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    assert_eq!(2, zip.len());
+    for (i, (name, data)) in [("Clip.mp4", &first), ("Clip (2).mp4", &second)].into_iter().enumerate() {
+        let mut file = zip.by_index(i).unwrap();
+        assert_eq!(name, file.name());
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(data, &content);
+    }
+}
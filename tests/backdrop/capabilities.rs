@@ -0,0 +1,76 @@
+use backdrop::capabilities::{parse_list, parse_version, Features};
+use backdrop::routes::{RenderOptions, SubtitleMode};
+
+const ENCODERS: &str = "\
+Encoders:
+ V..... = Video
+ A..... = Audio
+ S..... = Subtitle
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+ S..... mov_text             3GPP Timed Text subtitle
+";
+
+const MUXERS: &str = "\
+ File formats:
+ D. = Demuxing supported
+ .E = Muxing supported
+ --
+  E matroska,webm    Matroska
+  E mp4             MP4 (MPEG-4 Part 14)
+";
+
+const FILTERS: &str = "\
+Filters:
+  T.. = Timeline support
+  A = Audio input/output
+  | = Source or sink filter
+ TSC acrossfade        AA->A      Cross fade two input audio streams.
+ ... aloop             A->A       Loop audio samples.
+";
+
+#[test]
+fn ffmpeg_version_is_parsed() {
+    let output = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\n\
+        built with gcc 13";
+    assert_eq!(parse_version(output).as_deref(), Some("6.1.1-3ubuntu5"));
+    assert_eq!(parse_version("command not found"), None);
+}
+
+#[test]
+fn capability_lists_skip_legends() {
+    let encoders = parse_list(ENCODERS);
+    assert_eq!(encoders.into_iter().collect::<Vec<_>>(), ["aac", "libx264", "mov_text"]);
+
+    let muxers = parse_list(MUXERS);
+    assert_eq!(muxers.into_iter().collect::<Vec<_>>(), ["matroska", "mp4", "webm"]);
+
+    let filters = parse_list(FILTERS);
+    assert_eq!(filters.into_iter().collect::<Vec<_>>(), ["acrossfade", "aloop"]);
+}
+
+#[test]
+fn options_need_their_features() {
+    let none = Features {
+        trim: false,
+        loop_audio: false,
+        loop_crossfade: false,
+        burn_subtitles: false,
+        soft_subtitles: false,
+    };
+    let mut options = RenderOptions::default();
+    assert!(none.check(&options, false).is_ok());
+    // Subtitles are burned in by default.
+    assert!(none.check(&options, true).is_err());
+    assert!(Features { burn_subtitles: true, ..none }.check(&options, true).is_ok());
+
+    options.subtitle_mode = SubtitleMode::Soft;
+    assert!(Features { burn_subtitles: true, ..none }.check(&options, true).is_err());
+
+    options = RenderOptions::default();
+    options.set("loop-duration", "60").unwrap();
+    assert!(none.check(&options, false).is_err());
+    assert!(Features::default().intersect(none).check(&options, false).is_err());
+    assert!(Features::default().check(&options, false).is_ok());
+}
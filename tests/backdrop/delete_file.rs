@@ -0,0 +1,83 @@
+use backdrop::download_link::DownloadLinks;
+use backdrop::render_cache;
+use backdrop::task_state::{self, TaskStatus};
+use mobc_redis::redis::AsyncCommands;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::helper::{download_links, get_redis_pool, store_ready_upload, TestApp};
+
+#[test]
+fn delete_tokens_are_bound_to_their_upload() {
+    let links = DownloadLinks::new(Secret::new("secret".to_owned()));
+    let id = Uuid::new_v4();
+    let token = links.delete_token(id);
+
+    assert!(links.verify_delete_token(id, &token));
+    assert!(!links.verify_delete_token(Uuid::new_v4(), &token));
+    // Delete tokens can't be used as download link signatures.
+    assert!(!links.verify(id, u64::MAX, &token));
+}
+
+#[tokio::test]
+async fn deleting_requires_the_delete_token() {
+    let test_app = TestApp::spawn().await;
+    let url = format!("{}/done/{}", test_app.address, Uuid::new_v4());
+
+    let response = reqwest::Client::new().delete(&url).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+
+    let response = reqwest::Client::new().delete(&url)
+        .bearer_auth("00".repeat(32))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn uploads_are_purged_with_the_delete_token() {
+    let test_app = TestApp::spawn().await;
+    let links = download_links();
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (target, video) = store_ready_upload(&mut conn, b"video").await;
+    let url = format!("{}/done/{target}", test_app.address);
+
+    let response = reqwest::Client::new().delete(&url)
+        .bearer_auth(links.delete_token(target))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+    let exists: bool = conn.exists(&video).await.unwrap();
+    assert!(!exists);
+    let record = task_state::load(&mut conn, target).await.unwrap().unwrap();
+    assert_eq!(TaskStatus::Cancelled, record.status);
+
+    // There is nothing left to delete.
+    let response = reqwest::Client::new().delete(&url)
+        .bearer_auth(links.delete_token(target))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn the_delete_token_cookie_is_accepted() {
+    let test_app = TestApp::spawn().await;
+    let links = download_links();
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (target, video) = store_ready_upload(&mut conn, b"video").await;
+    // Another upload refers to the same cached video.
+    let refs_key = render_cache::refs_key(&video);
+    let _: () = conn.set(&refs_key, 2).await.unwrap();
+
+    let response = reqwest::Client::new().delete(format!("{}/done/{target}", test_app.address))
+        .header(reqwest::header::COOKIE, format!("delete-token={}", links.delete_token(target)))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, response.status());
+    // The cookie is removed along with the upload.
+    let cookie = response.headers()[reqwest::header::SET_COOKIE].to_str().unwrap();
+    assert!(cookie.starts_with("delete-token=;"));
+
+    // The video is kept for the other upload.
+    let exists: bool = conn.exists(&video).await.unwrap();
+    assert!(exists);
+    let refs: u32 = conn.get(&refs_key).await.unwrap();
+    assert_eq!(1, refs);
+}
@@ -0,0 +1,82 @@
+use backdrop::download_link::DownloadLinks;
+use backdrop::utils::unix_now;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::helper::{download_links, get_redis_pool, store_ready_upload, TestApp};
+
+fn links() -> DownloadLinks {
+    DownloadLinks::new(Secret::new("secret".to_owned()))
+}
+
+#[test]
+fn signed_links_are_verified() {
+    let (id, expires) = (Uuid::new_v4(), unix_now() + 60);
+    let signature = links().sign(id, expires);
+
+    assert!(links().verify(id, expires, &signature));
+    assert!(!links().verify(Uuid::new_v4(), expires, &signature));
+    assert!(!links().verify(id, expires + 1, &signature));
+    assert!(!links().verify(id, expires, "not hex"));
+
+    let other = DownloadLinks::new(Secret::new("other".to_owned()));
+    assert!(!other.verify(id, expires, &signature));
+}
+
+#[test]
+fn expired_links_are_rejected() {
+    let (id, expires) = (Uuid::new_v4(), unix_now() - 1);
+    assert!(!links().verify(id, expires, &links().sign(id, expires)));
+}
+
+#[test]
+fn batch_tokens_only_fit_their_batch() {
+    let batch = Uuid::new_v4();
+    let token = links().batch_token(batch);
+
+    assert!(links().verify_batch_token(batch, &token));
+    assert!(!links().verify_batch_token(Uuid::new_v4(), &token));
+    assert!(!links().verify_batch_token(batch, "not hex"));
+    // A batch token is no delete token of the same ID.
+    assert!(!links().verify_delete_token(batch, &token));
+}
+
+#[tokio::test]
+async fn downloads_require_a_valid_signature() {
+    let test_app = TestApp::spawn().await;
+    let route = format!(
+        "load/{}?expires={}&signature={}",
+        Uuid::new_v4(), unix_now() + 60, "00".repeat(32),
+    );
+    let response = test_app.get_route(&route).await;
+    assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+}
+
+#[tokio::test]
+async fn posters_require_a_valid_signature() {
+    let test_app = TestApp::spawn().await;
+    let (id, expires) = (Uuid::new_v4(), unix_now() + 60);
+    // A signature made with another secret.
+    let route = format!("poster/{id}?expires={expires}&signature={}", links().sign(id, expires));
+    let response = test_app.get_route(&route).await;
+    assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+}
+
+#[tokio::test]
+async fn empty_videos_are_downloaded() {
+    let test_app = TestApp::spawn().await;
+    let links = download_links();
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (target, _) = store_ready_upload(&mut conn, b"").await;
+
+    let url = format!("{}{}", test_app.address, links.url(target, unix_now() + 60));
+    let response = reqwest::Client::new().get(&url).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    assert!(response.bytes().await.unwrap().is_empty());
+
+    // No range of an empty video can be satisfied.
+    let response = reqwest::Client::new().get(&url)
+        .header(reqwest::header::RANGE, "bytes=0-")
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::RANGE_NOT_SATISFIABLE, response.status());
+}
@@ -2,10 +2,16 @@ use once_cell::sync::Lazy;
 use mobc::Pool;
 use mobc_redis::{RedisConnectionManager, redis};
 use secrecy::{Secret, ExposeSecret};
+use uuid::Uuid;
 
 use backdrop::startup::Application;
 use backdrop::configuration::get_configuration;
+use backdrop::download_link::DownloadLinks;
 use backdrop::telemetry::*;
+use backdrop::priority::Priority;
+use backdrop::routes::RenderOptions;
+use backdrop::storage;
+use backdrop::task_state::{self, TaskRecord, TaskStatus};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_name = "test".to_owned();
@@ -27,8 +33,13 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 });
 
 
+// Admin token of every test app.
+#[allow(dead_code)]
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
 pub struct TestApp {
     pub address: String,
+    #[allow(dead_code)]
     pub port: u16,
     api_client: reqwest::Client,
 }
@@ -40,6 +51,7 @@ impl TestApp {
         let configuration = {
             let mut c = get_configuration().expect("Failed to read configuratoin");
             c.application.port = 0;
+            c.application.admin_token = Some(Secret::new(ADMIN_TOKEN.to_owned()));
             c
         };
 
@@ -47,7 +59,7 @@ impl TestApp {
             .await
             .expect("Failed to build application");
         let application_port = application.port();
-        let _ = tokio::spawn(application.run_until_stopped());
+        drop(tokio::spawn(application.run_until_stopped()));
 
         let client = reqwest::Client::builder()
             .redirect(reqwest::redirect::Policy::none())
@@ -63,7 +75,7 @@ impl TestApp {
 
     pub async fn get_route(&self, r: &str) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/{}", &self.address, r))
+            .get(format!("{}/{}", &self.address, r))
             .send()
             .await
             .expect("Failed to execute request")
@@ -82,3 +94,41 @@ pub fn get_redis_pool() -> Pool<RedisConnectionManager> {
     let manager = RedisConnectionManager::new(client);
     Pool::builder().max_open(50).build(manager)
 }
+
+// Download links signed like the ones of every test app.
+#[allow(dead_code)]
+pub fn download_links() -> DownloadLinks {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    DownloadLinks::new(configuration.application.download_secret)
+}
+
+// Store a finished upload whose video is a single chunk of `video_data`.
+// Returns the upload's ID and the key of its video.
+#[allow(dead_code)]
+pub async fn store_ready_upload(
+    conn: &mut mobc::Connection<RedisConnectionManager>,
+    video_data: &[u8],
+) -> (Uuid, String) {
+    let (target, video) = (Uuid::new_v4(), format!("test-video:{}", Uuid::new_v4()));
+    storage::append_chunk(conn, &video, video_data).await.unwrap();
+    let record = TaskRecord {
+        status: TaskStatus::Ready,
+        created_at: 0,
+        updated_at: 0,
+        started_at: None,
+        finished_at: None,
+        attempts: 1,
+        last_error: None,
+        options: RenderOptions::default(),
+        priority: Priority::Normal,
+        filename: None,
+        output: None,
+        expires_at: None,
+        api_key: None,
+        video: Some(video.clone()),
+    };
+    let mut pipe = redis::pipe();
+    task_state::create_in(&mut pipe, target, &record);
+    let _: () = pipe.query_async(&mut **conn).await.unwrap();
+    (target, video)
+}
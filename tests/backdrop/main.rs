@@ -1,3 +1,24 @@
+mod admin;
+mod api;
+mod api_keys;
+mod archive;
+mod capabilities;
+mod delete_file;
+mod download_link;
 mod helper;
+mod metrics;
+mod output_name;
 mod health_check;
-mod redis;
\ No newline at end of file
+mod priority;
+mod progress_events;
+mod redis;
+mod render_cache;
+mod render_options;
+mod render_stats;
+mod retention;
+mod sandbox;
+mod save_file;
+mod storage;
+mod subtitles;
+mod task_state;
+mod webhooks;
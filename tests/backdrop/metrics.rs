@@ -0,0 +1,17 @@
+use crate::helper::TestApp;
+
+#[tokio::test]
+async fn metrics_are_served_in_prometheus_format() {
+    let test_app = TestApp::spawn().await;
+    // Make sure at least one request was recorded.
+    test_app.get_route("health_check").await;
+
+    let response = test_app.get_route("metrics").await;
+    assert!(response.status().is_success());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("http_requests_total{method=\"GET\",route=\"/health_check\",status=\"200\"}"));
+    assert!(body.contains("upload_size_bytes_count"));
+    // The memory used by redis is read on every scrape.
+    assert!(body.contains("\nredis_used_memory_bytes "));
+    assert!(!body.contains("\nredis_used_memory_bytes 0\n"));
+}
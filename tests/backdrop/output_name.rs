@@ -0,0 +1,52 @@
+use backdrop::configuration::RenderLimits;
+use backdrop::ffprobe::{parse_probe, Ffprobe};
+use backdrop::routes::{output_name, DEFAULT_NAME};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn custom_names_take_precedence() {
+    assert_eq!("My video.mp4", output_name(Some("My video"), Some("Title"), None, Some("a.mp3")));
+    // The extension isn't doubled.
+    assert_eq!("My video.mp4", output_name(Some("My video.MP4"), None, None, None));
+}
+
+#[test]
+fn names_are_derived_from_tags_then_file_names() {
+    assert_eq!("Artist - Title.mp4", output_name(None, Some("Title"), Some("Artist"), Some("a.mp3")));
+    assert_eq!("Title.mp4", output_name(None, Some("Title"), None, Some("a.mp3")));
+    // An artist without a title isn't enough to name the video.
+    assert_eq!("01 track.mp4", output_name(None, None, Some("Artist"), Some("01 track.mp3")));
+    assert_eq!(DEFAULT_NAME, output_name(None, None, None, None));
+}
+
+#[test]
+fn names_are_sanitized() {
+    assert_eq!("ACDC - Back in Black.mp4", output_name(None, Some("Back in Black"), Some("AC/DC"), None));
+    assert!(!output_name(Some("../../etc/secret"), None, None, None).contains('/'));
+    // Names which are sanitized away fall back to the next source.
+    assert_eq!("track.mp4", output_name(Some("///"), None, None, Some("track.mp3")));
+    assert_eq!(104, output_name(Some(&"a".repeat(300)), None, None, None).len());
+}
+
+#[test]
+fn probes_report_duration_and_tags() {
+    let output = br#"{"format": {"duration": "61.5", "tags": {"TITLE": " Song ", "artist": "Band"}}}"#;
+    let probe = parse_probe(output).unwrap();
+    assert_eq!(61.5, probe.duration);
+    assert_eq!(Some("Song"), probe.title.as_deref());
+    assert_eq!(Some("Band"), probe.artist.as_deref());
+
+    let untagged = parse_probe(br#"{"format": {"duration": "3.0"}}"#).unwrap();
+    assert_eq!(None, untagged.title);
+    assert!(parse_probe(br#"{"format": {"duration": "N/A"}}"#).is_none());
+}
+
+#[test]
+fn ffprobe_is_found_next_to_ffmpeg() {
+    let limits = RenderLimits { cpu_time: 60, memory: 512, file_size: 100, threads: 2, nice: 10 };
+    let path = |ffmpeg: &str| Ffprobe::next_to(Path::new(ffmpeg), limits.clone()).path().to_owned();
+    assert_eq!(PathBuf::from("ffprobe"), path("ffmpeg"));
+    assert_eq!(PathBuf::from("/opt/ffmpeg-6/bin/ffprobe"), path("/opt/ffmpeg-6/bin/ffmpeg"));
+    assert_eq!(PathBuf::from("/usr/bin/ffprobe-7"), path("/usr/bin/ffmpeg-7"));
+    assert_eq!(PathBuf::from("ffprobe"), path("/usr/local/bin/encoder"));
+}
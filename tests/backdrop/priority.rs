@@ -0,0 +1,37 @@
+use backdrop::priority::Priority;
+use backdrop::RENDER_QUEUE_KEY;
+
+#[test]
+fn priorities_parse_case_insensitively() {
+    assert_eq!(Priority::try_from("high".to_owned()), Ok(Priority::High));
+    assert_eq!(Priority::try_from("Normal".to_owned()), Ok(Priority::Normal));
+    assert_eq!(Priority::try_from("LOW".to_owned()), Ok(Priority::Low));
+    assert!(Priority::try_from("urgent".to_owned()).is_err());
+}
+
+#[test]
+fn every_priority_has_its_own_lane() {
+    let keys: Vec<_> = Priority::ALL.iter().map(Priority::queue_key).collect();
+    assert_eq!(keys.len(), 3);
+    assert!(keys.iter().all(|k| keys.iter().filter(|o| o == &k).count() == 1));
+    // Tasks queued before priorities existed are still taken as normal priority.
+    assert_eq!(Priority::Normal.queue_key(), RENDER_QUEUE_KEY);
+}
+
+#[test]
+fn tasks_without_priority_are_normal_priority() {
+    let task: backdrop::routes::RenderTask = serde_json::from_str(&format!(
+        r#"{{"target":"{0}","audio":"{0}","image":"{0}"}}"#,
+        uuid::Uuid::new_v4(),
+    )).unwrap();
+    assert_eq!(task.priority, Priority::Normal);
+    assert_eq!(task.queued_at, None);
+}
+
+#[test]
+fn priorities_rank_in_lane_order() {
+    assert!(Priority::High.is_above(Priority::Normal));
+    assert!(Priority::Normal.is_above(Priority::Low));
+    assert!(!Priority::Normal.is_above(Priority::Normal));
+    assert!(!Priority::Low.is_above(Priority::High));
+}
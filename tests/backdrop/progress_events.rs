@@ -0,0 +1,135 @@
+use backdrop::priority::Priority;
+use backdrop::progress_events::{self, ProgressEvent};
+use backdrop::purge::purge;
+use backdrop::routes::RenderOptions;
+use backdrop::task_state::{self, TaskRecord, TaskStatus, Transition};
+use mobc_redis::redis;
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::helper::{get_redis_pool, TestApp};
+
+#[test]
+fn events_are_tagged_with_their_kind() {
+    assert_eq!(json!({ "event": "state" }), serde_json::to_value(ProgressEvent::State).unwrap());
+    assert_eq!(
+        json!({ "event": "progress", "fraction": 0.5 }),
+        serde_json::to_value(ProgressEvent::Progress { fraction: 0.5 }).unwrap(),
+    );
+}
+
+#[test]
+fn events_are_deserialized_from_their_payload() {
+    for event in [ProgressEvent::State, ProgressEvent::Progress { fraction: 0.25 }] {
+        let payload = serde_json::to_string(&event).unwrap();
+        assert_eq!(event, serde_json::from_str(&payload).unwrap());
+    }
+}
+
+// Server-sent events read from the body of `GET /done/events/{progressId}`.
+struct EventStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventStream {
+    async fn open(app: &TestApp, target: Uuid) -> Self {
+        let response = app.get_route(&format!("done/events/{target}")).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "text/event-stream");
+        Self { response, buffer: String::new() }
+    }
+
+    // The name and data of the next event. Returns `None` once the stream ended.
+    async fn next(&mut self) -> Option<(String, serde_json::Value)> {
+        loop {
+            if let Some((message, rest)) = self.buffer.split_once("\n\n") {
+                let (event, data) = message.split_once('\n').unwrap();
+                let event = event.strip_prefix("event: ").unwrap().to_owned();
+                let data = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+                self.buffer = rest.to_owned();
+                return Some((event, data));
+            }
+            let chunk = self.response.chunk().await.unwrap()?;
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    // Like `next`, but gives up after `timeout`.
+    async fn next_within(&mut self, timeout: Duration) -> Option<(String, serde_json::Value)> {
+        tokio::time::timeout(timeout, self.next()).await.ok().flatten()
+    }
+}
+
+#[tokio::test]
+async fn published_events_are_streamed_until_the_state_is_final() {
+    let app = TestApp::spawn().await;
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let target = Uuid::new_v4();
+    let record = TaskRecord {
+        status: TaskStatus::Rendering,
+        created_at: 0,
+        updated_at: 0,
+        started_at: None,
+        finished_at: None,
+        attempts: 1,
+        last_error: None,
+        options: RenderOptions::default(),
+        priority: Priority::Normal,
+        filename: None,
+        output: None,
+        expires_at: None,
+        api_key: None,
+        video: None,
+    };
+    let mut pipe = redis::pipe();
+    task_state::create_in(&mut pipe, target, &record);
+    let _: () = pipe.query_async(&mut *conn).await.unwrap();
+
+    // Every stream starts with the current state.
+    let mut streams = [EventStream::open(&app, target).await, EventStream::open(&app, target).await];
+    for stream in &mut streams {
+        let (event, data) = stream.next().await.unwrap();
+        assert_eq!("state", event);
+        assert_eq!("pending", data["progress"]);
+    }
+
+    // The app subscribes to the events in the background, so the
+    // progress is published until it reaches the first stream.
+    let progress = ProgressEvent::Progress { fraction: 0.5 };
+    let mut received = None;
+    for _ in 0..50 {
+        progress_events::publish(&mut conn, target, progress).await;
+        received = streams[0].next_within(Duration::from_millis(100)).await;
+        if received.is_some() {
+            break;
+        }
+    }
+    // Events of an upload are handed out to all of its subscribers.
+    for received in [received, streams[1].next_within(Duration::from_secs(5)).await] {
+        let (event, data) = received.expect("no progress event received");
+        assert_eq!("progress", event);
+        assert_eq!(0.5, data["fraction"]);
+    }
+
+    // The streams end once they sent the final state.
+    let transition = Transition::failed("test failure", 60);
+    assert!(task_state::transition(&mut conn, target, &transition).await.unwrap());
+    progress_events::publish(&mut conn, target, ProgressEvent::State).await;
+    for stream in &mut streams {
+        let state = loop {
+            let (event, data) = stream.next_within(Duration::from_secs(5)).await
+                .expect("no final state received");
+            if event == "state" {
+                break data;
+            }
+        };
+        assert_eq!("failed", state["progress"]);
+        assert_eq!("test failure", state["reason"]);
+        let end = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(matches!(end, Ok(None)), "stream didn't end");
+    }
+
+    assert!(purge(&mut conn, target).await.unwrap());
+}
@@ -0,0 +1,28 @@
+use backdrop::render_cache::{cache_key, hash};
+use backdrop::routes::RenderOptions;
+
+#[test]
+fn identical_uploads_share_a_cache_key() {
+    let (audio, image) = (hash(b"audio"), hash(b"image"));
+    let options = RenderOptions::default();
+
+    assert_eq!(hash(b"audio"), audio);
+    assert_eq!(
+        cache_key(&audio, &image, None, &options),
+        cache_key(&audio, &image, None, &options),
+    );
+}
+
+#[test]
+fn cache_key_depends_on_assets_and_options() {
+    let (audio, image) = (hash(b"audio"), hash(b"image"));
+    let options = RenderOptions::default();
+    let key = cache_key(&audio, &image, None, &options);
+
+    assert_ne!(key, cache_key(&image, &audio, None, &options));
+    assert_ne!(key, cache_key(&audio, &image, Some(&hash(b"lyrics")), &options));
+
+    let mut trimmed = RenderOptions::default();
+    trimmed.set("trim-start", "10").unwrap();
+    assert_ne!(key, cache_key(&audio, &image, None, &trimmed));
+}
@@ -0,0 +1,71 @@
+use backdrop::routes::{parse_timestamp, RenderOptions};
+
+#[test]
+fn timestamps_parse_seconds_and_clock_notation() {
+    assert_eq!(parse_timestamp("75"), Some(75.0));
+    assert_eq!(parse_timestamp("75.5"), Some(75.5));
+    assert_eq!(parse_timestamp("1:15.5"), Some(75.5));
+    assert_eq!(parse_timestamp("01:01:15"), Some(3675.0));
+}
+
+#[test]
+fn invalid_timestamps_are_rejected() {
+    for invalid in ["", "abc", "-3", "1:60", "1.5:00", "1:2:3:4", "inf", "NaN"] {
+        assert_eq!(parse_timestamp(invalid), None, "accepted `{invalid}`");
+    }
+}
+
+#[test]
+fn trim_range_is_validated_against_audio_duration() {
+    let mut options = RenderOptions::default();
+    options.set("trim-start", "0:10").unwrap();
+    options.set("trim-end", "0:50").unwrap();
+    assert!(options.validate(60.0).is_ok());
+    assert_eq!(options.trimmed_duration(60.0), 40.0);
+
+    // End past the end of the audio.
+    assert!(options.validate(45.0).is_err());
+
+    // Start after end.
+    options.set("trim-start", "55").unwrap();
+    assert!(options.validate(60.0).is_err());
+}
+
+#[test]
+fn blank_and_unknown_options() {
+    let mut options = RenderOptions::default();
+    options.set("trim-start", "  ").unwrap();
+    assert_eq!(options, RenderOptions::default());
+    assert!(options.set("not-an-option", "1").is_err());
+    assert!(options.set("trim-end", "soon").is_err());
+}
+
+#[test]
+fn loop_options_are_validated() {
+    let mut options = RenderOptions::default();
+    options.set("loop-duration", "1:00:00").unwrap();
+    options.set("loop-crossfade", "2.5").unwrap();
+    assert!(options.validate(30.0).is_ok());
+    assert_eq!(options.output_duration(30.0), 3600.0);
+
+    // The crossfade must be shorter than half of the looped audio.
+    options.set("trim-end", "0:04").unwrap();
+    assert!(options.validate(30.0).is_err());
+
+    // A crossfade without a loop makes no sense.
+    let mut options = RenderOptions::default();
+    options.set("loop-crossfade", "1").unwrap();
+    assert!(options.validate(30.0).is_err());
+
+    // Loops are capped.
+    let mut options = RenderOptions::default();
+    options.set("loop-duration", "13:00:00").unwrap();
+    assert!(options.validate(30.0).is_err());
+
+    // So is the audio which is looped, unless it's trimmed to a shorter part.
+    let mut options = RenderOptions::default();
+    options.set("loop-duration", "1:00:00").unwrap();
+    assert!(options.validate(600.0).is_err());
+    options.set("trim-end", "5:00").unwrap();
+    assert!(options.validate(600.0).is_ok());
+}
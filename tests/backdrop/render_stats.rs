@@ -0,0 +1,40 @@
+use backdrop::render_stats::RenderStats;
+use backdrop::routes::{RenderOptions, RenderTask};
+use serde_json::json;
+
+fn task(audio_duration: Option<f64>, options: RenderOptions) -> RenderTask {
+    serde_json::from_value(json!({
+        "target": uuid::Uuid::new_v4(),
+        "audio": uuid::Uuid::new_v4(),
+        "image": uuid::Uuid::new_v4(),
+        "audio_duration": audio_duration,
+        "options": options,
+    })).unwrap()
+}
+
+#[test]
+fn renders_are_profiled_by_their_most_expensive_option() {
+    let mut options = RenderOptions::default();
+    assert_eq!("plain", options.profile(false));
+    options.set("trim-start", "10").unwrap();
+    assert_eq!("trimmed", options.profile(false));
+    options.set("loop-duration", "60").unwrap();
+    assert_eq!("looped", options.profile(false));
+    assert_eq!("burned_subtitles", options.profile(true));
+}
+
+#[test]
+fn estimates_without_history_scale_with_the_video_duration() {
+    let stats = RenderStats::default();
+    let short = stats.estimate(&task(Some(60.0), RenderOptions::default()));
+    let long = stats.estimate(&task(Some(120.0), RenderOptions::default()));
+    assert!(short > 0.0);
+    assert_eq!(short * 2.0, long);
+
+    // Looped videos take as long as the loop, not the audio.
+    let mut looped = RenderOptions::default();
+    looped.set("loop-duration", "120").unwrap();
+    assert_eq!(long, stats.estimate(&task(Some(60.0), looped)));
+
+    assert!(stats.estimate(&task(None, RenderOptions::default())) > 0.0);
+}
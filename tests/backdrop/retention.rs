@@ -0,0 +1,82 @@
+use backdrop::configuration::get_configuration;
+use backdrop::retention::Retention;
+use backdrop::task_state;
+use mobc_redis::redis::AsyncCommands;
+use uuid::Uuid;
+
+use crate::helper::{download_links, get_redis_pool, store_ready_upload, TestApp};
+
+#[test]
+fn lifetimes_are_bounded_by_the_maximum() {
+    let retention = Retention::new(5, 60);
+    assert_eq!(300, retention.default_lifetime());
+    assert_eq!(3600, retention.max_lifetime());
+
+    assert_eq!(Ok(60), retention.lifetime(1));
+    assert_eq!(Ok(3600), retention.parse(" 60 "));
+    assert!(retention.lifetime(0).is_err());
+    assert!(retention.lifetime(61).is_err());
+    assert!(retention.parse("1.5").is_err());
+    assert!(retention.parse("forever").is_err());
+}
+
+#[test]
+fn the_default_lifetime_is_always_allowed() {
+    let retention = Retention::new(30, 10);
+    assert_eq!(1800, retention.max_lifetime());
+    assert_eq!(Ok(1800), retention.lifetime(30));
+}
+
+#[tokio::test]
+async fn extending_requires_the_delete_token() {
+    let test_app = TestApp::spawn().await;
+    let url = format!("{}/done/{}/extend", test_app.address, Uuid::new_v4());
+
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn extending_beyond_the_maximum_is_rejected() {
+    let test_app = TestApp::spawn().await;
+    let links = download_links();
+    let max_lifetime = get_configuration().expect("Failed to read configuration")
+        .render_worker.max_lifetime;
+    let id = Uuid::new_v4();
+    let url = format!("{}/done/{id}/extend?lifetime={}", test_app.address, max_lifetime + 1);
+
+    let response = reqwest::Client::new().post(&url)
+        .bearer_auth(links.delete_token(id))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn extending_keeps_the_video_longer() {
+    let test_app = TestApp::spawn().await;
+    let links = download_links();
+    let mut conn = get_redis_pool().get().await.unwrap();
+    let (target, video) = store_ready_upload(&mut conn, b"video").await;
+    let _: () = conn.expire(&video, 60).await.unwrap();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/done/{target}/extend?lifetime=120", test_app.address))
+        .bearer_auth(links.delete_token(target))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    let extended: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(120 * 60, extended["expires_in"]);
+
+    let ttl: i64 = conn.ttl(&video).await.unwrap();
+    assert!(ttl > 60 * 60);
+    let record = task_state::load(&mut conn, target).await.unwrap().unwrap();
+    assert!(record.expires_at.is_some());
+
+    // Uploads which aren't finished can't be extended.
+    let id = Uuid::new_v4();
+    let response = reqwest::Client::new()
+        .post(format!("{}/done/{id}/extend", test_app.address))
+        .bearer_auth(links.delete_token(id))
+        .send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+}
@@ -0,0 +1,46 @@
+use backdrop::configuration::RenderLimits;
+use backdrop::render_worker::sandbox::{exceeded_limit, ResourceLimit};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+fn limits() -> RenderLimits {
+    RenderLimits { cpu_time: 60, memory: 512, file_size: 100, threads: 2, nice: 10 }
+}
+
+fn killed_by(signal: i32) -> ExitStatus {
+    ExitStatus::from_raw(signal)
+}
+
+// Exit code of bubblewrap whose child was killed by `signal`.
+fn bwrap_killed_by(signal: i32) -> ExitStatus {
+    ExitStatus::from_raw((128 + signal) << 8)
+}
+
+#[test]
+fn cpu_limit_is_only_blamed_once_it_is_used_up() {
+    let used_up = Duration::from_secs(60);
+    for status in [killed_by(libc::SIGKILL), killed_by(libc::SIGXCPU), bwrap_killed_by(libc::SIGKILL)] {
+        assert_eq!(Some(ResourceLimit::CpuTime), exceeded_limit(&limits(), status, "", used_up));
+        // E.g. the OOM killer sends `SIGKILL`, too.
+        assert_eq!(None, exceeded_limit(&limits(), status, "", Duration::from_secs(3)));
+    }
+    let unlimited = RenderLimits { cpu_time: 0, ..limits() };
+    assert_eq!(None, exceeded_limit(&unlimited, killed_by(libc::SIGKILL), "", used_up));
+}
+
+#[test]
+fn memory_limit_is_only_blamed_if_it_is_set() {
+    let stderr = "Error while filtering: Cannot allocate memory\n";
+    let failed = ExitStatus::from_raw(1 << 8);
+    assert_eq!(Some(ResourceLimit::Memory), exceeded_limit(&limits(), failed, stderr, Duration::ZERO));
+    let unlimited = RenderLimits { memory: 0, ..limits() };
+    assert_eq!(None, exceeded_limit(&unlimited, failed, stderr, Duration::ZERO));
+}
+
+#[test]
+fn other_failures_exceed_no_limit() {
+    let failed = ExitStatus::from_raw(1 << 8);
+    assert_eq!(None, exceeded_limit(&limits(), failed, "Invalid data found", Duration::from_secs(100)));
+    assert_eq!(Some(ResourceLimit::FileSize), exceeded_limit(&limits(), killed_by(libc::SIGXFSZ), "", Duration::ZERO));
+}
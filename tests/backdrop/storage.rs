@@ -0,0 +1,18 @@
+use backdrop::storage::{chunk_slices, CHUNK_SIZE};
+
+#[test]
+fn ranges_within_a_chunk_take_a_single_slice() {
+    let slices: Vec<_> = chunk_slices(10, 19).collect();
+    assert_eq!(vec![(0, 10..20)], slices);
+}
+
+#[test]
+fn ranges_across_chunks_are_split_at_chunk_borders() {
+    let chunk = CHUNK_SIZE as u64;
+    let slices: Vec<_> = chunk_slices(chunk - 5, 2 * chunk + 4).collect();
+    assert_eq!(vec![
+        (0, CHUNK_SIZE - 5..CHUNK_SIZE),
+        (1, 0..CHUNK_SIZE),
+        (2, 0..5),
+    ], slices);
+}
@@ -0,0 +1,46 @@
+use backdrop::routes::{parse_subtitles, to_srt, Cue};
+
+fn cue(start: f64, end: Option<f64>, text: &str) -> Cue {
+    Cue { start, end, text: text.to_owned() }
+}
+
+#[test]
+fn lrc_lines_last_until_the_next_line() {
+    let lrc = "[ti:Song]\n[ar:Artist]\n[00:01.00]First\n[00:03.50]<00:03.50>Second\n[00:05.00]\n[00:07.00]Third\n";
+    assert_eq!(parse_subtitles(lrc).unwrap(), vec![
+        cue(1.0, Some(3.5), "First"),
+        cue(3.5, Some(5.0), "Second"),
+        cue(7.0, None, "Third"),
+    ]);
+}
+
+#[test]
+fn srt_and_webvtt_cues_are_parsed() {
+    let srt = "1\n00:00:01,000 --> 00:00:02,500\nHello\nthere\n\n2\n00:00:03,000 --> 00:00:04,000\nBye\n";
+    assert_eq!(parse_subtitles(srt).unwrap(), vec![
+        cue(1.0, Some(2.5), "Hello\nthere"),
+        cue(3.0, Some(4.0), "Bye"),
+    ]);
+
+    let vtt = "WEBVTT\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 align:start\n<v Singer>Hello</v>\n";
+    assert_eq!(parse_subtitles(vtt).unwrap(), vec![cue(1.0, Some(2.0), "Hello")]);
+}
+
+#[test]
+fn malformed_subtitles_report_the_line() {
+    let err = parse_subtitles("1\n00:00:01,000 --> soon\nHello\n").unwrap_err();
+    assert!(err.contains("line 2"), "{err}");
+    let err = parse_subtitles("[00:01.00]Fine\nno timestamp\n").unwrap_err();
+    assert!(err.contains("line 2"), "{err}");
+    assert!(parse_subtitles("just some text").is_err());
+    assert!(parse_subtitles("WEBVTT\n").is_err());
+}
+
+#[test]
+fn srt_output_is_shifted_and_cut() {
+    let cues = vec![cue(1.0, Some(3.0), "Gone"), cue(6.0, None, "Last")];
+    assert_eq!(
+        to_srt(&cues, 5.0, 10.0),
+        "1\n00:00:01,000 --> 00:00:10,000\nLast\n\n",
+    );
+}
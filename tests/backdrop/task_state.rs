@@ -0,0 +1,74 @@
+use backdrop::priority::Priority;
+use backdrop::routes::RenderOptions;
+use backdrop::task_state::{TaskRecord, TaskStatus};
+use uuid::Uuid;
+
+use crate::helper::TestApp;
+
+#[test]
+fn tasks_follow_the_state_machine() {
+    use TaskStatus::*;
+    assert!(Queued.can_become(Rendering));
+    assert!(Queued.can_become(Ready));
+    assert!(Rendering.can_become(Queued));
+    assert!(Rendering.can_become(Rendering));
+    assert!(Rendering.can_become(Failed));
+    assert!(Ready.can_become(Expired));
+    assert!(Failed.can_become(Cancelled));
+
+    assert!(!Queued.can_become(Expired));
+    assert!(!Ready.can_become(Rendering));
+    assert!(!Failed.can_become(Ready));
+    for status in TaskStatus::ALL {
+        assert!(!Cancelled.can_become(status));
+        assert!(!Expired.can_become(status));
+    }
+}
+
+#[test]
+fn only_cancelled_and_expired_tasks_are_final() {
+    let finals: Vec<_> = TaskStatus::ALL.into_iter().filter(|s| s.is_final()).collect();
+    assert_eq!(vec![TaskStatus::Cancelled, TaskStatus::Expired], finals);
+}
+
+#[test]
+fn statuses_round_trip() {
+    for status in TaskStatus::ALL {
+        assert_eq!(Ok(status), TaskStatus::try_from(status.as_str()));
+        assert_eq!(format!("\"{}\"", status.as_str()), serde_json::to_string(&status).unwrap());
+    }
+    assert!(TaskStatus::try_from("pending").is_err());
+}
+
+#[test]
+fn records_keep_their_api_key_private() {
+    let record = TaskRecord {
+        status: TaskStatus::Ready,
+        created_at: 0,
+        updated_at: 0,
+        started_at: None,
+        finished_at: None,
+        attempts: 1,
+        last_error: None,
+        options: RenderOptions::default(),
+        priority: Priority::Normal,
+        filename: None,
+        output: None,
+        expires_at: None,
+        api_key: Some(Uuid::new_v4()),
+        video: Some("video".to_owned()),
+    };
+    let json = serde_json::to_value(&record).unwrap();
+    assert!(json.get("api_key").is_none());
+    assert!(json.get("video").is_none());
+}
+
+#[tokio::test]
+async fn unknown_uploads_are_not_found() {
+    let test_app = TestApp::spawn().await;
+
+    let response = test_app.get_route(&format!("done/ready/{}", Uuid::new_v4())).await;
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("gone", body["progress"]);
+}
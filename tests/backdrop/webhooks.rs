@@ -0,0 +1,158 @@
+use backdrop::configuration::WebhookSettings;
+use backdrop::webhooks::{
+    is_public_address, parse_callback_url, signature, Delivery, WebhookEvent, Webhooks, DELIVERY_HEADER,
+    SIGNATURE_HEADER,
+};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{header_exists, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const SECRET: &str = "webhook-secret";
+
+// Webhooks which may be sent to the mock servers on the loopback interface.
+fn webhooks(max_attempts: u32) -> Webhooks {
+    Webhooks::new(&WebhookSettings {
+        secret: Secret::new(SECRET.to_owned()),
+        timeout: 5,
+        max_attempts,
+        retry_delay: 10,
+        allow_private_addresses: true,
+    }).unwrap()
+}
+
+// Value of the header `name` of a received request.
+fn header(request: &wiremock::Request, name: &str) -> String {
+    request.headers.iter()
+        .find(|(n, _)| n.as_str().eq_ignore_ascii_case(name))
+        // Values are split at commas, which signatures contain.
+        .map(|(_, values)| values.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(","))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_json() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .and(header_exists(SIGNATURE_HEADER))
+        .and(header_exists(DELIVERY_HEADER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let target = Uuid::new_v4();
+    let delivery = Delivery::new(
+        format!("{}/hook", server.uri()), target, WebhookEvent::Failed, Some("too long".to_owned()),
+    );
+    let attempt = webhooks(3).send(&delivery).await;
+    assert!(attempt.delivered);
+    assert_eq!(Some(200), attempt.status);
+    assert_eq!(1, attempt.attempt);
+
+    let request = &server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!("failed", body["event"]);
+    assert_eq!(target.to_string(), body["progress_id"]);
+    assert_eq!("too long", body["reason"]);
+    assert_eq!(delivery.id.to_string(), header(request, DELIVERY_HEADER));
+
+    // The signature covers the timestamp and the body.
+    let header = header(request, SIGNATURE_HEADER);
+    let (timestamp, sig) = header.strip_prefix("t=").unwrap().split_once(",v1=").unwrap();
+    let timestamp: u64 = timestamp.parse().unwrap();
+    let secret = Secret::new(SECRET.to_owned());
+    assert_eq!(signature(&secret, timestamp, &request.body), sig);
+    assert_ne!(signature(&secret, timestamp + 1, &request.body), sig);
+}
+
+#[tokio::test]
+async fn failed_deliveries_record_why() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let mut delivery = Delivery::new(server.uri(), Uuid::new_v4(), WebhookEvent::Ready, None);
+    delivery.attempts = 2;
+    let attempt = webhooks(3).send(&delivery).await;
+    assert!(!attempt.delivered);
+    assert_eq!(Some(503), attempt.status);
+    assert_eq!(3, attempt.attempt);
+    assert!(attempt.error.is_some());
+
+    // Nothing listens on a port which was only reserved.
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let uri = format!("http://127.0.0.1:{port}");
+    let attempt = webhooks(3).send(&Delivery::new(uri, Uuid::new_v4(), WebhookEvent::Ready, None)).await;
+    assert!(!attempt.delivered);
+    assert_eq!(None, attempt.status);
+}
+
+#[test]
+fn retries_back_off_exponentially() {
+    let webhooks = webhooks(4);
+    assert_eq!(Some(10), webhooks.retry_delay(1));
+    assert_eq!(Some(20), webhooks.retry_delay(2));
+    assert_eq!(Some(40), webhooks.retry_delay(3));
+    assert_eq!(None, webhooks.retry_delay(4));
+}
+
+#[test]
+fn callback_urls_must_be_http() {
+    assert!(parse_callback_url("https://example.com/hooks/backdrop").is_ok());
+    assert!(parse_callback_url(" http://93.184.216.34:8080 ").is_ok());
+    assert!(parse_callback_url("ftp://example.com").is_err());
+    assert!(parse_callback_url("file:///etc/passwd").is_err());
+    assert!(parse_callback_url("not a url").is_err());
+}
+
+#[test]
+fn callback_urls_must_be_public() {
+    for url in [
+        "http://127.0.0.1:8080",
+        "http://localhost/hook",
+        "http://api.localhost/hook",
+        "http://10.0.0.5/hook",
+        "http://192.168.1.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://0.0.0.0:6379",
+        "http://[::1]/hook",
+        "http://[fd00::1]/hook",
+        "http://[::ffff:127.0.0.1]/hook",
+    ] {
+        assert!(parse_callback_url(url).is_err(), "url: {url}");
+    }
+    assert!(is_public_address("8.8.8.8".parse().unwrap()));
+    assert!(is_public_address("2001:4860:4860::8888".parse().unwrap()));
+    assert!(!is_public_address("100.64.0.1".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn webhooks_are_not_sent_to_private_addresses() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let webhooks = Webhooks::new(&WebhookSettings {
+        secret: Secret::new(SECRET.to_owned()),
+        timeout: 5,
+        max_attempts: 3,
+        retry_delay: 10,
+        allow_private_addresses: false,
+    }).unwrap();
+
+    // Names are resolved before sending, so they can't point at private addresses either.
+    let by_name = server.uri().replace("127.0.0.1", "localhost");
+    for uri in [server.uri(), by_name] {
+        let delivery = Delivery::new(format!("{uri}/hook"), Uuid::new_v4(), WebhookEvent::Ready, None);
+        let attempt = webhooks.send(&delivery).await;
+        assert!(!attempt.delivered, "uri: {uri}");
+        assert_eq!(None, attempt.status);
+        assert!(attempt.error.unwrap().contains("non-public address"));
+    }
+}